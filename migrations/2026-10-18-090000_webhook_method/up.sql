@@ -0,0 +1,3 @@
+ALTER TABLE webhooks
+ADD
+  COLUMN method VARCHAR(16) NOT NULL DEFAULT 'POST';
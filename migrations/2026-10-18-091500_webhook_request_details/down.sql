@@ -0,0 +1,4 @@
+ALTER TABLE webhooks
+DROP COLUMN IF EXISTS query_string,
+DROP COLUMN IF EXISTS path_tail,
+DROP COLUMN IF EXISTS remote_addr;
@@ -0,0 +1,7 @@
+ALTER TABLE webhooks
+ADD
+  COLUMN query_string TEXT,
+ADD
+  COLUMN path_tail TEXT NOT NULL DEFAULT '',
+ADD
+  COLUMN remote_addr VARCHAR(64);
@@ -0,0 +1,2 @@
+ALTER TABLE webhooks
+ALTER COLUMN headers TYPE TEXT USING headers :: TEXT;
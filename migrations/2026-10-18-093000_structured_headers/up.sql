@@ -0,0 +1,38 @@
+-- Headers used to be stored as the Debug output of a HeaderMap, which looks like
+-- {"host": "localhost:3030", "accept": "*/*"}
+-- Pull the name/value pairs back out of that so existing rows keep their headers
+CREATE FUNCTION legacy_headers_to_jsonb(raw TEXT) RETURNS JSONB AS $$
+DECLARE
+  parsed JSONB;
+BEGIN
+  SELECT
+    jsonb_agg(
+      jsonb_build_object(
+        'name',
+        found.m [1],
+        'value',
+        regexp_replace(COALESCE(found.m [2], found.m [3]), '\\(["\\])', '\1', 'g')
+      )
+      ORDER BY
+        found.ord
+    ) INTO parsed
+  FROM
+    regexp_matches(
+      raw,
+      '"([^"]*)": (?:"((?:[^"\\]|\\.)*)"|(Sensitive))',
+      'g'
+    ) WITH ORDINALITY AS found(m, ord);
+  IF parsed IS NULL AND btrim(raw) <> '{}' THEN
+    -- Couldn't make sense of it, keep the original text rather than lose it
+    parsed := jsonb_build_array(
+      jsonb_build_object('name', 'x-legacy-headers', 'value', raw)
+    );
+  END IF;
+  RETURN COALESCE(parsed, '[]' :: JSONB);
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE webhooks
+ALTER COLUMN headers TYPE JSONB USING legacy_headers_to_jsonb(headers);
+
+DROP FUNCTION legacy_headers_to_jsonb(TEXT);
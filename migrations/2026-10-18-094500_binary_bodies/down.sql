@@ -0,0 +1,9 @@
+-- Bodies which aren't valid UTF-8 can't go back into a TEXT column, keep them as escaped bytea
+ALTER TABLE webhooks
+ALTER COLUMN body TYPE TEXT USING CASE
+    WHEN body_encoding = 'utf-8' THEN convert_from(body, 'UTF8')
+    ELSE encode(body, 'escape')
+  END;
+
+ALTER TABLE webhooks
+DROP COLUMN IF EXISTS body_encoding;
@@ -0,0 +1,6 @@
+ALTER TABLE webhooks
+ALTER COLUMN body TYPE BYTEA USING convert_to(body, 'UTF8');
+
+ALTER TABLE webhooks
+ADD
+  COLUMN body_encoding VARCHAR(16) NOT NULL DEFAULT 'utf-8';
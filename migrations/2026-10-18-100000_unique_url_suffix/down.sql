@@ -0,0 +1,4 @@
+ALTER TABLE tags
+DROP CONSTRAINT IF EXISTS unique_url_suffix;
+
+CREATE INDEX url_match ON tags (url_suffix);
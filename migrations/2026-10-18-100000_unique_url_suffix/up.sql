@@ -0,0 +1,51 @@
+-- Fold duplicate tags into the oldest tag with the same suffix, webhooks included
+UPDATE
+  webhooks
+SET
+  tag_id = keepers.keep_id
+FROM
+  tags,
+  (
+    SELECT
+      url_suffix,
+      min(tag_id) AS keep_id
+    FROM
+      tags
+    GROUP BY
+      url_suffix
+    HAVING
+      count(*) > 1
+  ) AS keepers
+WHERE
+  webhooks.tag_id = tags.tag_id
+  AND tags.url_suffix = keepers.url_suffix
+  AND tags.tag_id <> keepers.keep_id;
+
+UPDATE
+  tags
+SET
+  active = true
+WHERE
+  tag_id IN (
+    SELECT
+      min(tag_id)
+    FROM
+      tags
+    GROUP BY
+      url_suffix
+    HAVING
+      count(*) > 1
+      AND bool_or(active)
+  );
+
+DELETE FROM
+  tags AS dupe USING tags AS keeper
+WHERE
+  dupe.url_suffix = keeper.url_suffix
+  AND dupe.tag_id > keeper.tag_id;
+
+DROP INDEX IF EXISTS url_match;
+
+ALTER TABLE tags
+ADD
+  CONSTRAINT unique_url_suffix UNIQUE (url_suffix);
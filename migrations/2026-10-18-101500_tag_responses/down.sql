@@ -0,0 +1,4 @@
+ALTER TABLE tags
+DROP COLUMN response_status,
+DROP COLUMN response_headers,
+DROP COLUMN response_body;
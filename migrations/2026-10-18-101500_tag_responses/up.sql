@@ -0,0 +1,5 @@
+-- Optional canned response per tag, a NULL status means record_webhook answers with its usual 200
+ALTER TABLE tags
+ADD COLUMN response_status INTEGER,
+ADD COLUMN response_headers JSONB NOT NULL DEFAULT '[]',
+ADD COLUMN response_body TEXT;
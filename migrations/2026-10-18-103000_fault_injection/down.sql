@@ -0,0 +1,13 @@
+ALTER TABLE webhooks
+DROP COLUMN response_status,
+DROP COLUMN response_headers,
+DROP COLUMN response_body,
+DROP COLUMN response_delay_ms,
+DROP COLUMN response_fault;
+
+ALTER TABLE tags
+DROP COLUMN delay_min_ms,
+DROP COLUMN delay_max_ms,
+DROP COLUMN failure_rate,
+DROP COLUMN failure_status,
+DROP COLUMN fail_next;
@@ -0,0 +1,15 @@
+-- Per-tag misbehaviour for exercising senders' retry logic
+ALTER TABLE tags
+ADD COLUMN delay_min_ms INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN delay_max_ms INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN failure_rate DOUBLE PRECISION NOT NULL DEFAULT 0,
+ADD COLUMN failure_status INTEGER NOT NULL DEFAULT 503,
+ADD COLUMN fail_next INTEGER NOT NULL DEFAULT 0;
+
+-- What the sender actually got back, NULL for webhooks recorded before this existed
+ALTER TABLE webhooks
+ADD COLUMN response_status INTEGER,
+ADD COLUMN response_headers JSONB,
+ADD COLUMN response_body BYTEA,
+ADD COLUMN response_delay_ms INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN response_fault VARCHAR(16);
@@ -0,0 +1,9 @@
+ALTER TABLE webhooks
+DROP COLUMN signature_verdict;
+
+ALTER TABLE tags
+DROP COLUMN signature_scheme,
+DROP COLUMN signature_secret,
+DROP COLUMN signature_header,
+DROP COLUMN signature_tolerance_secs,
+DROP COLUMN reject_unsigned;
@@ -0,0 +1,11 @@
+-- Shared secret and scheme for checking who really sent a tag's webhooks
+ALTER TABLE tags
+ADD COLUMN signature_scheme VARCHAR(16) NOT NULL DEFAULT 'none',
+ADD COLUMN signature_secret TEXT NOT NULL DEFAULT '',
+ADD COLUMN signature_header VARCHAR(64) NOT NULL DEFAULT 'X-Signature',
+ADD COLUMN signature_tolerance_secs INTEGER NOT NULL DEFAULT 300,
+ADD COLUMN reject_unsigned BOOLEAN NOT NULL DEFAULT false;
+
+-- NULL when the tag wasn't checking signatures at the time
+ALTER TABLE webhooks
+ADD COLUMN signature_verdict VARCHAR(16);
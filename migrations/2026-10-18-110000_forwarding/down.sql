@@ -0,0 +1,10 @@
+ALTER TABLE webhooks
+DROP COLUMN forward_url,
+DROP COLUMN forward_status,
+DROP COLUMN forward_headers,
+DROP COLUMN forward_body,
+DROP COLUMN forward_latency_ms,
+DROP COLUMN forward_error;
+
+ALTER TABLE tags
+DROP COLUMN forward_url;
@@ -0,0 +1,12 @@
+-- Where a tag's webhooks are relayed once recorded, empty for nowhere
+ALTER TABLE tags
+ADD COLUMN forward_url TEXT NOT NULL DEFAULT '';
+
+-- What the forward target made of it, all NULL for webhooks that weren't forwarded
+ALTER TABLE webhooks
+ADD COLUMN forward_url TEXT,
+ADD COLUMN forward_status INTEGER,
+ADD COLUMN forward_headers JSONB,
+ADD COLUMN forward_body BYTEA,
+ADD COLUMN forward_latency_ms INTEGER,
+ADD COLUMN forward_error TEXT;
@@ -0,0 +1,16 @@
+-- Every time a recorded webhook was sent again by hand, and what came back
+CREATE TABLE replays (
+    id SERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    target_url TEXT NOT NULL,
+    strip_hop_by_hop BOOLEAN NOT NULL,
+    host_override TEXT,
+    sent_at TIMESTAMP NOT NULL DEFAULT now(),
+    status INTEGER,
+    headers JSONB,
+    body BYTEA,
+    latency_ms INTEGER,
+    error TEXT
+);
+
+CREATE INDEX by_webhook ON replays (webhook_id, id DESC);
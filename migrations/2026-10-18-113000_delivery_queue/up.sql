@@ -0,0 +1,17 @@
+-- Forwards waiting to go out, or that went out, so an upstream outage only delays them
+CREATE TABLE deliveries (
+    id SERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    target_url TEXT NOT NULL,
+    -- pending, delivered or dead
+    state VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP NOT NULL DEFAULT now(),
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX due_deliveries ON deliveries (next_attempt) WHERE state = 'pending';
+CREATE INDEX deliveries_by_state ON deliveries (state, updated_at DESC);
+CREATE INDEX deliveries_by_webhook ON deliveries (webhook_id, id DESC);
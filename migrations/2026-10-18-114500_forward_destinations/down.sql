@@ -0,0 +1,45 @@
+ALTER TABLE tags
+ADD COLUMN forward_url TEXT NOT NULL DEFAULT '';
+
+-- Only one destination per tag fits back in, the oldest enabled one wins
+UPDATE tags
+SET forward_url = first_destination.url
+FROM (
+    SELECT DISTINCT ON (tag_id) tag_id, url
+    FROM forward_destinations
+    WHERE enabled
+    ORDER BY tag_id, id
+) AS first_destination
+WHERE first_destination.tag_id = tags.tag_id;
+
+ALTER TABLE webhooks
+ADD COLUMN forward_url TEXT,
+ADD COLUMN forward_status INTEGER,
+ADD COLUMN forward_headers JSONB,
+ADD COLUMN forward_body BYTEA,
+ADD COLUMN forward_latency_ms INTEGER,
+ADD COLUMN forward_error TEXT;
+
+UPDATE webhooks
+SET forward_url = latest.target_url,
+    forward_status = latest.response_status,
+    forward_headers = latest.response_headers,
+    forward_body = latest.response_body,
+    forward_latency_ms = latest.latency_ms,
+    forward_error = latest.last_error
+FROM (
+    SELECT DISTINCT ON (webhook_id) *
+    FROM deliveries
+    WHERE attempts > 0
+    ORDER BY webhook_id, id DESC
+) AS latest
+WHERE latest.webhook_id = webhooks.id;
+
+ALTER TABLE deliveries
+DROP COLUMN destination_id,
+DROP COLUMN response_status,
+DROP COLUMN response_headers,
+DROP COLUMN response_body,
+DROP COLUMN latency_ms;
+
+DROP TABLE forward_destinations;
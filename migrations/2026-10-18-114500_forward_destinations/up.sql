@@ -0,0 +1,68 @@
+-- Where a tag's webhooks are relayed, only matching ones when a condition is set
+CREATE TABLE forward_destinations (
+    id SERIAL PRIMARY KEY,
+    tag_id INTEGER NOT NULL REFERENCES tags (tag_id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    url TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    -- A header that has to be present, with this value when one's given
+    match_header VARCHAR(256),
+    match_header_value TEXT,
+    -- A path into a json body that has to exist, equal to this when it's given
+    match_json_path TEXT,
+    match_json_value JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (tag_id, name)
+);
+
+INSERT INTO forward_destinations (tag_id, name, url)
+SELECT tag_id, 'default', forward_url FROM tags WHERE forward_url <> '';
+
+-- Each delivery is to one destination and keeps what its latest attempt got back
+ALTER TABLE deliveries
+ADD COLUMN destination_id INTEGER REFERENCES forward_destinations (id) ON DELETE SET NULL,
+ADD COLUMN response_status INTEGER,
+ADD COLUMN response_headers JSONB,
+ADD COLUMN response_body BYTEA,
+ADD COLUMN latency_ms INTEGER;
+
+UPDATE deliveries
+SET destination_id = forward_destinations.id
+FROM webhooks, forward_destinations
+WHERE webhooks.id = deliveries.webhook_id
+AND forward_destinations.tag_id = webhooks.tag_id;
+
+UPDATE deliveries
+SET response_status = webhooks.forward_status,
+    response_headers = webhooks.forward_headers,
+    response_body = webhooks.forward_body,
+    latency_ms = webhooks.forward_latency_ms
+FROM webhooks
+WHERE webhooks.id = deliveries.webhook_id
+AND webhooks.forward_url IS NOT NULL;
+
+-- Forwarded before there was a queue, those that failed were never retried
+INSERT INTO deliveries (
+    webhook_id, target_url, state, attempts, last_error, created_at, updated_at,
+    destination_id, response_status, response_headers, response_body, latency_ms
+)
+SELECT webhooks.id, webhooks.forward_url,
+    CASE WHEN webhooks.forward_status BETWEEN 200 AND 299 THEN 'delivered' ELSE 'dead' END,
+    1, webhooks.forward_error, webhooks.upload_time, webhooks.upload_time,
+    forward_destinations.id, webhooks.forward_status, webhooks.forward_headers,
+    webhooks.forward_body, webhooks.forward_latency_ms
+FROM webhooks
+LEFT JOIN forward_destinations ON forward_destinations.tag_id = webhooks.tag_id
+WHERE webhooks.forward_url IS NOT NULL
+AND NOT EXISTS (SELECT 1 FROM deliveries WHERE deliveries.webhook_id = webhooks.id);
+
+ALTER TABLE webhooks
+DROP COLUMN forward_url,
+DROP COLUMN forward_status,
+DROP COLUMN forward_headers,
+DROP COLUMN forward_body,
+DROP COLUMN forward_latency_ms,
+DROP COLUMN forward_error;
+
+ALTER TABLE tags
+DROP COLUMN forward_url;
@@ -0,0 +1,3 @@
+ALTER TABLE tags
+DROP COLUMN retention_max_age_secs,
+DROP COLUMN retention_max_count;
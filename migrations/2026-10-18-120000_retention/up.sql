@@ -0,0 +1,4 @@
+-- Per-tag overrides of the global retention policy, NULL follows the global setting and 0 keeps forever
+ALTER TABLE tags
+ADD COLUMN retention_max_age_secs INTEGER CHECK (retention_max_age_secs >= 0),
+ADD COLUMN retention_max_count INTEGER CHECK (retention_max_count >= 0);
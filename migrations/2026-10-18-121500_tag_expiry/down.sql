@@ -0,0 +1,2 @@
+DROP INDEX expiring;
+ALTER TABLE tags DROP COLUMN expires_at;
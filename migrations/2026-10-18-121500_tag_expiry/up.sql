@@ -0,0 +1,3 @@
+-- Throwaway tags stop recording once this passes, NULL never expires
+ALTER TABLE tags ADD COLUMN expires_at TIMESTAMP;
+CREATE INDEX expiring ON tags (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,7 @@
+DROP INDEX by_upload_time;
+DROP INDEX header_search;
+DROP INDEX body_json_search;
+DROP INDEX body_search;
+DROP FUNCTION webhook_body_json(BYTEA, VARCHAR);
+DROP FUNCTION webhook_body_tsvector(BYTEA, VARCHAR);
+DROP FUNCTION webhook_body_text(BYTEA, VARCHAR);
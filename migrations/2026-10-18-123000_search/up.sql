@@ -0,0 +1,35 @@
+-- Bodies are stored as bytes, search only looks at the ones that are text
+CREATE FUNCTION webhook_body_text(body BYTEA, encoding VARCHAR) RETURNS TEXT AS $$
+BEGIN
+  IF encoding <> 'utf-8' THEN
+    RETURN NULL;
+  END IF;
+  RETURN convert_from(body, 'UTF8');
+EXCEPTION WHEN OTHERS THEN
+  -- NUL is valid UTF-8 but postgres text can't hold it
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+-- tsvectors top out at 1MB, so huge bodies are indexed by their start rather than failing the insert
+CREATE FUNCTION webhook_body_tsvector(body BYTEA, encoding VARCHAR) RETURNS TSVECTOR AS $$
+BEGIN
+  RETURN to_tsvector('simple', left(webhook_body_text(body, encoding), 262144));
+EXCEPTION WHEN OTHERS THEN
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+-- NULL for anything that doesn't parse as JSON
+CREATE FUNCTION webhook_body_json(body BYTEA, encoding VARCHAR) RETURNS JSONB AS $$
+BEGIN
+  RETURN webhook_body_text(body, encoding) :: JSONB;
+EXCEPTION WHEN OTHERS THEN
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+CREATE INDEX body_search ON webhooks USING GIN (webhook_body_tsvector(body, body_encoding));
+CREATE INDEX body_json_search ON webhooks USING GIN (webhook_body_json(body, body_encoding) jsonb_path_ops);
+CREATE INDEX header_search ON webhooks USING GIN (headers jsonb_path_ops);
+CREATE INDEX by_upload_time ON webhooks (upload_time);
@@ -0,0 +1,2 @@
+ALTER TABLE webhooks
+ALTER COLUMN method TYPE VARCHAR(16) USING left(method, 16);
@@ -0,0 +1,3 @@
+-- Extension methods can be any length, varchar to text is binary compatible so nothing is rewritten
+ALTER TABLE webhooks
+ALTER COLUMN method TYPE TEXT;
@@ -0,0 +1,802 @@
+extern crate diesel;
+use super::config::AppConfig;
+use super::db;
+use super::delivery::{self, DeliveryQuery, DeliveryQueue, DeliveryView};
+use super::destinations;
+use super::display::{self, WebhookQuery, WebhookSummary};
+use super::faults;
+use super::forward::Forwarder;
+use super::har::{self, Har, HarQuery};
+use super::model::{
+    Delivery, Destination, DestinationSpec, Replay, ResponseSpec, Tag, TagChanges, Webhook,
+};
+use super::replay::{self, ReplayError, ReplayRequest};
+use super::retention;
+use super::schema::tags;
+use super::schema::webhooks;
+use super::search::{self, SearchHit, SearchQuery};
+use super::signatures;
+use super::tagmgr::{self, TagListQuery};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager, PooledConnection};
+use diesel::result::DatabaseErrorKind;
+use log::{debug, warn};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::reply::{Json, WithStatus};
+
+type ApiReply = WithStatus<Json>;
+
+// Every non-2xx response from the api has this body
+#[derive(Serialize, Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub error: String,
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewTagRequest {
+    // Left out to have one generated
+    pub url_suffix: Option<String>,
+    // Like "24h" or "7d", left out the tag never expires
+    pub expires_in: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CreatedTag {
+    #[serde(flatten)]
+    tag: Tag,
+    record_url: String,
+}
+
+#[derive(Serialize)]
+struct TagList {
+    tag_count: usize,
+    tags: Vec<Tag>,
+}
+
+#[derive(Serialize)]
+struct WebhookList {
+    webhooks: Vec<WebhookSummary>,
+    next_before: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct SearchResults {
+    webhooks: Vec<SearchHit>,
+    next_before: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct ReplayList {
+    replays: Vec<Replay>,
+}
+
+#[derive(Serialize)]
+struct DestinationList {
+    destinations: Vec<Destination>,
+}
+
+#[derive(Serialize)]
+struct WebhookDelivery {
+    #[serde(flatten)]
+    delivery: Delivery,
+    destination: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WebhookDeliveryList {
+    deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Serialize)]
+struct DeliveryList {
+    deliveries: Vec<DeliveryView>,
+}
+
+#[derive(Serialize)]
+struct Imported {
+    imported: usize,
+    ids: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct Redriven {
+    redriven: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiWebhook<'a> {
+    #[serde(flatten)]
+    webhook: &'a Webhook,
+    body_text: Option<&'a str>,
+}
+
+impl<'a> ApiWebhook<'a> {
+    pub(crate) fn new(webhook: &'a Webhook) -> ApiWebhook<'a> {
+        ApiWebhook {
+            body_text: webhook.body_text(),
+            webhook,
+        }
+    }
+}
+
+fn ok_reply<T: Serialize>(status: StatusCode, val: &T) -> ApiReply {
+    warp::reply::with_status(warp::reply::json(val), status)
+}
+
+pub(crate) fn error_reply(status: StatusCode, message: &str) -> ApiReply {
+    counter!("api.errors", 1);
+    let body = ApiError {
+        status: status.as_u16(),
+        error: status.canonical_reason().unwrap_or("Error").to_string(),
+        message: message.to_string(),
+    };
+    warp::reply::with_status(warp::reply::json(&body), status)
+}
+
+fn db_error_reply(err: diesel::result::Error) -> ApiReply {
+    match err {
+        diesel::result::Error::NotFound => error_reply(StatusCode::NOT_FOUND, "Not found"),
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+            if info.constraint_name() == Some(tagmgr::SUFFIX_CONSTRAINT) =>
+        {
+            error_reply(
+                StatusCode::CONFLICT,
+                "A tag with that url_suffix already exists",
+            )
+        }
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+            error_reply(StatusCode::CONFLICT, info.message())
+        }
+        diesel::result::Error::QueryBuilderError(msg) => {
+            error_reply(StatusCode::BAD_REQUEST, &msg.to_string())
+        }
+        err => {
+            warn!("Database error serving api request: {}", err);
+            error_reply(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string())
+        }
+    }
+}
+
+// No connection is an api error like any other, just one that's worth retrying
+fn connect(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> Result<PooledConnection<ConnectionManager<PgConnection>>, ApiReply> {
+    db::connect(pool).map_err(|_| error_reply(StatusCode::SERVICE_UNAVAILABLE, db::UNAVAILABLE))
+}
+
+fn find_tag(conn: &PgConnection, suffix: &str) -> Result<Tag, diesel::result::Error> {
+    tags::table
+        .filter(tags::url_suffix.eq(suffix))
+        .first::<Tag>(conn)
+}
+
+// GET /api/v1/tags
+pub async fn list_tags(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    query: TagListQuery,
+) -> Result<ApiReply, Infallible> {
+    let mut listing = tags::table.order_by(tags::created_at).into_boxed();
+    if !query.include_inactive.unwrap_or(false) {
+        listing = listing
+            .filter(tags::active.eq(true))
+            .filter(tagmgr::unexpired());
+    }
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found = listing.load::<Tag>(&conn);
+    Ok(match found {
+        Ok(all_tags) => ok_reply(
+            StatusCode::OK,
+            &TagList {
+                tag_count: all_tags.len(),
+                tags: all_tags,
+            },
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/tags
+pub async fn create_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: AppConfig,
+    host: Option<String>,
+    body: NewTagRequest,
+) -> Result<ApiReply, Infallible> {
+    if let Some(Err(message)) = body.url_suffix.as_deref().map(tagmgr::validate_suffix) {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, &message));
+    }
+    let expiry = match tagmgr::parse_expiry(body.expires_in.as_deref()) {
+        Ok(expiry) => expiry,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let created = tagmgr::create_tag(&conn, body.url_suffix, config.tag_suffix_length, expiry);
+    Ok(match created {
+        Ok(tag) => ok_reply(
+            StatusCode::CREATED,
+            &CreatedTag {
+                record_url: tagmgr::record_url(&config, host.as_deref(), &tag.url_suffix),
+                tag,
+            },
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// GET /api/v1/tags/:suffix
+pub async fn get_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match find_tag(&conn, &suffix) {
+        Ok(tag) => ok_reply(StatusCode::OK, &tag),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// PATCH /api/v1/tags/:suffix
+pub async fn update_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    changes: TagChanges,
+) -> Result<ApiReply, Infallible> {
+    let validated = changes
+        .url_suffix
+        .as_deref()
+        .map_or(Ok(()), tagmgr::validate_suffix)
+        .and_then(|_| faults::validate_fault_changes(&changes))
+        .and_then(|_| signatures::validate_signature_changes(&changes))
+        .and_then(|_| retention::validate_retention_changes(&changes));
+    if let Err(message) = validated {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, &message));
+    }
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let tag = match find_tag(&conn, &suffix) {
+        Ok(tag) => tag,
+        Err(err) => return Ok(db_error_reply(err)),
+    };
+    if let Err(message) = signatures::validate_secret_for(&tag, &changes) {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, &message));
+    }
+    let updated = diesel::update(tags::table.find(tag.tag_id))
+        .set(&changes)
+        .get_result::<Tag>(&conn);
+    Ok(match updated {
+        Ok(tag) => ok_reply(StatusCode::OK, &tag),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// DELETE /api/v1/tags/:suffix
+pub async fn delete_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match tagmgr::delete_tag(&conn, &suffix) {
+        Ok(deleted) => ok_reply(StatusCode::OK, &deleted),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/tags/:suffix/activate and /api/v1/tags/:suffix/deactivate
+pub async fn set_tag_active(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    active: bool,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match tagmgr::set_tag_active(&conn, &suffix, active) {
+        Ok(tag) => ok_reply(StatusCode::OK, &tag),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// PUT /api/v1/tags/:suffix/response, DELETE clears it by sending the empty spec
+pub async fn set_tag_response(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    spec: ResponseSpec,
+) -> Result<ApiReply, Infallible> {
+    if let Err(message) = tagmgr::validate_response_spec(&spec) {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, &message));
+    }
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match tagmgr::set_response_spec(&conn, &suffix, &spec) {
+        Ok(tag) => ok_reply(StatusCode::OK, &tag),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// Names are unique within a tag, that's the only conflict a destination can run into
+fn destination_error_reply(err: diesel::result::Error) -> ApiReply {
+    match err {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+            if info.constraint_name() == Some(destinations::NAME_CONSTRAINT) =>
+        {
+            error_reply(StatusCode::CONFLICT, destinations::DUPLICATE_NAME)
+        }
+        err => db_error_reply(err),
+    }
+}
+
+// GET /api/v1/tags/:suffix/destinations
+pub async fn list_destinations(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found =
+        find_tag(&conn, &suffix).and_then(|tag| destinations::load_destinations(&conn, tag.tag_id));
+    Ok(match found {
+        Ok(found) => ok_reply(
+            StatusCode::OK,
+            &DestinationList {
+                destinations: found,
+            },
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/tags/:suffix/destinations
+pub async fn create_destination(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    spec: DestinationSpec,
+) -> Result<ApiReply, Infallible> {
+    let spec = match destinations::clean_spec(spec) {
+        Ok(spec) => spec,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let created = find_tag(&conn, &suffix)
+        .and_then(|tag| destinations::create_destination(&conn, tag.tag_id, &spec));
+    Ok(match created {
+        Ok(destination) => ok_reply(StatusCode::CREATED, &destination),
+        Err(err) => destination_error_reply(err),
+    })
+}
+
+// PUT /api/v1/tags/:suffix/destinations/:id, anything left out is cleared
+pub async fn replace_destination(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    destination_id: i32,
+    spec: DestinationSpec,
+) -> Result<ApiReply, Infallible> {
+    let spec = match destinations::clean_spec(spec) {
+        Ok(spec) => spec,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let replaced = find_tag(&conn, &suffix).and_then(|tag| {
+        destinations::replace_destination(&conn, tag.tag_id, destination_id, &spec)
+    });
+    Ok(match replaced {
+        Ok(destination) => ok_reply(StatusCode::OK, &destination),
+        Err(err) => destination_error_reply(err),
+    })
+}
+
+// DELETE /api/v1/tags/:suffix/destinations/:id
+pub async fn delete_destination(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    destination_id: i32,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let deleted = find_tag(&conn, &suffix)
+        .and_then(|tag| destinations::delete_destination(&conn, tag.tag_id, destination_id));
+    Ok(match deleted {
+        Ok(destination) => ok_reply(StatusCode::OK, &destination),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// GET /api/v1/tags/:suffix/har
+pub async fn export_har(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: AppConfig,
+    host: Option<String>,
+    suffix: String,
+    query: HarQuery,
+) -> Result<ApiReply, Infallible> {
+    let (since, until) = match query.bounds() {
+        Ok(bounds) => bounds,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found =
+        find_tag(&conn, &suffix).and_then(|tag| har::load_entries(&conn, tag.tag_id, since, until));
+    Ok(match found {
+        Ok(found) => ok_reply(
+            StatusCode::OK,
+            &har::build_har(
+                &found,
+                &tagmgr::record_url(&config, host.as_deref(), &suffix),
+            ),
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/tags/:suffix/har, all or nothing
+pub async fn import_har(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    body: Har,
+) -> Result<ApiReply, Infallible> {
+    let imported = match har::read_entries(&body) {
+        Ok(imported) => imported,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let saved =
+        find_tag(&conn, &suffix).and_then(|tag| har::save_imported(&conn, tag.tag_id, &imported));
+    Ok(match saved {
+        Ok(ids) => ok_reply(
+            StatusCode::CREATED,
+            &Imported {
+                imported: ids.len(),
+                ids,
+            },
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// GET /api/v1/tags/:suffix/webhooks
+pub async fn list_webhooks(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    query: WebhookQuery,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found = find_tag(&conn, &suffix)
+        .and_then(|tag| display::load_webhook_page(&conn, tag.tag_id, &query));
+    Ok(match found {
+        Ok((summaries, next_before)) => ok_reply(
+            StatusCode::OK,
+            &WebhookList {
+                webhooks: summaries,
+                next_before,
+            },
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// GET /api/v1/webhooks/:id
+pub async fn get_webhook(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    webhook_id: i32,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found = webhooks::table.find(webhook_id).first::<Webhook>(&conn);
+    Ok(match found {
+        Ok(webhook) => ok_reply(StatusCode::OK, &ApiWebhook::new(&webhook)),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// GET /api/v1/search, across every tag
+pub async fn search_webhooks(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    query: SearchQuery,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let criteria = match search::prepare(&conn, &query) {
+        Ok(criteria) => criteria,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    Ok(
+        match search::search(&conn, &criteria, query.before, query.limit) {
+            Ok((hits, next_before)) => ok_reply(
+                StatusCode::OK,
+                &SearchResults {
+                    webhooks: hits,
+                    next_before,
+                },
+            ),
+            Err(err) => db_error_reply(err),
+        },
+    )
+}
+
+// GET /api/v1/webhooks/:id/deliveries, one per destination the webhook was forwarded to
+pub async fn list_webhook_deliveries(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    webhook_id: i32,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found = webhooks::table
+        .find(webhook_id)
+        .select(webhooks::id)
+        .first::<i32>(&conn)
+        .and_then(|found_id| delivery::load_webhook_deliveries(&conn, found_id));
+    Ok(match found {
+        Ok(found) => ok_reply(
+            StatusCode::OK,
+            &WebhookDeliveryList {
+                deliveries: found
+                    .into_iter()
+                    .map(|(delivery, destination)| WebhookDelivery {
+                        delivery,
+                        destination,
+                    })
+                    .collect(),
+            },
+        ),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/webhooks/:id/replay, a 201 just means the attempt was recorded, check its status
+pub async fn replay_webhook(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    forwarder: Forwarder,
+    webhook_id: i32,
+    request: ReplayRequest,
+) -> Result<ApiReply, Infallible> {
+    if let Err(message) = request.validate() {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, &message));
+    }
+    // Only held for the lookup, nothing should sit on a connection while the replay is out
+    let found = match connect(&pool) {
+        Ok(conn) => webhooks::table.find(webhook_id).first::<Webhook>(&conn),
+        Err(reply) => return Ok(reply),
+    };
+    let webhook = match found {
+        Ok(webhook) => webhook,
+        Err(err) => return Ok(db_error_reply(err)),
+    };
+    Ok(
+        match replay::replay_webhook(&pool, &forwarder, &webhook, &request).await {
+            Ok(attempt) => ok_reply(StatusCode::CREATED, &attempt),
+            Err(ReplayError::Unrecorded) => {
+                error_reply(StatusCode::SERVICE_UNAVAILABLE, replay::UNRECORDED)
+            }
+            Err(ReplayError::Database(err)) => db_error_reply(err),
+        },
+    )
+}
+
+// GET /api/v1/webhooks/:id/replays
+pub async fn list_replays(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    webhook_id: i32,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    let found = webhooks::table
+        .find(webhook_id)
+        .select(webhooks::id)
+        .first::<i32>(&conn)
+        .and_then(|found_id| replay::load_replays(&conn, found_id));
+    Ok(match found {
+        Ok(replays) => ok_reply(StatusCode::OK, &ReplayList { replays }),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// GET /api/v1/deliveries, dead letters unless another state is asked for
+pub async fn list_deliveries(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    query: DeliveryQuery,
+) -> Result<ApiReply, Infallible> {
+    let state = match query.state() {
+        Ok(state) => state,
+        Err(message) => return Ok(error_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match delivery::load_deliveries(&conn, state) {
+        Ok(deliveries) => ok_reply(StatusCode::OK, &DeliveryList { deliveries }),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/deliveries/:id/redrive, only dead letters can be
+pub async fn redrive_delivery(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    queue: DeliveryQueue,
+    delivery_id: i32,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match queue.redrive(&conn, delivery_id) {
+        Ok(redriven) => ok_reply(StatusCode::OK, &redriven),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// POST /api/v1/deliveries/redrive, every dead letter at once
+pub async fn redrive_dead(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    queue: DeliveryQueue,
+) -> Result<ApiReply, Infallible> {
+    let conn = match connect(&pool) {
+        Ok(conn) => conn,
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match queue.redrive_dead(&conn) {
+        Ok(redriven) => ok_reply(StatusCode::OK, &Redriven { redriven }),
+        Err(err) => db_error_reply(err),
+    })
+}
+
+// Turns whatever warp rejected an api request with into a json error body
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<ApiReply, Infallible> {
+    debug!("Api request rejected: {:?}", rejection);
+    let reply = if rejection.is_not_found() {
+        error_reply(StatusCode::NOT_FOUND, "No such api endpoint")
+    } else if let Some(err) = rejection.find::<warp::body::BodyDeserializeError>() {
+        error_reply(StatusCode::BAD_REQUEST, &err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::InvalidQuery>() {
+        error_reply(StatusCode::BAD_REQUEST, &err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::PayloadTooLarge>() {
+        error_reply(StatusCode::PAYLOAD_TOO_LARGE, &err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::UnsupportedMediaType>() {
+        error_reply(StatusCode::UNSUPPORTED_MEDIA_TYPE, &err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::MethodNotAllowed>() {
+        // Checked last since every route with the same path but another method adds one of these
+        error_reply(StatusCode::METHOD_NOT_ALLOWED, &err.to_string())
+    } else {
+        error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unhandled rejection")
+    };
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{db_error_reply, delete_tag, destination_error_reply, get_tag, ApiReply};
+    use crate::db;
+    use crate::destinations;
+    use crate::tagmgr;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind, Error};
+    use std::time::Duration;
+    use warp::Reply;
+
+    // Just the parts of a postgres error the replies look at
+    struct Violation(&'static str);
+
+    impl DatabaseErrorInformation for Violation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+        fn details(&self) -> Option<&str> {
+            None
+        }
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+        fn table_name(&self) -> Option<&str> {
+            None
+        }
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+        fn constraint_name(&self) -> Option<&str> {
+            Some(self.0)
+        }
+    }
+
+    fn violation(constraint: &'static str) -> Error {
+        Error::DatabaseError(
+            DatabaseErrorKind::UniqueViolation,
+            Box::new(Violation(constraint)),
+        )
+    }
+
+    async fn read_reply(reply: ApiReply) -> (u16, String) {
+        let response = reply.into_response();
+        let status = response.status().as_u16();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (status, error["message"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_db_error_reply() {
+        assert_eq!(
+            read_reply(db_error_reply(Error::NotFound)).await,
+            (404, "Not found".to_string())
+        );
+        assert_eq!(
+            read_reply(db_error_reply(violation(tagmgr::SUFFIX_CONSTRAINT))).await,
+            (409, "A tag with that url_suffix already exists".to_string())
+        );
+        assert_eq!(
+            read_reply(db_error_reply(violation("replays_pkey"))).await,
+            (
+                409,
+                "duplicate key value violates unique constraint".to_string()
+            )
+        );
+        assert_eq!(
+            read_reply(destination_error_reply(violation(
+                destinations::NAME_CONSTRAINT
+            )))
+            .await,
+            (409, destinations::DUPLICATE_NAME.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_pool() {
+        // Never manages to connect, so every checkout times out
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::new("pgsql://test"));
+        let reply = get_tag(pool.clone(), "shop".to_string()).await.unwrap();
+        assert_eq!(read_reply(reply).await, (503, db::UNAVAILABLE.to_string()));
+        let reply = delete_tag(pool, "shop".to_string()).await.unwrap();
+        assert_eq!(read_reply(reply).await, (503, db::UNAVAILABLE.to_string()));
+    }
+}
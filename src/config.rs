@@ -13,6 +13,20 @@ pub struct AppConfig {
     pub stats_interval: Duration,
     pub enable_stats_logger: bool,
     pub http_stats_port: u16,
+    pub trust_forwarded_for: bool,
+    pub tag_suffix_length: usize,
+    pub public_url: Option<String>,
+    pub enable_pg_notify: bool,
+    pub forward_timeout: Duration,
+    pub delivery_max_attempts: i32,
+    pub delivery_backoff: Duration,
+    pub delivery_backoff_max: Duration,
+    pub retention_max_age: Option<Duration>,
+    pub retention_max_count: Option<i64>,
+    pub retention_interval: Duration,
+    pub retention_batch_size: i64,
+    pub delete_expired_tags: bool,
+    pub expired_tag_grace: Duration,
 }
 
 impl AppConfig {
@@ -24,7 +38,7 @@ impl AppConfig {
         for<'a> &'a mut T: IntoIterator<Item = (String, String)>,
     {
         let vars_map: HashMap<std::string::String, std::string::String> =
-            HashMap::from_iter(environ.into_iter());
+            HashMap::from_iter(environ);
         let listen_addr = vars_map
             .get("LISTEN_IP")
             .unwrap_or(&"127.0.0.1".to_string())
@@ -60,6 +74,104 @@ impl AppConfig {
             .unwrap_or(&"3031".to_string())
             .parse::<u16>()
             .unwrap();
+        let trust_forwarded_for = vars_map
+            .get("TRUST_FORWARDED_FOR")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()
+            .unwrap();
+        let tag_suffix_length = vars_map
+            .get("TAG_SUFFIX_LENGTH")
+            .unwrap_or(&"16".to_string())
+            .parse::<usize>()
+            .unwrap();
+        // Generated suffixes have to fit in the url_suffix VARCHAR(32) column
+        assert!(
+            (1..=32).contains(&tag_suffix_length),
+            "TAG_SUFFIX_LENGTH must be between 1 and 32"
+        );
+        let public_url = vars_map
+            .get("PUBLIC_URL")
+            .map(|val| val.trim_end_matches('/').to_string());
+        // Route live webhooks through postgres so every replica sees them
+        let enable_pg_notify = vars_map
+            .get("ENABLE_PG_NOTIFY")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()
+            .unwrap();
+        // How long a forward target gets to answer before it's recorded as an error
+        let forward_timeout_secs = vars_map
+            .get("FORWARD_TIMEOUT_SECS")
+            .unwrap_or(&"30".to_string())
+            .parse::<u64>()
+            .unwrap();
+        let forward_timeout = Duration::from_secs(forward_timeout_secs);
+        // Failed forwards are retried this many times in all before they're dead letters
+        let delivery_max_attempts = vars_map
+            .get("DELIVERY_MAX_ATTEMPTS")
+            .unwrap_or(&"8".to_string())
+            .parse::<i32>()
+            .unwrap();
+        assert!(
+            delivery_max_attempts >= 1,
+            "DELIVERY_MAX_ATTEMPTS must be at least 1"
+        );
+        // The wait after the first failure, doubling each time up to the max
+        let delivery_backoff_secs = vars_map
+            .get("DELIVERY_BACKOFF_SECS")
+            .unwrap_or(&"10".to_string())
+            .parse::<u64>()
+            .unwrap();
+        let delivery_backoff = Duration::from_secs(delivery_backoff_secs);
+        let delivery_backoff_max_secs = vars_map
+            .get("DELIVERY_BACKOFF_MAX_SECS")
+            .unwrap_or(&"3600".to_string())
+            .parse::<u64>()
+            .unwrap();
+        let delivery_backoff_max = Duration::from_secs(delivery_backoff_max_secs);
+        // Webhooks older than this are pruned unless their tag says otherwise, unset or 0 keeps them
+        let retention_max_age = vars_map
+            .get("RETENTION_MAX_AGE")
+            .map(|val| {
+                parse_duration(val).expect("RETENTION_MAX_AGE must look like 3600, 12h or 30d")
+            })
+            .filter(|age| *age > Duration::from_secs(0));
+        // Only the newest this many webhooks of each tag are kept, unset or 0 keeps them all
+        let retention_max_count = vars_map
+            .get("RETENTION_MAX_COUNT")
+            .map(|val| val.parse::<i64>().unwrap())
+            .filter(|count| *count > 0);
+        let retention_interval_secs = vars_map
+            .get("RETENTION_INTERVAL_SECS")
+            .unwrap_or(&"3600".to_string())
+            .parse::<u64>()
+            .unwrap();
+        assert!(
+            retention_interval_secs >= 1,
+            "RETENTION_INTERVAL_SECS must be at least 1"
+        );
+        let retention_interval = Duration::from_secs(retention_interval_secs);
+        // Rows deleted per statement, so pruning never holds locks on a big chunk of the table
+        let retention_batch_size = vars_map
+            .get("RETENTION_BATCH_SIZE")
+            .unwrap_or(&"1000".to_string())
+            .parse::<i64>()
+            .unwrap();
+        assert!(
+            retention_batch_size >= 1,
+            "RETENTION_BATCH_SIZE must be at least 1"
+        );
+        // Expired tags stop recording straight away, these decide if and when they're deleted too
+        let delete_expired_tags = vars_map
+            .get("DELETE_EXPIRED_TAGS")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()
+            .unwrap();
+        let expired_tag_grace = parse_duration(
+            vars_map
+                .get("EXPIRED_TAG_GRACE")
+                .unwrap_or(&"7d".to_string()),
+        )
+        .expect("EXPIRED_TAG_GRACE must look like 3600, 12h or 7d");
         AppConfig {
             db_url,
             max_conns,
@@ -68,10 +180,64 @@ impl AppConfig {
             stats_interval,
             enable_stats_logger,
             http_stats_port,
+            trust_forwarded_for,
+            tag_suffix_length,
+            public_url,
+            enable_pg_notify,
+            forward_timeout,
+            delivery_max_attempts,
+            delivery_backoff,
+            delivery_backoff_max,
+            retention_max_age,
+            retention_max_count,
+            retention_interval,
+            retention_batch_size,
+            delete_expired_tags,
+            expired_tag_grace,
         }
     }
 }
 
+// Seconds, optionally with an s, m, h, d or w suffix, as in "90", "15m" or "7d"
+pub fn parse_duration(val: &str) -> Result<Duration, String> {
+    let val = val.trim();
+    let (digits, unit) = match val.char_indices().last() {
+        Some((at, last)) if last.is_ascii_alphabetic() => (&val[..at], last),
+        _ => (val, 's'),
+    };
+    let multiplier = match unit.to_ascii_lowercase() {
+        's' => Some(1),
+        'm' => Some(60),
+        'h' => Some(60 * 60),
+        'd' => Some(60 * 60 * 24),
+        'w' => Some(60 * 60 * 24 * 7),
+        _ => None,
+    };
+    multiplier
+        .and_then(|multiplier| {
+            let amount = digits.trim().parse::<u64>().ok()?;
+            amount.checked_mul(multiplier)
+        })
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is not a duration like 3600, 12h or 30d", val))
+}
+
+// The inverse of parse_duration, in the largest unit that divides evenly
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        ('w', 60 * 60 * 24 * 7),
+        ('d', 60 * 60 * 24),
+        ('h', 60 * 60),
+        ('m', 60),
+    ];
+    units
+        .iter()
+        .find(|(_, size)| secs > 0 && secs % *size == 0)
+        .map(|(unit, size)| format!("{}{}", secs / size, unit))
+        .unwrap_or_else(|| format!("{}s", secs))
+}
+
 #[test]
 pub fn test_ipv4_get_config() {
     let expected = AppConfig {
@@ -82,6 +248,20 @@ pub fn test_ipv4_get_config() {
         stats_interval: Duration::from_secs(888),
         enable_stats_logger: false,
         http_stats_port: 4322,
+        trust_forwarded_for: true,
+        tag_suffix_length: 16,
+        public_url: None,
+        enable_pg_notify: false,
+        forward_timeout: Duration::from_secs(30),
+        delivery_max_attempts: 8,
+        delivery_backoff: Duration::from_secs(10),
+        delivery_backoff_max: Duration::from_secs(3600),
+        retention_max_age: None,
+        retention_max_count: None,
+        retention_interval: Duration::from_secs(3600),
+        retention_batch_size: 1000,
+        delete_expired_tags: false,
+        expired_tag_grace: Duration::from_secs(7 * 24 * 60 * 60),
     };
     let mut mock_env = HashMap::new();
     mock_env.insert(
@@ -94,6 +274,7 @@ pub fn test_ipv4_get_config() {
     mock_env.insert("LISTEN_IP".to_string(), "5.4.3.2".to_string());
     mock_env.insert("ENABLE_STATS_LOGGER".to_string(), "false".to_string());
     mock_env.insert("HTTP_STATS_PORT".to_string(), "4322".to_string());
+    mock_env.insert("TRUST_FORWARDED_FOR".to_string(), "true".to_string());
     let config = AppConfig::new(&mut mock_env.into_iter());
     assert_eq!(expected, config);
 }
@@ -111,6 +292,20 @@ pub fn test_ipv6_get_config() {
         stats_interval: Duration::from_secs(888),
         enable_stats_logger: false,
         http_stats_port: 4322,
+        trust_forwarded_for: true,
+        tag_suffix_length: 24,
+        public_url: Some("https://hooks.example.com".to_string()),
+        enable_pg_notify: true,
+        forward_timeout: Duration::from_secs(5),
+        delivery_max_attempts: 3,
+        delivery_backoff: Duration::from_secs(1),
+        delivery_backoff_max: Duration::from_secs(60),
+        retention_max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        retention_max_count: Some(500),
+        retention_interval: Duration::from_secs(60),
+        retention_batch_size: 50,
+        delete_expired_tags: true,
+        expired_tag_grace: Duration::from_secs(12 * 60 * 60),
     };
     let mut mock_env = HashMap::new();
     mock_env.insert(
@@ -126,8 +321,48 @@ pub fn test_ipv6_get_config() {
     );
     mock_env.insert("ENABLE_STATS_LOGGER".to_string(), "false".to_string());
     mock_env.insert("HTTP_STATS_PORT".to_string(), "4322".to_string());
+    mock_env.insert("TRUST_FORWARDED_FOR".to_string(), "true".to_string());
+    mock_env.insert("TAG_SUFFIX_LENGTH".to_string(), "24".to_string());
+    mock_env.insert(
+        "PUBLIC_URL".to_string(),
+        "https://hooks.example.com/".to_string(),
+    );
+    mock_env.insert("ENABLE_PG_NOTIFY".to_string(), "true".to_string());
+    mock_env.insert("FORWARD_TIMEOUT_SECS".to_string(), "5".to_string());
+    mock_env.insert("DELIVERY_MAX_ATTEMPTS".to_string(), "3".to_string());
+    mock_env.insert("DELIVERY_BACKOFF_SECS".to_string(), "1".to_string());
+    mock_env.insert("DELIVERY_BACKOFF_MAX_SECS".to_string(), "60".to_string());
+    mock_env.insert("RETENTION_MAX_AGE".to_string(), "30d".to_string());
+    mock_env.insert("RETENTION_MAX_COUNT".to_string(), "500".to_string());
+    mock_env.insert("RETENTION_INTERVAL_SECS".to_string(), "60".to_string());
+    mock_env.insert("RETENTION_BATCH_SIZE".to_string(), "50".to_string());
+    mock_env.insert("DELETE_EXPIRED_TAGS".to_string(), "true".to_string());
+    mock_env.insert("EXPIRED_TAG_GRACE".to_string(), "12h".to_string());
     let config = AppConfig::new(&mut mock_env.into_iter());
     assert_eq!(expected, config);
 }
 
+#[test]
+pub fn test_parse_duration() {
+    assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90"));
+    assert_eq!(Ok(Duration::from_secs(0)), parse_duration("0"));
+    assert_eq!(Ok(Duration::from_secs(15 * 60)), parse_duration("15m"));
+    assert_eq!(Ok(Duration::from_secs(24 * 60 * 60)), parse_duration("24h"));
+    assert_eq!(
+        Ok(Duration::from_secs(7 * 24 * 60 * 60)),
+        parse_duration(" 7D ")
+    );
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("7y").is_err());
+    assert!(parse_duration("-1d").is_err());
+    assert!(parse_duration("d").is_err());
+    assert_eq!("6d", format_duration(Duration::from_secs(6 * 24 * 60 * 60)));
+    assert_eq!(
+        "2w",
+        format_duration(Duration::from_secs(14 * 24 * 60 * 60))
+    );
+    assert_eq!("90s", format_duration(Duration::from_secs(90)));
+    assert_eq!("0s", format_duration(Duration::from_secs(0)));
+}
+
 //TODO Add tests which verify panics on missing config params
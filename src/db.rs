@@ -1,13 +1,33 @@
 use super::config::AppConfig;
-use diesel::{pg::PgConnection, r2d2::ConnectionManager};
+use diesel::{pg::PgConnection, r2d2::{ConnectionManager, PooledConnection}};
 use diesel_migrations::embed_migrations;
-use log::{debug, info};
+use log::{debug, info, warn};
 use metrics::{counter, timing};
 use quanta::Clock;
+use warp::http::StatusCode;
 
 
 embed_migrations!();
 
+// What a request gets when the pool can't hand out a connection in time
+pub const UNAVAILABLE: &str = "The database is unavailable, try again shortly";
+
+// Handlers check out connections through here rather than unwrapping, so a db that's down
+// or a pool that's run dry costs a 503 instead of the whole process
+pub fn connect(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> Result<PooledConnection<ConnectionManager<PgConnection>>, r2d2::Error> {
+    pool.get().map_err(|err| {
+        counter!("db.connect.errors", 1);
+        warn!("Unable to get a database connection: {}", err);
+        err
+    })
+}
+
+pub fn unavailable() -> warp::reply::WithStatus<&'static str> {
+    warp::reply::with_status(UNAVAILABLE, StatusCode::SERVICE_UNAVAILABLE)
+}
+
 
 pub struct DbFacade {
     pool: Option<r2d2::Pool<ConnectionManager<PgConnection>>>,
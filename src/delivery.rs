@@ -0,0 +1,618 @@
+use super::config::AppConfig;
+use super::db;
+use super::destinations;
+use super::faults::random_unit;
+use super::forward::{self, Forwarder};
+use super::model::{Delivery, DeliveryOutcome, NewDelivery, Webhook};
+use super::schema::{deliveries, forward_destinations, tags, webhooks};
+use super::templating::Templater;
+use diesel::dsl::{now, Eq};
+use diesel::pg::data_types::PgInterval;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::{BigInt, Integer};
+use futures::future;
+use log::{debug, info, warn};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use warp::http::header::LOCATION;
+use warp::http::{Response, StatusCode};
+
+// Due deliveries are claimed this many at a time and sent concurrently
+const CLAIM_BATCH: i64 = 16;
+// Retries come due without anyone waking the worker, this is how late they can be
+const POLL_SECS: u64 = 5;
+// A claimed delivery comes due again this long after its send would have timed out,
+// in case whoever claimed it went away mid-send
+const LEASE_MARGIN_SECS: u64 = 60;
+const LIST_LIMIT: i64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryState {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+impl DeliveryState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Delivered => "delivered",
+            DeliveryState::Dead => "dead",
+        }
+    }
+
+    pub fn parse(val: &str) -> Option<DeliveryState> {
+        match val {
+            "pending" => Some(DeliveryState::Pending),
+            "delivered" => Some(DeliveryState::Delivered),
+            "dead" => Some(DeliveryState::Dead),
+            _ => None,
+        }
+    }
+}
+
+// Handed to the record filter to queue forwards and to the worker that sends them
+#[derive(Clone)]
+pub struct DeliveryQueue {
+    wake: Arc<Notify>,
+    max_attempts: i32,
+    backoff: Duration,
+    backoff_max: Duration,
+    lease: Duration,
+}
+
+impl DeliveryQueue {
+    pub fn new(config: &AppConfig) -> DeliveryQueue {
+        DeliveryQueue {
+            wake: Arc::new(Notify::new()),
+            max_attempts: config.delivery_max_attempts,
+            backoff: config.delivery_backoff,
+            backoff_max: config.delivery_backoff_max,
+            lease: config.forward_timeout + Duration::from_secs(LEASE_MARGIN_SECS),
+        }
+    }
+
+    // One delivery per enabled destination the webhook matches, queued rather than sent
+    // straight away so a destination that's down only delays it
+    pub fn enqueue(
+        &self,
+        pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+        found_tag_id: i32,
+        webhook: &Webhook,
+    ) {
+        let conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(err) => {
+                counter!("delivery.enqueue.errors", 1);
+                warn!("Unable to queue webhook {}: {}", webhook.id, err);
+                return;
+            }
+        };
+        let matching: Vec<(i32, String)> =
+            match destinations::load_destinations(&conn, found_tag_id) {
+                Ok(found) => found
+                    .iter()
+                    .filter(|destination| {
+                        destination.enabled && destinations::matches(destination, webhook)
+                    })
+                    .map(|destination| {
+                        let target = forward::target_url(
+                            &destination.url,
+                            &webhook.path_tail,
+                            webhook.query_string.as_deref(),
+                        );
+                        (destination.id, target)
+                    })
+                    .collect(),
+                Err(err) => {
+                    counter!("delivery.enqueue.errors", 1);
+                    warn!(
+                        "Unable to load destinations for webhook {}: {}",
+                        webhook.id, err
+                    );
+                    return;
+                }
+            };
+        if matching.is_empty() {
+            return;
+        }
+        let new_deliveries: Vec<NewDelivery> = matching
+            .iter()
+            .map(|(destination_id, target)| NewDelivery {
+                webhook_id: webhook.id,
+                target_url: target,
+                destination_id: Some(*destination_id),
+            })
+            .collect();
+        let queued = diesel::insert_into(deliveries::table)
+            .values(&new_deliveries)
+            .execute(&conn);
+        match queued {
+            Ok(count) => {
+                counter!("delivery.enqueue.queued", count.try_into().unwrap());
+                self.wake.notify();
+            }
+            Err(err) => {
+                counter!("delivery.enqueue.errors", 1);
+                warn!("Unable to queue webhook {}: {}", webhook.id, err);
+            }
+        }
+    }
+
+    // A dead letter starts over with a full set of attempts
+    pub fn redrive(
+        &self,
+        conn: &PgConnection,
+        delivery_id: i32,
+    ) -> Result<Delivery, diesel::result::Error> {
+        let redriven = diesel::update(
+            deliveries::table
+                .find(delivery_id)
+                .filter(deliveries::state.eq(DeliveryState::Dead.as_str())),
+        )
+        .set(redrive_changes())
+        .get_result::<Delivery>(conn)?;
+        counter!("delivery.redrive.redriven", 1);
+        self.wake.notify();
+        Ok(redriven)
+    }
+
+    pub fn redrive_dead(&self, conn: &PgConnection) -> Result<usize, diesel::result::Error> {
+        let redriven = diesel::update(
+            deliveries::table.filter(deliveries::state.eq(DeliveryState::Dead.as_str())),
+        )
+        .set(redrive_changes())
+        .execute(conn)?;
+        counter!("delivery.redrive.redriven", redriven.try_into().unwrap());
+        self.wake.notify();
+        Ok(redriven)
+    }
+}
+
+type RedriveChanges = (
+    Eq<deliveries::state, &'static str>,
+    Eq<deliveries::attempts, i32>,
+    Eq<deliveries::next_attempt, now>,
+    Eq<deliveries::updated_at, now>,
+);
+
+fn redrive_changes() -> RedriveChanges {
+    (
+        deliveries::state.eq(DeliveryState::Pending.as_str()),
+        deliveries::attempts.eq(0),
+        deliveries::next_attempt.eq(now),
+        deliveries::updated_at.eq(now),
+    )
+}
+
+// Exponential from the base and capped, then somewhere in the upper half of that
+// so a burst of failures doesn't come back in lockstep
+pub(crate) fn backoff_delay(attempts: i32, base: Duration, cap: Duration, roll: f64) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 31) as u32;
+    let exponential = base.checked_mul(1 << doublings).unwrap_or(cap).min(cap);
+    exponential / 2 + exponential.mul_f64(roll) / 2
+}
+
+// Runs for the life of the process, replicas can each run one since claims skip locked rows
+pub fn spawn_worker(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    forwarder: Forwarder,
+    queue: &DeliveryQueue,
+) {
+    let queue = queue.clone();
+    tokio::spawn(async move {
+        info!(
+            "Delivery worker started, {} attempts per forward",
+            queue.max_attempts
+        );
+        loop {
+            let claimed = pool
+                .get()
+                .map_err(|err| err.to_string())
+                .and_then(|conn| claim_due(&conn, queue.lease).map_err(|err| err.to_string()));
+            match claimed {
+                Ok(due) => {
+                    let full = due.len() as i64 == CLAIM_BATCH;
+                    future::join_all(
+                        due.into_iter()
+                            .map(|delivery| attempt(&pool, &forwarder, &queue, delivery)),
+                    )
+                    .await;
+                    // More may be waiting behind a full batch
+                    if full {
+                        continue;
+                    }
+                }
+                Err(err) => {
+                    counter!("delivery.worker.errors", 1);
+                    warn!("Unable to claim due deliveries: {}", err);
+                }
+            }
+            let _ =
+                tokio::time::timeout(Duration::from_secs(POLL_SECS), queue.wake.notified()).await;
+        }
+    });
+}
+
+// Claiming counts the attempt and pushes the delivery out by the lease in one statement
+fn claim_due(conn: &PgConnection, lease: Duration) -> Result<Vec<Delivery>, diesel::result::Error> {
+    diesel::sql_query(
+        "UPDATE deliveries \
+         SET attempts = attempts + 1, updated_at = now(), \
+         next_attempt = now() + $2 * interval '1 second' \
+         WHERE id IN (SELECT id FROM deliveries \
+         WHERE state = 'pending' AND next_attempt <= now() \
+         ORDER BY next_attempt LIMIT $1 FOR UPDATE SKIP LOCKED) \
+         RETURNING *",
+    )
+    .bind::<BigInt, _>(CLAIM_BATCH)
+    .bind::<Integer, _>(lease.as_secs().try_into().unwrap_or(i32::MAX))
+    .load::<Delivery>(conn)
+}
+
+// Anything but a 2xx is worth trying again, the delivery keeps whatever its latest attempt got
+async fn attempt(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    forwarder: &Forwarder,
+    queue: &DeliveryQueue,
+    delivery: Delivery,
+) {
+    // Giving up on a pool error is fine, the lease runs out and the delivery is claimed again
+    let found = pool.get().map_err(|err| err.to_string()).and_then(|conn| {
+        webhooks::table
+            .find(delivery.webhook_id)
+            .first::<Webhook>(&conn)
+            .map_err(|err| err.to_string())
+    });
+    let webhook = match found {
+        Ok(webhook) => webhook,
+        Err(err) => {
+            counter!("delivery.worker.errors", 1);
+            warn!(
+                "Unable to load webhook {} for delivery {}: {}",
+                delivery.webhook_id, delivery.id, err
+            );
+            return;
+        }
+    };
+    let headers = forward::strip_hop_by_hop(&webhook.headers);
+    let result = forwarder
+        .send(
+            &webhook.method,
+            &delivery.target_url,
+            &headers,
+            &webhook.body,
+        )
+        .await;
+    let failure = match &result {
+        Ok(upstream) if (200..300).contains(&upstream.status) => None,
+        Ok(upstream) => Some(format!("Upstream answered {}", upstream.status)),
+        Err(err) => Some(err.clone()),
+    };
+    let outcome = match &result {
+        Ok(upstream) => DeliveryOutcome {
+            response_status: Some(upstream.status.into()),
+            response_headers: Some(&upstream.headers),
+            response_body: Some(&upstream.body),
+            latency_ms: Some(upstream.latency.as_millis().try_into().unwrap_or(i32::MAX)),
+            last_error: failure.as_deref(),
+        },
+        Err(_) => DeliveryOutcome {
+            last_error: failure.as_deref(),
+            ..Default::default()
+        },
+    };
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(err) => {
+            counter!("delivery.worker.errors", 1);
+            warn!(
+                "Unable to save attempt at delivery {} of webhook {}: {}",
+                delivery.id, webhook.id, err
+            );
+            return;
+        }
+    };
+    let this_delivery = deliveries::table.find(delivery.id);
+    let saved = match &failure {
+        None => {
+            counter!("delivery.attempt.delivered", 1);
+            debug!(
+                "Delivered webhook {} to {}",
+                webhook.id, delivery.target_url
+            );
+            diesel::update(this_delivery)
+                .set((
+                    &outcome,
+                    deliveries::state.eq(DeliveryState::Delivered.as_str()),
+                    deliveries::updated_at.eq(now),
+                ))
+                .execute(&conn)
+        }
+        Some(err) if delivery.attempts >= queue.max_attempts => {
+            counter!("delivery.attempt.dead", 1);
+            warn!(
+                "Giving up on delivery {} of webhook {} to {} after {} attempts: {}",
+                delivery.id, webhook.id, delivery.target_url, delivery.attempts, err
+            );
+            diesel::update(this_delivery)
+                .set((
+                    &outcome,
+                    deliveries::state.eq(DeliveryState::Dead.as_str()),
+                    deliveries::updated_at.eq(now),
+                ))
+                .execute(&conn)
+        }
+        Some(err) => {
+            counter!("delivery.attempt.retried", 1);
+            let wait = backoff_delay(
+                delivery.attempts,
+                queue.backoff,
+                queue.backoff_max,
+                random_unit(),
+            );
+            debug!(
+                "Delivery {} failed attempt {}, retrying in {}s: {}",
+                delivery.id,
+                delivery.attempts,
+                wait.as_secs(),
+                err
+            );
+            let wait_micros = wait.as_micros().try_into().unwrap_or(i64::MAX);
+            diesel::update(this_delivery)
+                .set((
+                    &outcome,
+                    deliveries::next_attempt.eq(now + PgInterval::from_microseconds(wait_micros)),
+                    deliveries::updated_at.eq(now),
+                ))
+                .execute(&conn)
+        }
+    };
+    if let Err(err) = saved {
+        warn!("Unable to save state of delivery {}: {}", delivery.id, err);
+    }
+}
+
+// A delivery with the tag it was recorded under, for linking back to the webhook,
+// and the name of the destination it's for while that still exists
+#[derive(Serialize)]
+pub struct DeliveryView {
+    #[serde(flatten)]
+    pub delivery: Delivery,
+    pub url_suffix: String,
+    pub destination: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DeliveryQuery {
+    pub state: Option<String>,
+}
+
+impl DeliveryQuery {
+    // Dead letters unless asked otherwise, they're what people come looking for
+    pub fn state(&self) -> Result<DeliveryState, String> {
+        match &self.state {
+            Some(val) => DeliveryState::parse(val)
+                .ok_or_else(|| format!("state must be pending, delivered or dead, not {}", val)),
+            None => Ok(DeliveryState::Dead),
+        }
+    }
+}
+
+// Most recently changed first
+pub(crate) fn load_deliveries(
+    conn: &PgConnection,
+    state: DeliveryState,
+) -> Result<Vec<DeliveryView>, diesel::result::Error> {
+    let found = deliveries::table
+        .inner_join(webhooks::table.inner_join(tags::table))
+        .left_join(forward_destinations::table)
+        .filter(deliveries::state.eq(state.as_str()))
+        .order_by(deliveries::updated_at.desc())
+        .limit(LIST_LIMIT)
+        .select((
+            deliveries::all_columns,
+            tags::url_suffix,
+            forward_destinations::name.nullable(),
+        ))
+        .load::<(Delivery, String, Option<String>)>(conn)?;
+    Ok(found
+        .into_iter()
+        .map(|(delivery, url_suffix, destination)| DeliveryView {
+            delivery,
+            url_suffix,
+            destination,
+        })
+        .collect())
+}
+
+// Every destination a webhook went to, in the order they were queued
+pub(crate) fn load_webhook_deliveries(
+    conn: &PgConnection,
+    webhook_id: i32,
+) -> Result<Vec<(Delivery, Option<String>)>, diesel::result::Error> {
+    deliveries::table
+        .left_join(forward_destinations::table)
+        .filter(deliveries::webhook_id.eq(webhook_id))
+        .order_by(deliveries::id)
+        .select((
+            deliveries::all_columns,
+            forward_destinations::name.nullable(),
+        ))
+        .load::<(Delivery, Option<String>)>(conn)
+}
+
+#[derive(Serialize)]
+struct DeliveriesPayload {
+    state: &'static str,
+    deliveries: Vec<DeliveryView>,
+    error: Option<String>,
+}
+
+// GET /deliveries
+pub async fn show_deliveries(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    query: DeliveryQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let state = match query.state() {
+        Ok(state) => state,
+        Err(message) => {
+            // The message quotes the query back, so it goes through the template to be escaped
+            let payload = DeliveriesPayload {
+                state: DeliveryState::Dead.as_str(),
+                deliveries: Vec::new(),
+                error: Some(message),
+            };
+            return Ok(render_deliveries(
+                &templater,
+                &payload,
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+    let found = match db::connect(&pool) {
+        Ok(conn) => load_deliveries(&conn, state),
+        Err(_) => {
+            let payload = DeliveriesPayload {
+                state: state.as_str(),
+                deliveries: Vec::new(),
+                error: Some(db::UNAVAILABLE.to_string()),
+            };
+            return Ok(render_deliveries(
+                &templater,
+                &payload,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ));
+        }
+    };
+    let (payload, status) = match found {
+        Ok(found) => (
+            DeliveriesPayload {
+                state: state.as_str(),
+                deliveries: found,
+                error: None,
+            },
+            StatusCode::OK,
+        ),
+        Err(err) => (
+            DeliveriesPayload {
+                state: state.as_str(),
+                deliveries: Vec::new(),
+                error: Some(err.to_string()),
+            },
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    };
+    Ok(render_deliveries(&templater, &payload, status))
+}
+
+fn render_deliveries(
+    templater: &Templater,
+    payload: &DeliveriesPayload,
+    status: StatusCode,
+) -> impl warp::Reply {
+    let html = templater.hb.render("deliveries", payload);
+    warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    )
+}
+
+// POST /deliveries/:id/redrive and /deliveries/redrive for every dead letter
+pub async fn redrive_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    queue: DeliveryQueue,
+    delivery_id: Option<i32>,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(db::UNAVAILABLE.to_string()))
+        }
+    };
+    let redriven = match delivery_id {
+        Some(found_id) => queue.redrive(&conn, found_id).map(|_| 1),
+        None => queue.redrive_dead(&conn),
+    };
+    Ok(match redriven {
+        Ok(_) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(LOCATION, "/deliveries")
+            .body(String::new()),
+        Err(diesel::result::Error::NotFound) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such dead letter".to_string()),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(err.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff_delay, show_deliveries, DeliveryQuery, DeliveryState};
+    use crate::templating::Templater;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use std::time::Duration;
+    use warp::http::header::CONTENT_TYPE;
+    use warp::Reply;
+
+    #[test]
+    fn test_backoff_delay() {
+        let base = Duration::from_secs(10);
+        let cap = Duration::from_secs(3600);
+        assert_eq!(backoff_delay(1, base, cap, 0.0), Duration::from_secs(5));
+        assert_eq!(backoff_delay(1, base, cap, 1.0), Duration::from_secs(10));
+        assert_eq!(backoff_delay(2, base, cap, 0.5), Duration::from_secs(15));
+        assert_eq!(backoff_delay(4, base, cap, 0.0), Duration::from_secs(40));
+        assert_eq!(backoff_delay(12, base, cap, 0.0), Duration::from_secs(1800));
+        assert_eq!(backoff_delay(i32::MAX, base, cap, 1.0), cap);
+        assert_eq!(backoff_delay(0, base, cap, 1.0), base);
+    }
+
+    #[test]
+    fn test_delivery_query_state() {
+        assert_eq!(DeliveryQuery::default().state(), Ok(DeliveryState::Dead));
+        let pending = DeliveryQuery {
+            state: Some("pending".to_string()),
+        };
+        assert_eq!(pending.state(), Ok(DeliveryState::Pending));
+        let bogus = DeliveryQuery {
+            state: Some("lost".to_string()),
+        };
+        assert!(bogus.state().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_show_deliveries_escapes_bad_state() {
+        // A bad state is turned away before the pool is asked for a connection
+        let pool = Pool::builder().build_unchecked(ConnectionManager::new("pgsql://test"));
+        let query = DeliveryQuery {
+            state: Some("<script>alert(1)</script>".to_string()),
+        };
+        let response = show_deliveries(pool, Templater::new(), query)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE].to_str().unwrap(),
+            "text/html; charset=utf-8"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("not &lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!page.contains("<script>alert"));
+    }
+}
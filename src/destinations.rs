@@ -0,0 +1,531 @@
+use super::db;
+use super::forward;
+use super::jsonpath;
+use super::model::{Destination, DestinationSpec, Tag, Webhook};
+use super::schema::{forward_destinations, tags};
+use super::templating::Templater;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use metrics::counter;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::http::header::{HeaderName, LOCATION};
+use warp::http::{Response, StatusCode};
+
+pub(crate) const DUPLICATE_NAME: &str = "This tag already has a destination with that name";
+// Postgres named the UNIQUE (tag_id, name) in the forward_destinations migration
+pub(crate) const NAME_CONSTRAINT: &str = "forward_destinations_tag_id_name_key";
+
+// Trims what was sent and turns blanks into None before checking it, for the api and form alike
+pub(crate) fn clean_spec(spec: DestinationSpec) -> Result<DestinationSpec, String> {
+    let blank_to_none = |val: Option<String>| {
+        val.map(|val| val.trim().to_string())
+            .filter(|val| !val.is_empty())
+    };
+    let cleaned = DestinationSpec {
+        name: spec.name.trim().to_string(),
+        url: spec.url.trim().to_string(),
+        enabled: spec.enabled,
+        match_header: blank_to_none(spec.match_header),
+        match_header_value: spec.match_header_value.filter(|val| !val.is_empty()),
+        match_json_path: blank_to_none(spec.match_json_path),
+        match_json_value: spec.match_json_value.filter(|val| !val.is_null()),
+    };
+    if cleaned.name.is_empty() || cleaned.name.chars().count() > 64 {
+        return Err("A destination needs a name of at most 64 characters".to_string());
+    }
+    if cleaned.url.is_empty() {
+        return Err("A destination needs a URL".to_string());
+    }
+    forward::validate_forward_url(&cleaned.url)?;
+    match &cleaned.match_header {
+        Some(name) => {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("{} is not a valid header name", name))?;
+        }
+        None if cleaned.match_header_value.is_some() => {
+            return Err("A header value to match needs a header name".to_string())
+        }
+        None => {}
+    }
+    if cleaned.match_json_path.is_none() && cleaned.match_json_value.is_some() {
+        return Err("A json value to match needs a path".to_string());
+    }
+    Ok(cleaned)
+}
+
+// Both conditions have to hold when both are set, a json condition never matches a non-json body
+pub(crate) fn matches(destination: &Destination, webhook: &Webhook) -> bool {
+    let header_ok = match &destination.match_header {
+        Some(name) => webhook
+            .headers
+            .iter()
+            .filter(|pair| pair.name.eq_ignore_ascii_case(name))
+            .any(|pair| {
+                destination
+                    .match_header_value
+                    .as_ref()
+                    .map_or(true, |wanted| pair.value == *wanted)
+            }),
+        None => true,
+    };
+    let json_ok = match &destination.match_json_path {
+        Some(path) => webhook
+            .body_text()
+            .and_then(|text| serde_json::from_str::<Value>(text).ok())
+            .map_or(false, |doc| {
+                jsonpath::matches(&doc, path, destination.match_json_value.as_ref())
+            }),
+        None => true,
+    };
+    header_ok && json_ok
+}
+
+// Oldest first, the order they're listed and queued in
+pub(crate) fn load_destinations(
+    conn: &PgConnection,
+    found_tag_id: i32,
+) -> Result<Vec<Destination>, diesel::result::Error> {
+    forward_destinations::table
+        .filter(forward_destinations::tag_id.eq(found_tag_id))
+        .order_by(forward_destinations::id)
+        .load::<Destination>(conn)
+}
+
+pub(crate) fn create_destination(
+    conn: &PgConnection,
+    found_tag_id: i32,
+    spec: &DestinationSpec,
+) -> Result<Destination, diesel::result::Error> {
+    diesel::insert_into(forward_destinations::table)
+        .values((forward_destinations::tag_id.eq(found_tag_id), spec))
+        .get_result::<Destination>(conn)
+}
+
+pub(crate) fn replace_destination(
+    conn: &PgConnection,
+    found_tag_id: i32,
+    destination_id: i32,
+    spec: &DestinationSpec,
+) -> Result<Destination, diesel::result::Error> {
+    diesel::update(
+        forward_destinations::table
+            .filter(forward_destinations::tag_id.eq(found_tag_id))
+            .filter(forward_destinations::id.eq(destination_id)),
+    )
+    .set(spec)
+    .get_result::<Destination>(conn)
+}
+
+// Its deliveries stay with their webhooks, they just lose the link back to it
+pub(crate) fn delete_destination(
+    conn: &PgConnection,
+    found_tag_id: i32,
+    destination_id: i32,
+) -> Result<Destination, diesel::result::Error> {
+    diesel::delete(
+        forward_destinations::table
+            .filter(forward_destinations::tag_id.eq(found_tag_id))
+            .filter(forward_destinations::id.eq(destination_id)),
+    )
+    .get_result::<Destination>(conn)
+}
+
+fn find_tag(conn: &PgConnection, suffix: &str) -> Result<Tag, diesel::result::Error> {
+    tags::table
+        .filter(tags::url_suffix.eq(suffix))
+        .first::<Tag>(conn)
+}
+
+// A scalar json value is typed as itself, anything that isn't json is taken as a string
+fn parse_destination_form(body: &HashMap<String, String>) -> DestinationSpec {
+    let field = |name: &str| body.get(name).cloned();
+    DestinationSpec {
+        name: field("name").unwrap_or_default(),
+        url: field("url").unwrap_or_default(),
+        enabled: body.contains_key("enabled"),
+        match_header: field("match_header"),
+        match_header_value: field("match_header_value"),
+        match_json_path: field("match_json_path"),
+        match_json_value: field("match_json_value")
+            .map(|val| val.trim().to_string())
+            .filter(|val| !val.is_empty())
+            .map(|val| serde_json::from_str(&val).unwrap_or(Value::String(val))),
+    }
+}
+
+// The form's fields as they'll be shown again, json values written back out as typed
+#[derive(Serialize, Default)]
+struct DestinationForm {
+    name: String,
+    url: String,
+    enabled: bool,
+    match_header: Option<String>,
+    match_header_value: Option<String>,
+    match_json_path: Option<String>,
+    match_json_value: Option<String>,
+}
+
+impl From<&DestinationSpec> for DestinationForm {
+    fn from(spec: &DestinationSpec) -> Self {
+        DestinationForm {
+            name: spec.name.clone(),
+            url: spec.url.clone(),
+            enabled: spec.enabled,
+            match_header: spec.match_header.clone(),
+            match_header_value: spec.match_header_value.clone(),
+            match_json_path: spec.match_json_path.clone(),
+            match_json_value: spec.match_json_value.as_ref().map(|val| match val {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DestinationRow {
+    #[serde(flatten)]
+    destination: Destination,
+    condition: Option<String>,
+}
+
+fn describe_condition(destination: &Destination) -> Option<String> {
+    let header =
+        destination
+            .match_header
+            .as_ref()
+            .map(|name| match &destination.match_header_value {
+                Some(value) => format!("header {} is {}", name, value),
+                None => format!("header {} is present", name),
+            });
+    let json =
+        destination
+            .match_json_path
+            .as_ref()
+            .map(|path| match &destination.match_json_value {
+                Some(value) => format!("{} is {}", path, value),
+                None => format!("{} exists", path),
+            });
+    match (header, json) {
+        (Some(header), Some(json)) => Some(format!("{} and {}", header, json)),
+        (header, json) => header.or(json),
+    }
+}
+
+#[derive(Serialize, Default)]
+struct DestinationsPayload {
+    url_suffix: String,
+    tag: Option<Tag>,
+    destinations: Vec<DestinationRow>,
+    // Set when the form is editing an existing destination rather than adding one
+    editing: Option<i32>,
+    form: DestinationForm,
+    error: Option<String>,
+}
+
+fn render_destinations(
+    templater: &Templater,
+    conn: &PgConnection,
+    mut payload: DestinationsPayload,
+    status: StatusCode,
+) -> impl warp::Reply {
+    if let Some(tag) = &payload.tag {
+        payload.destinations = load_destinations(conn, tag.tag_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|destination| DestinationRow {
+                condition: describe_condition(&destination),
+                destination,
+            })
+            .collect();
+    }
+    let html = templater.hb.render("tag_forward", &payload);
+    warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    )
+}
+
+// GET /tags/:tag/forward and /tags/:tag/forward/:id to edit one
+pub async fn show_destinations(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+    destination_id: Option<i32>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => return Ok(Box::new(db::unavailable())),
+    };
+    let found = find_tag(&conn, &suffix).and_then(|tag| {
+        let editing = match destination_id {
+            Some(wanted) => Some(
+                forward_destinations::table
+                    .filter(forward_destinations::tag_id.eq(tag.tag_id))
+                    .filter(forward_destinations::id.eq(wanted))
+                    .first::<Destination>(&conn)?,
+            ),
+            None => None,
+        };
+        Ok((tag, editing))
+    });
+    let (payload, status) = match found {
+        Ok((tag, editing)) => (
+            DestinationsPayload {
+                url_suffix: suffix,
+                tag: Some(tag),
+                editing: editing.as_ref().map(|destination| destination.id),
+                form: match &editing {
+                    Some(destination) => DestinationForm::from(&DestinationSpec::from(destination)),
+                    None => DestinationForm {
+                        enabled: true,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            },
+            StatusCode::OK,
+        ),
+        Err(diesel::result::Error::NotFound) => (
+            DestinationsPayload {
+                url_suffix: suffix,
+                error: Some("No such tag or destination".to_string()),
+                ..Default::default()
+            },
+            StatusCode::NOT_FOUND,
+        ),
+        Err(err) => (
+            DestinationsPayload {
+                url_suffix: suffix,
+                error: Some(err.to_string()),
+                ..Default::default()
+            },
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    };
+    Ok(Box::new(render_destinations(
+        &templater, &conn, payload, status,
+    )))
+}
+
+// POST /tags/:tag/forward to add a destination and /tags/:tag/forward/:id to save one
+pub async fn save_destination_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+    destination_id: Option<i32>,
+    body: HashMap<String, String>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => return Ok(Box::new(db::unavailable())),
+    };
+    let requested = parse_destination_form(&body);
+    let tag = match find_tag(&conn, &suffix) {
+        Ok(tag) => tag,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(Box::new(
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("No such tag".to_string()),
+            ))
+        }
+        Err(err) => {
+            return Ok(Box::new(
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(err.to_string()),
+            ))
+        }
+    };
+    let saved = clean_spec(requested.clone()).and_then(|spec| {
+        match destination_id {
+            Some(found_id) => replace_destination(&conn, tag.tag_id, found_id, &spec),
+            None => create_destination(&conn, tag.tag_id, &spec),
+        }
+        .map_err(|err| match err {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => DUPLICATE_NAME.to_string(),
+            diesel::result::Error::NotFound => "No such destination".to_string(),
+            err => err.to_string(),
+        })
+    });
+    match saved {
+        Ok(_) => {
+            counter!("destinations.save_destination_form.saved", 1);
+            Ok(Box::new(
+                Response::builder()
+                    .status(StatusCode::SEE_OTHER)
+                    .header(LOCATION, format!("/tags/{}/forward", suffix))
+                    .body(String::new()),
+            ))
+        }
+        Err(message) => {
+            let payload = DestinationsPayload {
+                url_suffix: suffix,
+                tag: Some(tag),
+                editing: destination_id,
+                form: DestinationForm::from(&requested),
+                error: Some(message),
+                ..Default::default()
+            };
+            Ok(Box::new(render_destinations(
+                &templater,
+                &conn,
+                payload,
+                StatusCode::BAD_REQUEST,
+            )))
+        }
+    }
+}
+
+// POST /tags/:tag/forward/:id/delete
+pub async fn delete_destination_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    destination_id: i32,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(db::UNAVAILABLE.to_string()))
+        }
+    };
+    let deleted = find_tag(&conn, &suffix)
+        .and_then(|tag| delete_destination(&conn, tag.tag_id, destination_id));
+    Ok(match deleted {
+        Ok(_) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(LOCATION, format!("/tags/{}/forward", suffix))
+            .body(String::new()),
+        Err(diesel::result::Error::NotFound) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such destination".to_string()),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(err.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clean_spec, describe_condition, matches, parse_destination_form};
+    use crate::model::{Destination, DestinationSpec, Webhook};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn webhook(event: &str, body: &str) -> Webhook {
+        crate::model::tests::webhook("POST", &[("x-github-event", event)], body.as_bytes())
+    }
+
+    fn destination(spec: DestinationSpec) -> Destination {
+        Destination {
+            id: 1,
+            tag_id: 1,
+            name: spec.name,
+            url: spec.url,
+            enabled: spec.enabled,
+            match_header: spec.match_header,
+            match_header_value: spec.match_header_value,
+            match_json_path: spec.match_json_path,
+            match_json_value: spec.match_json_value,
+            created_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let push = webhook("push", r#"{"ref": "refs/heads/main", "forced": false}"#);
+        let issue = webhook("issues", "not json");
+        let everything = destination(DestinationSpec::default());
+        assert!(matches(&everything, &push) && matches(&everything, &issue));
+        let pushes = destination(DestinationSpec {
+            match_header: Some("X-GitHub-Event".to_string()),
+            match_header_value: Some("push".to_string()),
+            ..Default::default()
+        });
+        assert!(matches(&pushes, &push));
+        assert!(!matches(&pushes, &issue));
+        let main_branch = destination(DestinationSpec {
+            match_json_path: Some("$.ref".to_string()),
+            match_json_value: Some(json!("refs/heads/main")),
+            ..Default::default()
+        });
+        assert!(matches(&main_branch, &push));
+        assert!(!matches(&main_branch, &issue));
+        let both = destination(DestinationSpec {
+            match_header: Some("x-github-event".to_string()),
+            match_json_path: Some("forced".to_string()),
+            match_json_value: Some(json!(true)),
+            ..Default::default()
+        });
+        assert!(!matches(&both, &push));
+        assert_eq!(
+            describe_condition(&both).as_deref(),
+            Some("header x-github-event is present and forced is true")
+        );
+        assert_eq!(describe_condition(&everything), None);
+    }
+
+    #[test]
+    fn test_clean_spec() {
+        let spec = DestinationSpec {
+            name: " staging ".to_string(),
+            url: "http://staging.internal/hooks".to_string(),
+            enabled: true,
+            match_header: Some("  ".to_string()),
+            ..Default::default()
+        };
+        let cleaned = clean_spec(spec.clone()).unwrap();
+        assert_eq!(cleaned.name, "staging");
+        assert_eq!(cleaned.match_header, None);
+        assert!(clean_spec(DestinationSpec {
+            name: String::new(),
+            ..spec.clone()
+        })
+        .is_err());
+        assert!(clean_spec(DestinationSpec {
+            url: "ftp://staging.internal/hooks".to_string(),
+            ..spec.clone()
+        })
+        .is_err());
+        assert!(clean_spec(DestinationSpec {
+            match_header_value: Some("push".to_string()),
+            ..spec.clone()
+        })
+        .is_err());
+        assert!(clean_spec(DestinationSpec {
+            match_header: Some("bad header".to_string()),
+            ..spec.clone()
+        })
+        .is_err());
+        assert!(clean_spec(DestinationSpec {
+            match_json_value: Some(json!(1)),
+            ..spec
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_destination_form() {
+        let mut form = HashMap::new();
+        form.insert("name".to_string(), "qa".to_string());
+        form.insert("url".to_string(), "http://qa.internal/in".to_string());
+        form.insert("match_json_path".to_string(), "$.count".to_string());
+        form.insert("match_json_value".to_string(), "3".to_string());
+        let spec = parse_destination_form(&form);
+        assert!(!spec.enabled);
+        assert_eq!(spec.match_json_value, Some(json!(3)));
+        form.insert("enabled".to_string(), "on".to_string());
+        form.insert("match_json_value".to_string(), "opened".to_string());
+        let spec = parse_destination_form(&form);
+        assert!(spec.enabled);
+        assert_eq!(spec.match_json_value, Some(json!("opened")));
+    }
+}
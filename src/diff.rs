@@ -0,0 +1,506 @@
+use super::db;
+use super::display::find_tag_id;
+use super::model::{HeaderList, Webhook};
+use super::schema::webhooks;
+use super::templating::Templater;
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use log::debug;
+use metrics::{counter, timing};
+use quanta::Clock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::convert::TryInto;
+use warp::http::StatusCode;
+
+// Past this many line comparisons the changed middle is shown as removed then added
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Deserialize, Debug)]
+pub struct DiffQuery {
+    pub a: Option<i32>,
+    pub b: Option<i32>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct HeaderChange {
+    name: String,
+    before: Vec<String>,
+    after: Vec<String>,
+    change: &'static str,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct JsonChange {
+    path: String,
+    change: &'static str,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+// One row of the side by side view, a None side is a gap opposite an added or removed line
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct LineRow {
+    before_line: Option<usize>,
+    before: Option<String>,
+    after_line: Option<usize>,
+    after: Option<String>,
+    change: &'static str,
+}
+
+// Values grouped by lowercased name, in the order the names first turn up
+fn group_headers(list: &HeaderList) -> Vec<(String, Vec<String>)> {
+    let mut grouped: Vec<(String, Vec<String>)> = Vec::new();
+    for pair in list.iter() {
+        let name = pair.name.to_lowercase();
+        match grouped.iter_mut().find(|(found, _)| *found == name) {
+            Some((_, values)) => values.push(pair.value.clone()),
+            None => grouped.push((name, vec![pair.value.clone()])),
+        }
+    }
+    grouped
+}
+
+pub(crate) fn diff_headers(before: &HeaderList, after: &HeaderList) -> Vec<HeaderChange> {
+    let before = group_headers(before);
+    let after = group_headers(after);
+    let find = |list: &[(String, Vec<String>)], name: &str| {
+        list.iter()
+            .find(|(found, _)| found == name)
+            .map(|(_, values)| values.clone())
+    };
+    let mut names: Vec<&String> = before.iter().map(|(name, _)| name).collect();
+    names.extend(
+        after
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| find(&before, name).is_none()),
+    );
+    names
+        .into_iter()
+        .map(|name| {
+            let (old, new) = (find(&before, name), find(&after, name));
+            let change = match (&old, &new) {
+                (Some(old), Some(new)) if old == new => "same",
+                (Some(_), Some(_)) => "changed",
+                (Some(_), None) => "removed",
+                _ => "added",
+            };
+            HeaderChange {
+                name: name.clone(),
+                before: old.unwrap_or_default(),
+                after: new.unwrap_or_default(),
+                change,
+            }
+        })
+        .collect()
+}
+
+// Paths come out in the $.a.b[0] form the search and websocket filters take
+fn child_path(path: &str, key: &str) -> String {
+    let plain = key
+        .chars()
+        .next()
+        .map_or(false, |first| first.is_ascii_alphabetic() || first == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        format!("{}.{}", path, key)
+    } else {
+        format!(
+            "{}[{}]",
+            path,
+            serde_json::to_string(key).unwrap_or_default()
+        )
+    }
+}
+
+pub(crate) fn diff_json(before: &Value, after: &Value) -> Vec<JsonChange> {
+    let mut changes = Vec::new();
+    walk_json("$", Some(before), Some(after), &mut changes);
+    changes
+}
+
+fn walk_json(
+    path: &str,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    changes: &mut Vec<JsonChange>,
+) {
+    match (before, after) {
+        (Some(Value::Object(old)), Some(Value::Object(new))) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                walk_json(&child_path(path, key), old.get(key), new.get(key), changes);
+            }
+        }
+        (Some(Value::Array(old)), Some(Value::Array(new))) => {
+            for index in 0..old.len().max(new.len()) {
+                let item_path = format!("{}[{}]", path, index);
+                walk_json(&item_path, old.get(index), new.get(index), changes);
+            }
+        }
+        (Some(old), Some(new)) if old == new => {}
+        (old, new) => changes.push(JsonChange {
+            path: path.to_string(),
+            change: match (old, new) {
+                (Some(_), Some(_)) => "changed",
+                (Some(_), None) => "removed",
+                _ => "added",
+            },
+            before: old.map(Value::to_string),
+            after: new.map(Value::to_string),
+        }),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum LineOp {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+// Longest common subsequence over whatever is left once the shared ends are trimmed off
+fn line_ops(before: &[&str], after: &[&str]) -> Vec<LineOp> {
+    let prefix = before
+        .iter()
+        .zip(after)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old = &before[prefix..before.len() - suffix];
+    let new = &after[prefix..after.len() - suffix];
+    let mut ops: Vec<LineOp> = (0..prefix)
+        .map(|index| LineOp::Same(index, index))
+        .collect();
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        ops.extend((0..old.len()).map(|index| LineOp::Removed(prefix + index)));
+        ops.extend((0..new.len()).map(|index| LineOp::Added(prefix + index)));
+    } else {
+        let width = new.len() + 1;
+        let mut common = vec![0u32; (old.len() + 1) * width];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i * width + j] = if old[i] == new[j] {
+                    common[(i + 1) * width + j + 1] + 1
+                } else {
+                    common[(i + 1) * width + j].max(common[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                ops.push(LineOp::Same(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if j == new.len()
+                || (i < old.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+            {
+                ops.push(LineOp::Removed(prefix + i));
+                i += 1;
+            } else {
+                ops.push(LineOp::Added(prefix + j));
+                j += 1;
+            }
+        }
+    }
+    let old_end = before.len() - suffix;
+    let new_end = after.len() - suffix;
+    ops.extend((0..suffix).map(|index| LineOp::Same(old_end + index, new_end + index)));
+    ops
+}
+
+// Runs of removed lines pair up with the added lines that follow them as changed rows
+pub(crate) fn diff_lines(before: &str, after: &str) -> Vec<LineRow> {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    let mut rows = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let flush = |rows: &mut Vec<LineRow>, removed: &mut Vec<usize>, added: &mut Vec<usize>| {
+        for index in 0..removed.len().max(added.len()) {
+            let (old_line, new_line) = (removed.get(index), added.get(index));
+            rows.push(LineRow {
+                before_line: old_line.map(|line| line + 1),
+                before: old_line.map(|line| old[*line].to_string()),
+                after_line: new_line.map(|line| line + 1),
+                after: new_line.map(|line| new[*line].to_string()),
+                change: match (old_line, new_line) {
+                    (Some(_), Some(_)) => "changed",
+                    (Some(_), None) => "removed",
+                    _ => "added",
+                },
+            });
+        }
+        removed.clear();
+        added.clear();
+    };
+    for op in line_ops(&old, &new) {
+        match op {
+            LineOp::Removed(line) => removed.push(line),
+            LineOp::Added(line) => added.push(line),
+            LineOp::Same(old_line, new_line) => {
+                flush(&mut rows, &mut removed, &mut added);
+                rows.push(LineRow {
+                    before_line: Some(old_line + 1),
+                    before: Some(old[old_line].to_string()),
+                    after_line: Some(new_line + 1),
+                    after: Some(new[new_line].to_string()),
+                    change: "same",
+                });
+            }
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+#[derive(Serialize)]
+struct DiffSide<'a> {
+    id: i32,
+    method: &'a str,
+    upload_time: NaiveDateTime,
+    path_tail: &'a str,
+    query_string: Option<&'a str>,
+    content_type: Option<&'a str>,
+    body_size: usize,
+}
+
+impl<'a> DiffSide<'a> {
+    fn new(webhook: &'a Webhook) -> DiffSide<'a> {
+        DiffSide {
+            id: webhook.id,
+            method: &webhook.method,
+            upload_time: webhook.upload_time,
+            path_tail: &webhook.path_tail,
+            query_string: webhook.query_string.as_deref(),
+            content_type: webhook.content_type(),
+            body_size: webhook.body.len(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DiffPayload<'a> {
+    url_suffix: &'a str,
+    a: DiffSide<'a>,
+    b: DiffSide<'a>,
+    headers: Vec<HeaderChange>,
+    headers_changed: usize,
+    // Both bodies are JSON, though the changes can still be empty when only formatting differs
+    is_json: bool,
+    json_changes: Option<Vec<JsonChange>>,
+    // Set when both bodies are text but at least one isn't JSON
+    lines: Option<Vec<LineRow>>,
+    identical_bodies: bool,
+}
+
+impl<'a> DiffPayload<'a> {
+    fn new(url_suffix: &'a str, before: &'a Webhook, after: &'a Webhook) -> DiffPayload<'a> {
+        let headers = diff_headers(&before.headers, &after.headers);
+        let parse = |text: &str| serde_json::from_str::<Value>(text).ok();
+        let (json_changes, lines) = match (before.body_text(), after.body_text()) {
+            (Some(old), Some(new)) => match (parse(old), parse(new)) {
+                (Some(old), Some(new)) => (Some(diff_json(&old, &new)), None),
+                _ => (None, Some(diff_lines(old, new))),
+            },
+            _ => (None, None),
+        };
+        DiffPayload {
+            url_suffix,
+            a: DiffSide::new(before),
+            b: DiffSide::new(after),
+            headers_changed: headers
+                .iter()
+                .filter(|header| header.change != "same")
+                .count(),
+            headers,
+            is_json: json_changes.is_some(),
+            json_changes,
+            lines,
+            identical_bodies: before.body == after.body,
+        }
+    }
+}
+
+fn load_pair(
+    conn: &PgConnection,
+    display_url: &str,
+    a: i32,
+    b: i32,
+) -> Result<(Webhook, Webhook), diesel::result::Error> {
+    let tag_id_val = find_tag_id(conn, display_url)?;
+    let load = |wanted: i32| {
+        webhooks::table
+            .filter(webhooks::tag_id.eq(tag_id_val))
+            .filter(webhooks::id.eq(wanted))
+            .first::<Webhook>(conn)
+    };
+    Ok((load(a)?, load(b)?))
+}
+
+// GET /display/:tag/diff?a=ID&b=ID
+pub async fn display_diff(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    display_url: String,
+    query: DiffQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    debug!("Diff requested for {}: {:?}", display_url, query);
+    let (a, b) = match (query.a, query.b) {
+        (Some(a), Some(b)) => (a, b),
+        _ => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html("Pick two webhooks to compare with a=ID&b=ID".to_string()),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+    let clock = Clock::new();
+    let diff_start = clock.start();
+    let (html, status) = match db::connect(&pool) {
+        Ok(conn) => match load_pair(&conn, &display_url, a, b) {
+            Ok((before, after)) => {
+                let payload = DiffPayload::new(&display_url, &before, &after);
+                if let Some(changes) = &payload.json_changes {
+                    counter!(
+                        "diff.display_diff.json_changes",
+                        changes.len().try_into().unwrap()
+                    );
+                }
+                (templater.hb.render("diff", &payload), StatusCode::OK)
+            }
+            Err(diesel::result::Error::NotFound) => {
+                (Ok("No such webhook".to_string()), StatusCode::NOT_FOUND)
+            }
+            Err(err) => (Ok(err.to_string()), StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        Err(_) => (
+            Ok(db::UNAVAILABLE.to_string()),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ),
+    };
+    timing!(
+        "diff.display_diff.time",
+        clock.delta(diff_start, clock.end())
+    );
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_headers, diff_json, diff_lines, line_ops, LineOp};
+    use crate::model::{HeaderList, HeaderPair};
+    use serde_json::json;
+
+    fn header_list(pairs: &[(&str, &str)]) -> HeaderList {
+        HeaderList(
+            pairs
+                .iter()
+                .map(|(name, value)| HeaderPair {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_diff_headers() {
+        let before = header_list(&[
+            ("Content-Type", "application/json"),
+            ("X-Event", "push"),
+            ("Accept", "a"),
+            ("accept", "b"),
+        ]);
+        let after = header_list(&[
+            ("content-type", "application/json"),
+            ("Accept", "a"),
+            ("X-Delivery", "42"),
+        ]);
+        let changes: Vec<(String, &str)> = diff_headers(&before, &after)
+            .into_iter()
+            .map(|header| (header.name, header.change))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("content-type".to_string(), "same"),
+                ("x-event".to_string(), "removed"),
+                ("accept".to_string(), "changed"),
+                ("x-delivery".to_string(), "added"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let before = json!({"id": 1, "items": [1, 2, 3], "user": {"name": "a", "odd key": true}});
+        let after = json!({"id": "1", "items": [1, 5], "user": {"name": "a"}, "extra": null});
+        let changes: Vec<(String, &str, Option<String>, Option<String>)> =
+            diff_json(&before, &after)
+                .into_iter()
+                .map(|change| (change.path, change.change, change.before, change.after))
+                .collect();
+        let some = |value: &str| Some(value.to_string());
+        assert_eq!(
+            changes,
+            vec![
+                ("$.extra".to_string(), "added", None, some("null")),
+                ("$.id".to_string(), "changed", some("1"), some("\"1\"")),
+                ("$.items[1]".to_string(), "changed", some("2"), some("5")),
+                ("$.items[2]".to_string(), "removed", some("3"), None),
+                (
+                    "$.user[\"odd key\"]".to_string(),
+                    "removed",
+                    some("true"),
+                    None
+                ),
+            ]
+        );
+        assert!(diff_json(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(
+            line_ops(&["a", "b", "c"], &["a", "x", "c", "d"]),
+            vec![
+                LineOp::Same(0, 0),
+                LineOp::Removed(1),
+                LineOp::Added(1),
+                LineOp::Same(2, 2),
+                LineOp::Added(3),
+            ]
+        );
+        let rows = diff_lines("one\ntwo\nthree\n", "zero\none\n2\nthree");
+        let changes: Vec<(Option<usize>, Option<usize>, &str)> = rows
+            .iter()
+            .map(|row| (row.before_line, row.after_line, row.change))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (None, Some(1), "added"),
+                (Some(1), Some(2), "same"),
+                (Some(2), Some(3), "changed"),
+                (Some(3), Some(4), "same"),
+            ]
+        );
+        assert_eq!(rows[2].before.as_deref(), Some("two"));
+        assert_eq!(rows[2].after.as_deref(), Some("2"));
+    }
+}
@@ -1,20 +1,127 @@
 extern crate chrono;
 extern crate diesel;
+use super::db;
+use super::delivery;
 use super::model::*;
+use super::replay::{self, ReplayFormView};
 use super::schema::tags;
 use super::schema::tags::dsl::*;
 use super::schema::webhooks;
 use super::schema::webhooks::dsl::*;
 use super::templating::Templater;
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::Integer;
 use log::debug;
 use metrics::{counter, timing};
 use quanta::Clock;
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use std::convert::TryInto;
+use std::fmt::Write;
 use std::mem;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use warp::http::{Response, StatusCode};
+
+const HISTORY_PAGE_SIZE: i64 = 50;
+const HISTORY_MAX_PAGE_SIZE: i64 = 500;
+// How much history the live page starts with before new webhooks stream in
+const LIVE_BACKLOG: i64 = 20;
+
+// What the display template gets, the webhook plus whichever rendering of the body makes sense
+#[derive(Serialize)]
+struct DisplayPayload<'a> {
+    #[serde(flatten)]
+    webhook: &'a Webhook,
+    body_text: Option<&'a str>,
+    // An empty body is falsy as text in the template, so binary gets a flag of its own
+    is_binary: bool,
+    body_hex: Option<String>,
+    body_size: usize,
+    content_type: Option<&'a str>,
+    url_suffix: Option<&'a str>,
+    response_body_text: Option<&'a str>,
+    replays: Vec<ReplayView<'a>>,
+    deliveries: Vec<DeliveryStatusView<'a>>,
+    // The webhook before this one in the same tag, for the compare link
+    previous_id: Option<i32>,
+    replay_form: ReplayFormView,
+}
+
+#[derive(Serialize)]
+struct DeliveryStatusView<'a> {
+    #[serde(flatten)]
+    delivery: &'a Delivery,
+    destination: Option<&'a str>,
+    response_body_text: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ReplayView<'a> {
+    #[serde(flatten)]
+    replay: &'a Replay,
+    body_text: Option<&'a str>,
+}
+
+impl<'a> DisplayPayload<'a> {
+    fn new(webhook: &'a Webhook, suffix: Option<&'a str>) -> DisplayPayload<'a> {
+        let body_text = webhook.body_text();
+        let body_hex = match body_text {
+            Some(_) => None,
+            None => Some(hex_dump(&webhook.body)),
+        };
+        DisplayPayload {
+            webhook,
+            body_text,
+            is_binary: body_text.is_none(),
+            body_hex,
+            body_size: webhook.body.len(),
+            content_type: webhook.content_type(),
+            url_suffix: suffix,
+            response_body_text: webhook.response_body_text(),
+            replays: Vec::new(),
+            deliveries: Vec::new(),
+            previous_id: None,
+            replay_form: ReplayFormView::default(),
+        }
+    }
+
+    fn with_replay_form(mut self, form: ReplayFormView) -> DisplayPayload<'a> {
+        self.replay_form = form;
+        self
+    }
+
+    fn with_previous(mut self, previous: Option<i32>) -> DisplayPayload<'a> {
+        self.previous_id = previous;
+        self
+    }
+
+    fn with_replays(mut self, replays: &'a [Replay]) -> DisplayPayload<'a> {
+        self.replays = replays
+            .iter()
+            .map(|replay| ReplayView {
+                body_text: replay.body_text(),
+                replay,
+            })
+            .collect();
+        self
+    }
+
+    fn with_deliveries(mut self, found: &'a [(Delivery, Option<String>)]) -> DisplayPayload<'a> {
+        self.deliveries = found
+            .iter()
+            .map(|(delivery, destination)| DeliveryStatusView {
+                response_body_text: delivery.response_body_text(),
+                destination: destination.as_deref(),
+                delivery,
+            })
+            .collect();
+        self
+    }
+}
 
 pub async fn display_last(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
@@ -39,7 +146,9 @@ pub async fn display_last(
         mem::size_of_val(&result).try_into().unwrap()
     );
     let render_start = clock.start();
-    let html = templater.hb.render("display", &result);
+    let html = templater
+        .hb
+        .render("display", &DisplayPayload::new(&result, None));
     timing!(
         "display.display_last.render_time",
         clock.delta(render_start, clock.end())
@@ -54,18 +163,462 @@ pub async fn display_last_by_tag(
     templater: Templater,
     display_url: String,
 ) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html(db::UNAVAILABLE.to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    };
     let tag_id_val: i32 = tags
-        .filter(url_suffix.eq(display_url))
+        .filter(url_suffix.eq(&display_url))
         .select(tags::tag_id)
-        .first(&pool.get().unwrap())
+        .first(&conn)
         .unwrap();
     let webhook_for_tag = webhooks
         .filter(webhooks::tag_id.eq(tag_id_val))
         .order_by(upload_time.desc())
-        .first::<Webhook>(&pool.get().unwrap())
+        .first::<Webhook>(&conn)
         .unwrap();
-    let html = templater.hb.render("display", &webhook_for_tag);
-    Ok(warp::reply::html(
-        html.unwrap_or_else(|err| err.to_string()),
+    let replays = replay::load_replays(&conn, webhook_for_tag.id).unwrap_or_default();
+    let sent = delivery::load_webhook_deliveries(&conn, webhook_for_tag.id).unwrap_or_default();
+    let previous = previous_webhook_id(&conn, &webhook_for_tag).unwrap_or_default();
+    let html = templater.hb.render(
+        "display",
+        &DisplayPayload::new(&webhook_for_tag, Some(&display_url))
+            .with_replays(&replays)
+            .with_deliveries(&sent)
+            .with_previous(previous),
+    );
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        StatusCode::OK,
     ))
 }
+
+pub async fn display_by_id(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    display_url: String,
+    webhook_id: i32,
+) -> Result<impl warp::Reply, Infallible> {
+    let (html, status) = match db::connect(&pool) {
+        Ok(conn) => render_webhook_page(
+            &conn,
+            &templater,
+            &display_url,
+            webhook_id,
+            ReplayFormView::default(),
+        ),
+        Err(_) => (db::UNAVAILABLE.to_string(), StatusCode::SERVICE_UNAVAILABLE),
+    };
+    Ok(warp::reply::with_status(warp::reply::html(html), status))
+}
+
+// One webhook in its tag, shared with forms on the page that send it back with an error
+pub(crate) fn render_webhook_page(
+    conn: &PgConnection,
+    templater: &Templater,
+    display_url: &str,
+    webhook_id: i32,
+    replay_form: ReplayFormView,
+) -> (String, StatusCode) {
+    let found = find_tag_id(conn, display_url).and_then(|tag_id_val| {
+        webhooks
+            .filter(webhooks::tag_id.eq(tag_id_val))
+            .filter(id.eq(webhook_id))
+            .first::<Webhook>(conn)
+    });
+    let (html, status) = match found {
+        Ok(webhook) => {
+            let replays = replay::load_replays(conn, webhook.id).unwrap_or_default();
+            let sent = delivery::load_webhook_deliveries(conn, webhook.id).unwrap_or_default();
+            let previous = previous_webhook_id(conn, &webhook).unwrap_or_default();
+            (
+                templater.hb.render(
+                    "display",
+                    &DisplayPayload::new(&webhook, Some(display_url))
+                        .with_replays(&replays)
+                        .with_deliveries(&sent)
+                        .with_previous(previous)
+                        .with_replay_form(replay_form),
+                ),
+                StatusCode::OK,
+            )
+        }
+        Err(diesel::result::Error::NotFound) => {
+            (Ok("No such webhook".to_string()), StatusCode::NOT_FOUND)
+        }
+        Err(err) => (Ok(err.to_string()), StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    (html.unwrap_or_else(|err| err.to_string()), status)
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct WebhookQuery {
+    // Keyset pagination, only show webhooks with an id lower than this
+    pub before: Option<i32>,
+    pub limit: Option<i64>,
+    pub method: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct WebhookSummary {
+    pub id: i32,
+    pub method: String,
+    pub upload_time: NaiveDateTime,
+    pub body_size: i32,
+    pub content_type: Option<String>,
+    pub response_status: Option<i32>,
+    pub signature_verdict: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HistoryPayload {
+    url_suffix: String,
+    webhooks: Vec<WebhookSummary>,
+    method: Option<String>,
+    is_first_page: bool,
+    next_before: Option<i32>,
+}
+
+// One page of webhooks for a tag, newest first, along with the id to pass as before for the next page
+pub(crate) fn load_webhook_page(
+    conn: &PgConnection,
+    tag_id_val: i32,
+    query: &WebhookQuery,
+) -> Result<(Vec<WebhookSummary>, Option<i32>), diesel::result::Error> {
+    let page_size = query
+        .limit
+        .unwrap_or(HISTORY_PAGE_SIZE)
+        .clamp(1, HISTORY_MAX_PAGE_SIZE);
+    // Rides the by_tag (tag_id, id DESC) index, fetching one extra row to know if there's another page
+    let mut page = webhooks
+        .filter(webhooks::tag_id.eq(tag_id_val))
+        .select((
+            id,
+            method,
+            upload_time,
+            headers,
+            sql::<Integer>("octet_length(body)"),
+            webhooks::response_status,
+            signature_verdict,
+        ))
+        .order_by(id.desc())
+        .limit(page_size + 1)
+        .into_boxed();
+    if let Some(before) = query.before {
+        page = page.filter(id.lt(before));
+    }
+    if let Some(wanted_method) = query.method.as_deref().filter(|m| !m.is_empty()) {
+        page = page.filter(method.eq(wanted_method.to_uppercase()));
+    }
+    if let Some(since) = query.since {
+        page = page.filter(upload_time.ge(since));
+    }
+    if let Some(until) = query.until {
+        page = page.filter(upload_time.lt(until));
+    }
+    let mut rows = page.load::<(
+        i32,
+        String,
+        NaiveDateTime,
+        HeaderList,
+        i32,
+        Option<i32>,
+        Option<String>,
+    )>(conn)?;
+    let has_more = rows.len() as i64 > page_size;
+    rows.truncate(page_size as usize);
+    let summaries: Vec<WebhookSummary> = rows
+        .into_iter()
+        .map(
+            |(row_id, row_method, row_time, row_headers, row_size, row_status, row_verdict)| {
+                WebhookSummary {
+                    id: row_id,
+                    method: row_method,
+                    upload_time: row_time,
+                    body_size: row_size,
+                    content_type: row_headers.get("content-type").map(String::from),
+                    response_status: row_status,
+                    signature_verdict: row_verdict,
+                }
+            },
+        )
+        .collect();
+    let next_before = if has_more {
+        summaries.last().map(|row| row.id)
+    } else {
+        None
+    };
+    Ok((summaries, next_before))
+}
+
+pub async fn display_history(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    display_url: String,
+    query: WebhookQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let clock = Clock::new();
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html(db::UNAVAILABLE.to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    };
+    let query_start = clock.start();
+    let found = find_tag_id(&conn, &display_url)
+        .and_then(|tag_id_val| load_webhook_page(&conn, tag_id_val, &query));
+    timing!(
+        "display.display_history.webhooks_query",
+        clock.delta(query_start, clock.end())
+    );
+    let (history, next_before) = match found {
+        Ok(page) => page,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html("No such tag".to_string()),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+        Err(err) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html(err.to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    };
+    counter!(
+        "display.display_history.webhook_count",
+        history.len().try_into().unwrap()
+    );
+    let payload = HistoryPayload {
+        url_suffix: display_url,
+        webhooks: history,
+        method: query.method,
+        is_first_page: query.before.is_none(),
+        next_before,
+    };
+    let html = templater.hb.render("history", &payload);
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        StatusCode::OK,
+    ))
+}
+
+#[derive(Serialize)]
+struct LivePayload {
+    url_suffix: String,
+    webhooks: Vec<WebhookSummary>,
+}
+
+// The page itself is just the newest few webhooks, the browser keeps it current from /stream
+pub async fn display_live(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    display_url: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html(db::UNAVAILABLE.to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    };
+    let query = WebhookQuery {
+        limit: Some(LIVE_BACKLOG),
+        ..Default::default()
+    };
+    let found = find_tag_id(&conn, &display_url)
+        .and_then(|tag_id_val| load_webhook_page(&conn, tag_id_val, &query));
+    let (html, status) = match found {
+        Ok((recent, _)) => (
+            templater.hb.render(
+                "live",
+                &LivePayload {
+                    url_suffix: display_url,
+                    webhooks: recent,
+                },
+            ),
+            StatusCode::OK,
+        ),
+        Err(diesel::result::Error::NotFound) => {
+            (Ok("No such tag".to_string()), StatusCode::NOT_FOUND)
+        }
+        Err(err) => (Ok(err.to_string()), StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    ))
+}
+
+pub(crate) fn find_tag_id(
+    conn: &PgConnection,
+    display_url: &str,
+) -> Result<i32, diesel::result::Error> {
+    tags.filter(url_suffix.eq(display_url))
+        .select(tags::tag_id)
+        .first(conn)
+}
+
+// The id of the webhook recorded just before this one in its tag
+fn previous_webhook_id(
+    conn: &PgConnection,
+    webhook: &Webhook,
+) -> Result<Option<i32>, diesel::result::Error> {
+    match webhook.tag_id {
+        Some(tag_id_val) => webhooks
+            .filter(webhooks::tag_id.eq(tag_id_val))
+            .filter(id.lt(webhook.id))
+            .order_by(id.desc())
+            .select(id)
+            .first::<i32>(conn)
+            .optional(),
+        None => Ok(None),
+    }
+}
+
+// Hands back the body exactly as it was received, with the Content-Type it was sent with.
+// Always as a download, since a sender's text/html would otherwise run on our origin
+pub async fn raw_body(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    webhook_id: i32,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(db::UNAVAILABLE.as_bytes().to_vec()))
+        }
+    };
+    let found = webhooks.find(webhook_id).first::<Webhook>(&conn);
+    let response = match found {
+        Ok(webhook) => Response::builder()
+            .header(
+                CONTENT_TYPE,
+                webhook.content_type().unwrap_or("application/octet-stream"),
+            )
+            .header(
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"webhook-{}\"", webhook.id),
+            )
+            .header(X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .body(webhook.body),
+        Err(diesel::result::Error::NotFound) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Vec::new()),
+        Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Vec::new()),
+    };
+    Ok(response)
+}
+
+// Offset, hex bytes and printable ascii, 16 bytes per line like hexdump -C
+pub(crate) fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x}  ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, "{:02x} ", byte);
+                }
+                None => out.push_str("   "),
+            }
+            if i == 7 {
+                out.push(' ');
+            }
+        }
+        out.push_str(" |");
+        out.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hex_dump, DisplayPayload};
+    use crate::model::tests::webhook;
+    use crate::replay::ReplayFormView;
+    use crate::templating::Templater;
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"\x1f\x8bhello, binary world!");
+        let expected =
+            "00000000  1f 8b 68 65 6c 6c 6f 2c  20 62 69 6e 61 72 79 20  |..hello, binary |\n\
+                        00000010  77 6f 72 6c 64 21                                 |world!|\n";
+        assert_eq!(dump, expected);
+    }
+
+    #[test]
+    fn test_render_replay_form() {
+        let templater = Templater::new();
+        let recorded = webhook("POST", &[], b"{}");
+        let render = |form: ReplayFormView| {
+            templater
+                .hb
+                .render(
+                    "display",
+                    &DisplayPayload::new(&recorded, Some("shop")).with_replay_form(form),
+                )
+                .expect("Display page should render")
+        };
+        let fresh = render(ReplayFormView::default());
+        assert!(fresh.contains(r#"name="strip_hop_by_hop" checked>"#));
+        assert!(!fresh.contains("notification is-danger"));
+
+        let failed = render(ReplayFormView {
+            url: "ftp://example.com/\"><b>".to_string(),
+            host: "bad host".to_string(),
+            strip_hop_by_hop: false,
+            error: Some("ftp://example.com/ needs to be an absolute URL".to_string()),
+        });
+        assert!(failed.contains(
+            r#"<div class="notification is-danger">ftp://example.com/ needs to be an absolute URL</div>"#
+        ));
+        assert!(failed.contains(r#"value="ftp://example.com/&quot;&gt;&lt;b&gt;""#));
+        assert!(failed.contains(r#"value="bad host""#));
+        assert!(failed.contains(r#"name="strip_hop_by_hop">"#));
+    }
+
+    #[test]
+    fn test_render_body_kinds() {
+        let templater = Templater::new();
+        let render = |body: &[u8]| {
+            let recorded = webhook("POST", &[], body);
+            templater
+                .hb
+                .render("display", &DisplayPayload::new(&recorded, Some("shop")))
+                .expect("Display page should render")
+        };
+        for text in [&b""[..], b"hello"] {
+            let page = render(text);
+            assert!(!page.contains(">Hex</p>"));
+            assert!(!page.contains("The body is binary"));
+        }
+        let page = render(b"\x1f\x8b\xff");
+        assert!(page.contains(">Hex</p>"));
+        assert!(page.contains("The body is binary"));
+    }
+}
@@ -0,0 +1,408 @@
+use super::config::AppConfig;
+use super::db;
+use super::display::find_tag_id;
+use super::forward;
+use super::model::{HeaderPair, Webhook};
+use super::schema::webhooks;
+use super::tagmgr;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use metrics::counter;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::fmt::Write;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::http::{Response, StatusCode, Uri};
+
+// Where an exported request is aimed when no ?url= is given
+const DEFAULT_TARGET: &str = "http://localhost:8080";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Curl,
+    Httpie,
+    Http,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        match name {
+            "curl" => Some(ExportFormat::Curl),
+            "httpie" => Some(ExportFormat::Httpie),
+            "http" => Some(ExportFormat::Http),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ExportQuery {
+    pub url: Option<String>,
+}
+
+// The URL the request goes to, with the path and query carried over like a forward
+#[derive(Debug)]
+pub(crate) struct Target {
+    url: String,
+    authority: String,
+    path_and_query: String,
+}
+
+pub(crate) fn parse_target(base: &str, webhook: &Webhook) -> Result<Target, String> {
+    let url = forward::target_url(base, &webhook.path_tail, webhook.query_string.as_deref());
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|_| format!("{} is not a valid URL", base))?;
+    match (uri.scheme_str(), uri.authority()) {
+        (Some("http"), Some(authority)) | (Some("https"), Some(authority)) => Ok(Target {
+            // Credentials in the URL don't belong in Host
+            authority: authority
+                .as_str()
+                .rsplit('@')
+                .next()
+                .unwrap_or("")
+                .to_string(),
+            path_and_query: uri
+                .path_and_query()
+                .map(|path| path.as_str())
+                .filter(|path| path.starts_with('/'))
+                .unwrap_or("/")
+                .to_string(),
+            url,
+        }),
+        _ => Err(format!(
+            "{} needs to be an absolute http:// or https:// URL",
+            base
+        )),
+    }
+}
+
+// POSIX single quotes keep everything literal, a quote itself has to step outside them
+pub(crate) fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+// Text without NULs can go on the command line, anything else is read from the data file
+fn inline_body(webhook: &Webhook) -> Option<&str> {
+    webhook.body_text().filter(|text| !text.contains('\0'))
+}
+
+fn data_file(webhook: &Webhook) -> String {
+    format!("webhook-{}", webhook.id)
+}
+
+// One argument per line so long header lists stay readable
+fn join_lines(parts: &[String]) -> String {
+    let mut command = parts.join(" \\\n    ");
+    command.push('\n');
+    command
+}
+
+// body_url is where the data file can be downloaded from, only used for binary bodies
+pub(crate) fn curl_command(webhook: &Webhook, target: &Target, body_url: &str) -> String {
+    let headers = forward::strip_hop_by_hop(&webhook.headers);
+    let has_body = !webhook.body.is_empty();
+    let inline = inline_body(webhook);
+    let mut out = String::new();
+    if has_body && inline.is_none() {
+        let _ = writeln!(
+            out,
+            "curl -sS -o {} {}",
+            shell_quote(&data_file(webhook)),
+            shell_quote(body_url)
+        );
+    }
+    // -g stops curl treating [] and {} in the URL as globs
+    let mut first = "curl -g".to_string();
+    match (webhook.method.as_str(), has_body) {
+        ("HEAD", _) => first.push_str(" --head"),
+        // What curl picks by itself
+        ("GET", false) | ("POST", true) => {}
+        (other, _) => {
+            let _ = write!(first, " -X {}", shell_quote(other));
+        }
+    }
+    let _ = write!(first, " {}", shell_quote(&target.url));
+    let mut parts = vec![first];
+    for pair in headers.iter() {
+        // A trailing ; is how curl sends a header with an empty value
+        let header = if pair.value.is_empty() {
+            format!("{};", pair.name)
+        } else {
+            format!("{}: {}", pair.name, pair.value)
+        };
+        parts.push(format!("-H {}", shell_quote(&header)));
+    }
+    if has_body {
+        // Otherwise curl claims the body is a form
+        if headers.get("content-type").is_none() {
+            parts.push("-H 'Content-Type:'".to_string());
+        }
+        match inline {
+            Some(text) => parts.push(format!("--data-raw {}", shell_quote(text))),
+            None => parts.push(format!(
+                "--data-binary {}",
+                shell_quote(&format!("@{}", data_file(webhook)))
+            )),
+        }
+    }
+    out.push_str(&join_lines(&parts));
+    out
+}
+
+// Header items are name:value, name; for an empty value, and a leading = has to be escaped
+// so HTTPie doesn't read name:= as a raw JSON field
+fn httpie_header(pair: &HeaderPair) -> String {
+    if pair.value.is_empty() {
+        format!("{};", pair.name)
+    } else if pair.value.starts_with('=') {
+        format!("{}:\\{}", pair.name, pair.value)
+    } else {
+        format!("{}:{}", pair.name, pair.value)
+    }
+}
+
+pub(crate) fn httpie_command(webhook: &Webhook, target: &Target, body_url: &str) -> String {
+    let headers = forward::strip_hop_by_hop(&webhook.headers);
+    let from_file = !webhook.body.is_empty() && inline_body(webhook).is_none();
+    let mut out = String::new();
+    if from_file {
+        let _ = writeln!(
+            out,
+            "http --ignore-stdin GET {} > {}",
+            shell_quote(body_url),
+            shell_quote(&data_file(webhook))
+        );
+    }
+    let mut first = "http".to_string();
+    if !from_file {
+        first.push_str(" --ignore-stdin");
+    }
+    if let Some(text) = inline_body(webhook).filter(|text| !text.is_empty()) {
+        let _ = write!(first, " --raw {}", shell_quote(text));
+    }
+    let _ = write!(
+        first,
+        " {} {}",
+        shell_quote(&webhook.method),
+        shell_quote(&target.url)
+    );
+    let mut parts = vec![first];
+    for pair in headers.iter() {
+        parts.push(shell_quote(&httpie_header(pair)));
+    }
+    if from_file {
+        parts.push(format!("< {}", shell_quote(&data_file(webhook))));
+    }
+    out.push_str(&join_lines(&parts));
+    out
+}
+
+// The request as it would go over the wire to the target, Host and Content-Length worked out again
+pub(crate) fn raw_request(webhook: &Webhook, target: &Target) -> Vec<u8> {
+    let headers = forward::strip_hop_by_hop(&webhook.headers);
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nhost: {}\r\n",
+        webhook.method, target.path_and_query, target.authority
+    );
+    for pair in headers.iter() {
+        let _ = write!(head, "{}: {}\r\n", pair.name, pair.value);
+    }
+    if !webhook.body.is_empty() {
+        let _ = write!(head, "content-length: {}\r\n", webhook.body.len());
+    }
+    head.push_str("\r\n");
+    let mut raw = head.into_bytes();
+    raw.extend_from_slice(&webhook.body);
+    raw
+}
+
+fn text_reply(status: StatusCode, message: &str) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(message.as_bytes().to_vec())
+        .unwrap()
+}
+
+// GET /display/:string/:id/export/:format, text to paste into a terminal
+pub async fn export_webhook(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: AppConfig,
+    host: Option<String>,
+    display_url: String,
+    webhook_id: i32,
+    format: String,
+    query: ExportQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let format = match ExportFormat::parse(&format) {
+        Some(format) => format,
+        None => return Ok(text_reply(StatusCode::NOT_FOUND, "No such export format")),
+    };
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => return Ok(text_reply(StatusCode::SERVICE_UNAVAILABLE, db::UNAVAILABLE)),
+    };
+    let found = find_tag_id(&conn, &display_url).and_then(|tag_id_val| {
+        webhooks::table
+            .filter(webhooks::tag_id.eq(tag_id_val))
+            .filter(webhooks::id.eq(webhook_id))
+            .first::<Webhook>(&conn)
+    });
+    let webhook = match found {
+        Ok(webhook) => webhook,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(text_reply(StatusCode::NOT_FOUND, "No such webhook"))
+        }
+        Err(err) => {
+            return Ok(text_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &err.to_string(),
+            ))
+        }
+    };
+    let base = query
+        .url
+        .as_deref()
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .unwrap_or(DEFAULT_TARGET);
+    let target = match parse_target(base, &webhook) {
+        Ok(target) => target,
+        Err(message) => return Ok(text_reply(StatusCode::BAD_REQUEST, &message)),
+    };
+    let body_url = format!(
+        "{}/raw/{}",
+        tagmgr::public_base(&config, host.as_deref()),
+        webhook.id
+    );
+    counter!("export.export_webhook.exports", 1);
+    let response = match format {
+        ExportFormat::Curl => {
+            text_reply(StatusCode::OK, &curl_command(&webhook, &target, &body_url))
+        }
+        ExportFormat::Httpie => text_reply(
+            StatusCode::OK,
+            &httpie_command(&webhook, &target, &body_url),
+        ),
+        // Binary requests can't be shown as text, so they're offered as a download
+        ExportFormat::Http if inline_body(&webhook).is_none() && !webhook.body.is_empty() => {
+            Response::builder()
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"webhook-{}.http\"", webhook.id),
+                )
+                .body(raw_request(&webhook, &target))
+                .unwrap()
+        }
+        ExportFormat::Http => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(raw_request(&webhook, &target))
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{curl_command, httpie_command, parse_target, raw_request, shell_quote};
+    use crate::model::Webhook;
+
+    fn webhook(method: &str, headers: &[(&str, &str)], body: &[u8]) -> Webhook {
+        Webhook {
+            id: 7,
+            query_string: Some("a=1&b=[2]".to_string()),
+            path_tail: "push".to_string(),
+            ..crate::model::tests::webhook(method, headers, body)
+        }
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("application/json"), "application/json");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+        assert_eq!(shell_quote("line\nbreak"), "'line\nbreak'");
+    }
+
+    #[test]
+    fn test_curl_command() {
+        let text = webhook(
+            "POST",
+            &[
+                ("host", "recorder"),
+                ("content-type", "application/json"),
+                ("x-note", "it's"),
+            ],
+            b"{\"a\": \"b\"}",
+        );
+        let target = parse_target("http://localhost:8080/in", &text).unwrap();
+        assert_eq!(
+            curl_command(&text, &target, "http://recorder/raw/7"),
+            "curl -g 'http://localhost:8080/in/push?a=1&b=[2]' \\\n    \
+             -H 'content-type: application/json' \\\n    \
+             -H 'x-note: it'\\''s' \\\n    \
+             --data-raw '{\"a\": \"b\"}'\n"
+        );
+        let binary = webhook("PUT", &[], b"\x1f\x8b\x00");
+        assert_eq!(
+            curl_command(&binary, &target, "http://recorder/raw/7"),
+            "curl -sS -o webhook-7 http://recorder/raw/7\n\
+             curl -g -X PUT 'http://localhost:8080/in/push?a=1&b=[2]' \\\n    \
+             -H 'Content-Type:' \\\n    \
+             --data-binary @webhook-7\n"
+        );
+    }
+
+    #[test]
+    fn test_httpie_command() {
+        let text = webhook(
+            "DELETE",
+            &[("x-empty", ""), ("x-eq", "=1"), ("x-event", "push")],
+            b"",
+        );
+        let target = parse_target("http://localhost:8080", &text).unwrap();
+        assert_eq!(
+            httpie_command(&text, &target, "http://recorder/raw/7"),
+            "http --ignore-stdin DELETE 'http://localhost:8080/push?a=1&b=[2]' \\\n    \
+             'x-empty;' \\\n    \
+             'x-eq:\\=1' \\\n    \
+             x-event:push\n"
+        );
+        let binary = webhook("POST", &[], b"\xff");
+        assert_eq!(
+            httpie_command(&binary, &target, "http://recorder/raw/7"),
+            "http --ignore-stdin GET http://recorder/raw/7 > webhook-7\n\
+             http POST 'http://localhost:8080/push?a=1&b=[2]' \\\n    \
+             < webhook-7\n"
+        );
+    }
+
+    #[test]
+    fn test_raw_request() {
+        let text = webhook(
+            "POST",
+            &[("host", "recorder"), ("content-length", "99"), ("x-a", "1")],
+            b"hi",
+        );
+        let target = parse_target("http://user:pw@upstream:81/base", &text).unwrap();
+        assert_eq!(
+            String::from_utf8(raw_request(&text, &target)).unwrap(),
+            "POST /base/push?a=1&b=[2] HTTP/1.1\r\nhost: upstream:81\r\nx-a: 1\r\n\
+             content-length: 2\r\n\r\nhi"
+        );
+        assert!(parse_target("ftp://upstream", &text).is_err());
+        assert!(parse_target("/relative", &text).is_err());
+    }
+}
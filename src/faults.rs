@@ -0,0 +1,167 @@
+use super::model::{Tag, TagChanges};
+use super::schema::tags::dsl::*;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use log::warn;
+use metrics::counter;
+use std::time::Duration;
+
+// Long enough to trip any sender's timeout, short enough not to tie up the server indefinitely
+pub(crate) const MAX_DELAY_MS: i32 = 120_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fault {
+    FailNext,
+    FailureRate,
+}
+
+impl Fault {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Fault::FailNext => "fail_next",
+            Fault::FailureRate => "failure_rate",
+        }
+    }
+}
+
+// How a tag wants this particular request mishandled
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct FaultPlan {
+    pub delay: Duration,
+    pub failure: Option<Fault>,
+}
+
+pub(crate) fn plan_faults(conn: &PgConnection, tag: &Tag) -> FaultPlan {
+    let delay = Duration::from_millis(pick_delay(
+        tag.delay_min_ms,
+        tag.delay_max_ms,
+        random_unit(),
+    ));
+    // fail_next is consumed even when the failure rate would have failed the request anyway
+    let failure = if tag.fail_next > 0 && take_fail_next(conn, tag.tag_id) {
+        Some(Fault::FailNext)
+    } else if random_unit() < tag.failure_rate {
+        Some(Fault::FailureRate)
+    } else {
+        None
+    };
+    if let Some(fault) = failure {
+        counter!("faults.plan_faults.injected", 1, "fault" => fault.as_str());
+    }
+    FaultPlan { delay, failure }
+}
+
+// Decremented in the db so concurrent requests, or replicas, can't both take the last one
+fn take_fail_next(conn: &PgConnection, tag_id_val: i32) -> bool {
+    let taken = diesel::update(tags.filter(tag_id.eq(tag_id_val)).filter(fail_next.gt(0)))
+        .set(fail_next.eq(fail_next - 1))
+        .execute(conn);
+    match taken {
+        Ok(rows) => rows > 0,
+        Err(err) => {
+            warn!(
+                "Unable to decrement fail_next for tag {}: {}",
+                tag_id_val, err
+            );
+            false
+        }
+    }
+}
+
+// Only checks the fault settings actually being changed
+pub(crate) fn validate_fault_changes(changes: &TagChanges) -> Result<(), String> {
+    for delay in changes
+        .delay_min_ms
+        .iter()
+        .chain(changes.delay_max_ms.iter())
+    {
+        if !(0..=MAX_DELAY_MS).contains(delay) {
+            return Err(format!("Delays must be between 0 and {}ms", MAX_DELAY_MS));
+        }
+    }
+    if let Some(rate) = changes.failure_rate {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err("failure_rate must be between 0 and 1".to_string());
+        }
+    }
+    if let Some(status) = changes.failure_status {
+        if !(500..=599).contains(&status) {
+            return Err("failure_status must be a 5xx status".to_string());
+        }
+    }
+    if changes.fail_next.map_or(false, |count| count < 0) {
+        return Err("fail_next can't be negative".to_string());
+    }
+    Ok(())
+}
+
+// A max below the min means a fixed delay of min
+pub(crate) fn pick_delay(min_ms: i32, max_ms: i32, roll: f64) -> u64 {
+    let min_ms = min_ms.clamp(0, MAX_DELAY_MS) as u64;
+    let max_ms = max_ms.clamp(0, MAX_DELAY_MS) as u64;
+    if max_ms <= min_ms {
+        return min_ms;
+    }
+    let spread = (roll * (max_ms - min_ms + 1) as f64) as u64;
+    min_ms + spread.min(max_ms - min_ms)
+}
+
+// Uniform in [0, 1), from the same openssl rng the tag suffixes use
+pub(crate) fn random_unit() -> f64 {
+    let mut buf = [0u8; 8];
+    openssl::rand::rand_bytes(&mut buf).expect("Must be able to get random bytes");
+    (u64::from_le_bytes(buf) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick_delay, random_unit, validate_fault_changes, MAX_DELAY_MS};
+    use crate::model::TagChanges;
+
+    #[test]
+    fn test_pick_delay() {
+        assert_eq!(pick_delay(0, 0, 0.7), 0);
+        assert_eq!(pick_delay(250, 0, 0.7), 250);
+        assert_eq!(pick_delay(250, 250, 0.7), 250);
+        assert_eq!(pick_delay(100, 200, 0.0), 100);
+        assert_eq!(pick_delay(100, 200, 0.5), 150);
+        assert_eq!(pick_delay(100, 200, 0.999_999), 200);
+        assert_eq!(pick_delay(0, i32::MAX, 0.999_999), MAX_DELAY_MS as u64);
+        let roll = random_unit();
+        assert!((0.0..1.0).contains(&roll));
+    }
+
+    #[test]
+    fn test_validate_fault_changes() {
+        let ok = TagChanges {
+            delay_min_ms: Some(100),
+            delay_max_ms: Some(2000),
+            failure_rate: Some(0.25),
+            failure_status: Some(502),
+            fail_next: Some(3),
+            ..Default::default()
+        };
+        assert!(validate_fault_changes(&ok).is_ok());
+        assert!(validate_fault_changes(&TagChanges::default()).is_ok());
+        for bad in [
+            TagChanges {
+                delay_max_ms: Some(MAX_DELAY_MS + 1),
+                ..Default::default()
+            },
+            TagChanges {
+                failure_rate: Some(1.5),
+                ..Default::default()
+            },
+            TagChanges {
+                failure_status: Some(404),
+                ..Default::default()
+            },
+            TagChanges {
+                fail_next: Some(-1),
+                ..Default::default()
+            },
+        ] {
+            assert!(validate_fault_changes(&bad).is_err());
+        }
+    }
+}
@@ -1,22 +1,70 @@
+use super::config::AppConfig;
+use super::delivery::{self, DeliveryQueue};
+use super::forward::Forwarder;
+use super::live::LiveFeed;
+use super::model::ResponseSpec;
+use super::record::IncomingHook;
+use super::retention::Pruner;
 use super::templating::Templater;
-use super::{display, healthcheck, record, tagmgr};
+use super::{
+    api, destinations, diff, display, export, har, healthcheck, live, record, replay, search,
+    tagmgr, ws,
+};
 use diesel::{pg::PgConnection, r2d2::ConnectionManager};
 use log::debug;
+use std::net::{IpAddr, SocketAddr};
 
+use warp::path::Tail;
 use warp::Filter;
 
 pub fn gen_filters(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
     templater: Templater,
+    config: AppConfig,
+    feed: LiveFeed,
+    forwarder: Forwarder,
+    queue: DeliveryQueue,
+    pruner: Pruner,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
     debug!("Beginning filter intialization");
     gen_display(pool.clone(), templater.clone())
-        .or(gen_record_tagged(pool.clone()))
-        .or(gen_healthcheck(pool.clone(), templater.clone()))
+        .or(gen_api(
+            pool.clone(),
+            &config,
+            forwarder.clone(),
+            queue.clone(),
+        ))
+        .or(gen_record_tagged(
+            pool.clone(),
+            feed.clone(),
+            queue.clone(),
+            templater.clone(),
+            &config,
+        ))
+        .or(gen_healthcheck(pool.clone(), templater.clone(), pruner.clone()))
         .or(gen_get_tags(pool.clone(), templater.clone()))
-        .or(gen_post_tag(pool.clone()))
+        .or(gen_post_tag(pool.clone(), templater.clone(), &config))
+        .or(gen_toggle_tag(pool.clone()))
+        .or(gen_delete_tag(pool.clone(), templater.clone()))
+        .or(gen_tag_response(pool.clone(), templater.clone()))
+        .or(gen_tag_faults(pool.clone(), templater.clone()))
+        .or(gen_tag_signature(pool.clone(), templater.clone()))
+        .or(gen_tag_retention(pool.clone(), templater.clone(), pruner))
+        .or(gen_tag_forward(pool.clone(), templater.clone()))
+        .or(gen_deliveries(pool.clone(), templater.clone(), queue))
+        .or(gen_search(pool.clone(), templater.clone()))
         .or(gen_show_new_tag(templater.clone()))
-        .or(gen_post_new_tag(pool.clone()))
+        .or(gen_post_new_tag(pool.clone(), templater.clone(), &config))
+        .or(gen_raw_body(pool.clone()))
+        .or(gen_display_history(pool.clone(), templater.clone()))
+        .or(gen_display_diff(pool.clone(), templater.clone()))
+        .or(gen_display_har(pool.clone(), &config))
+        .or(gen_display_live(pool.clone(), templater.clone()))
+        .or(gen_display_stream(pool.clone(), feed.clone()))
+        .or(gen_websocket(pool.clone(), feed))
+        .or(gen_display_replay(pool.clone(), templater.clone(), forwarder))
+        .or(gen_display_export(pool.clone(), &config))
+        .or(gen_display_by_id(pool.clone(), templater.clone()))
         .or(gen_display_by_tag(pool, templater))
 }
 
@@ -48,6 +96,182 @@ fn gen_display_by_tag(
         })
 }
 
+// GET /search
+fn gen_search(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing search filter");
+    warp::path!("search")
+        .and(warp::get())
+        .and(warp::query::<search::SearchQuery>())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|query, pool, templater| search::search_page(pool, templater, query))
+}
+
+// GET /display/:string/history
+fn gen_display_history(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_history filter");
+    warp::path!("display" / String / "history")
+        .and(warp::get())
+        .and(warp::query::<display::WebhookQuery>())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|display_url, query, pool, templater| {
+            display::display_history(pool, templater, display_url, query)
+        })
+}
+
+// GET /display/:string/diff
+fn gen_display_diff(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_diff filter");
+    warp::path!("display" / String / "diff")
+        .and(warp::get())
+        .and(warp::query::<diff::DiffQuery>())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|display_url, query, pool, templater| {
+            diff::display_diff(pool, templater, display_url, query)
+        })
+}
+
+// GET and POST /display/:string/har
+fn gen_display_har(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: &AppConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_har filter");
+    let download = warp::path!("display" / String / "har")
+        .and(warp::get())
+        .and(warp::query::<har::HarQuery>())
+        .and(with_db(pool.clone()))
+        .and(with_config(config.clone()))
+        .and(warp::header::optional::<String>("host"))
+        .and_then(|display_url, query, pool, config, host| {
+            har::download_har(pool, config, host, display_url, query)
+        });
+    let import = warp::path!("display" / String / "har")
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(har::MAX_HAR_SIZE))
+        .and(with_db(pool))
+        .and_then(|display_url, form, pool| har::import_har_form(pool, display_url, form));
+    download.or(import)
+}
+
+// GET /display/:string/live
+fn gen_display_live(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_live filter");
+    warp::path!("display" / String / "live")
+        .and(warp::get())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|display_url, pool, templater| {
+            display::display_live(pool, templater, display_url)
+        })
+}
+
+// GET /display/:string/stream
+fn gen_display_stream(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    feed: LiveFeed,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_stream filter");
+    warp::path!("display" / String / "stream")
+        .and(warp::get())
+        .and(with_db(pool))
+        .and(with_feed(feed))
+        .and_then(|display_url, pool, feed| live::stream_tag(pool, feed, display_url))
+}
+
+// GET /ws
+fn gen_websocket(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    feed: LiveFeed,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing websocket filter");
+    warp::path!("ws")
+        .and(warp::ws())
+        .and(with_db(pool))
+        .and(with_feed(feed))
+        .map(|socket: warp::ws::Ws, pool, feed| {
+            socket.on_upgrade(move |websocket| ws::client_connected(websocket, pool, feed))
+        })
+}
+
+// GET /display/:string/:id
+fn gen_display_by_id(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_by_id filter");
+    warp::path!("display" / String / i32)
+        .and(warp::get())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|display_url, webhook_id, pool, templater| {
+            display::display_by_id(pool, templater, display_url, webhook_id)
+        })
+}
+
+// POST /display/:string/:id/replay
+fn gen_display_replay(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    forwarder: Forwarder,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_replay filter");
+    warp::path!("display" / String / i32 / "replay")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and(with_forwarder(forwarder))
+        .and_then(|display_url, webhook_id, body, pool, templater, forwarder| {
+            replay::replay_form(pool, templater, forwarder, display_url, webhook_id, body)
+        })
+}
+
+// GET /display/:string/:id/export/:format
+fn gen_display_export(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: &AppConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing display_export filter");
+    warp::path!("display" / String / i32 / "export" / String)
+        .and(warp::get())
+        .and(warp::query::<export::ExportQuery>())
+        .and(with_db(pool))
+        .and(with_config(config.clone()))
+        .and(warp::header::optional::<String>("host"))
+        .and_then(
+            |display_url, webhook_id, format, query, pool, config, host| {
+                export::export_webhook(pool, config, host, display_url, webhook_id, format, query)
+            },
+        )
+}
+
+// GET /raw/:id
+fn gen_raw_body(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing raw_body filter");
+    warp::path!("raw" / i32)
+        .and(warp::get())
+        .and(with_db(pool))
+        .and_then(|webhook_id, pool| display::raw_body(pool, webhook_id))
+}
+
 // GET /tags/
 fn gen_get_tags(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
@@ -58,9 +282,201 @@ fn gen_get_tags(
         .and(warp::get())
         .and(with_db(pool))
         .and(with_templater(templater))
+        .and(warp::query::<tagmgr::TagListQuery>())
         .and_then(tagmgr::display_tagmgr)
 }
 
+// POST /tags/:string/activate and /tags/:string/deactivate
+fn gen_toggle_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing toggle_tag filter");
+    let activate = warp::path!("tags" / String / "activate").map(|suffix| (suffix, true));
+    let deactivate = warp::path!("tags" / String / "deactivate").map(|suffix| (suffix, false));
+    activate
+        .or(deactivate)
+        .unify()
+        .and(warp::post())
+        .and(with_db(pool))
+        .and_then(|(suffix, active), pool| tagmgr::toggle_tag(pool, suffix, active))
+}
+
+// POST /tags/:string/delete
+fn gen_delete_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing delete_tag filter");
+    warp::path!("tags" / String / "delete")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|suffix, body, pool, templater| {
+            tagmgr::delete_tag_form(pool, templater, suffix, body)
+        })
+}
+
+// GET and POST /tags/:string/response
+fn gen_tag_response(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing tag_response filter");
+    let show = warp::path!("tags" / String / "response")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater.clone()))
+        .and_then(|suffix, pool, templater| tagmgr::show_response_form(pool, templater, suffix));
+    let save = warp::path!("tags" / String / "response")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|suffix, body, pool, templater| {
+            tagmgr::save_response_form(pool, templater, suffix, body)
+        });
+    show.or(save)
+}
+
+// GET and POST /tags/:string/faults
+fn gen_tag_faults(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing tag_faults filter");
+    let show = warp::path!("tags" / String / "faults")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater.clone()))
+        .and_then(|suffix, pool, templater| tagmgr::show_faults_form(pool, templater, suffix));
+    let save = warp::path!("tags" / String / "faults")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|suffix, body, pool, templater| {
+            tagmgr::save_faults_form(pool, templater, suffix, body)
+        });
+    show.or(save)
+}
+
+// GET and POST /tags/:string/signature
+fn gen_tag_signature(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing tag_signature filter");
+    let show = warp::path!("tags" / String / "signature")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater.clone()))
+        .and_then(|suffix, pool, templater| tagmgr::show_signature_form(pool, templater, suffix));
+    let save = warp::path!("tags" / String / "signature")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and_then(|suffix, body, pool, templater| {
+            tagmgr::save_signature_form(pool, templater, suffix, body)
+        });
+    show.or(save)
+}
+
+// GET and POST /tags/:string/retention
+fn gen_tag_retention(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    pruner: Pruner,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing tag_retention filter");
+    let show = warp::path!("tags" / String / "retention")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater.clone()))
+        .and(with_pruner(pruner.clone()))
+        .and_then(|suffix, pool, templater, pruner| {
+            tagmgr::show_retention_form(pool, templater, pruner, suffix)
+        });
+    let save = warp::path!("tags" / String / "retention")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool))
+        .and(with_templater(templater))
+        .and(with_pruner(pruner))
+        .and_then(|suffix, body, pool, templater, pruner| {
+            tagmgr::save_retention_form(pool, templater, pruner, suffix, body)
+        });
+    show.or(save)
+}
+
+// GET and POST /tags/:string/forward, /tags/:string/forward/:id and POST /tags/:string/forward/:id/delete
+fn gen_tag_forward(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing tag_forward filter");
+    let list = warp::path!("tags" / String / "forward").map(|suffix| (suffix, None));
+    let one = warp::path!("tags" / String / "forward" / i32).map(|suffix, id| (suffix, Some(id)));
+    let show = list
+        .or(one)
+        .unify()
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater.clone()))
+        .and_then(|(suffix, destination_id), pool, templater| {
+            destinations::show_destinations(pool, templater, suffix, destination_id)
+        });
+    let save = list
+        .or(one)
+        .unify()
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::form())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater))
+        .and_then(|(suffix, destination_id), body, pool, templater| {
+            destinations::save_destination_form(pool, templater, suffix, destination_id, body)
+        });
+    let delete = warp::path!("tags" / String / "forward" / i32 / "delete")
+        .and(warp::post())
+        .and(with_db(pool))
+        .and_then(|suffix, destination_id, pool| {
+            destinations::delete_destination_form(pool, suffix, destination_id)
+        });
+    show.or(save).or(delete)
+}
+
+// GET /deliveries, POST /deliveries/:id/redrive and /deliveries/redrive
+fn gen_deliveries(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    queue: DeliveryQueue,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing deliveries filter");
+    let show = warp::path!("deliveries")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_templater(templater))
+        .and(warp::query::<delivery::DeliveryQuery>())
+        .and_then(delivery::show_deliveries);
+    let redrive_one = warp::path!("deliveries" / i32 / "redrive").map(Some);
+    let redrive_all = warp::path!("deliveries" / "redrive").map(|| None);
+    let redrive = redrive_one
+        .or(redrive_all)
+        .unify()
+        .and(warp::post())
+        .and(with_db(pool))
+        .and(with_queue(queue))
+        .and_then(|delivery_id, pool, queue| delivery::redrive_form(pool, queue, delivery_id));
+    show.or(redrive)
+}
+
 // GET /new_tag
 fn gen_show_new_tag(templater: Templater) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
     debug!("Initializing show_new_tag filter");
@@ -73,12 +489,17 @@ fn gen_show_new_tag(templater: Templater) -> impl Filter<Extract = impl warp::Re
 // POST /new_tag
 fn gen_post_new_tag(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    config: &AppConfig,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
     debug!("Initializing post_new_tag filter");
     warp::path!("new_tag")
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 32))
         .and(with_db(pool))
+        .and(with_templater(templater))
+        .and(with_config(config.clone()))
+        .and(warp::header::optional::<String>("host"))
         .and(warp::body::form())
         .and_then(tagmgr::new_tag)
 }
@@ -86,28 +507,57 @@ fn gen_post_new_tag(
 // POST /tags
 fn gen_post_tag(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    config: &AppConfig,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
     debug!("Initializing post_tag filter");
     warp::path!("tags")
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 32))
         .and(with_db(pool))
+        .and(with_templater(templater))
+        .and(with_config(config.clone()))
+        .and(warp::header::optional::<String>("host"))
         .and(warp::body::form())
         .and_then(tagmgr::new_tag)
 }
 
-// POST /record/:string
+// ANY /record/:string/...
 fn gen_record_tagged(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    feed: LiveFeed,
+    queue: DeliveryQueue,
+    templater: Templater,
+    config: &AppConfig,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
     debug!("Initializing record filter");
-    warp::path!("record" / String)
-        .and(warp::post())
-        .and(warp::body::bytes())
+    warp::path("record")
+        .and(warp::path::param::<String>())
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(with_query_string())
+        .and(with_remote_addr(config.trust_forwarded_for))
         .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .map(
+            |url_suffix, tail: Tail, method, query_string, remote_addr, headers, body| {
+                IncomingHook {
+                    url_suffix,
+                    path_tail: tail.as_str().to_string(),
+                    method,
+                    query_string,
+                    remote_addr,
+                    headers,
+                    body,
+                }
+            },
+        )
         .and(with_db(pool))
-        .and_then(|url_suffix, body, headers, pool| {
-            record::record_webhook(pool, body, headers, url_suffix)
+        .and(with_feed(feed))
+        .and(with_queue(queue))
+        .and(with_templater(templater))
+        .and_then(|hook, pool, feed, queue, templater| {
+            record::record_webhook(pool, feed, queue, templater, hook)
         })
 }
 
@@ -115,15 +565,274 @@ fn gen_record_tagged(
 fn gen_healthcheck(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
     templater: Templater,
+    pruner: Pruner,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
     debug!("Initializing healthcheck filter");
     warp::path!("healthcheck")
         .and(warp::get())
         .and(with_db(pool))
         .and(with_templater(templater))
+        .and(with_pruner(pruner))
         .and_then(healthcheck::healthcheck)
 }
 
+// /api/v1/...
+// Anything under the prefix which doesn't match gets a json error rather than falling through
+fn gen_api(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: &AppConfig,
+    forwarder: Forwarder,
+    queue: DeliveryQueue,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    debug!("Initializing api filters");
+    warp::path("api").and(warp::path("v1")).and(
+        gen_api_list_tags(pool.clone())
+            .or(gen_api_create_tag(pool.clone(), config))
+            .or(gen_api_get_tag(pool.clone()))
+            .or(gen_api_update_tag(pool.clone()))
+            .or(gen_api_delete_tag(pool.clone()))
+            .or(gen_api_tag_response(pool.clone()))
+            .or(gen_api_destinations(pool.clone()))
+            .or(gen_api_toggle_tag(pool.clone()))
+            .or(gen_api_list_webhooks(pool.clone()))
+            .or(gen_api_get_webhook(pool.clone()))
+            .or(gen_api_search(pool.clone()))
+            .or(gen_api_webhook_deliveries(pool.clone()))
+            .or(gen_api_har(pool.clone(), config))
+            .or(gen_api_replays(pool.clone(), forwarder))
+            .or(gen_api_deliveries(pool, queue))
+            .recover(api::handle_rejection),
+    )
+}
+
+// GET /api/v1/tags
+fn gen_api_list_tags(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("tags")
+        .and(warp::get())
+        .and(with_db(pool))
+        .and(warp::query::<tagmgr::TagListQuery>())
+        .and_then(api::list_tags)
+}
+
+// POST /api/v1/tags
+fn gen_api_create_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: &AppConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("tags")
+        .and(warp::post())
+        .and(with_db(pool))
+        .and(with_config(config.clone()))
+        .and(warp::header::optional::<String>("host"))
+        .and(with_json_body())
+        .and_then(api::create_tag)
+}
+
+// GET /api/v1/tags/:string
+fn gen_api_get_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("tags" / String)
+        .and(warp::get())
+        .and(with_db(pool))
+        .and_then(|suffix, pool| api::get_tag(pool, suffix))
+}
+
+// PATCH /api/v1/tags/:string
+fn gen_api_update_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("tags" / String)
+        .and(warp::patch().or(warp::put()).unify())
+        .and(with_db(pool))
+        .and(with_json_body())
+        .and_then(|suffix, pool, changes| api::update_tag(pool, suffix, changes))
+}
+
+// DELETE /api/v1/tags/:string
+fn gen_api_delete_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("tags" / String)
+        .and(warp::delete())
+        .and(with_db(pool))
+        .and_then(|suffix, pool| api::delete_tag(pool, suffix))
+}
+
+// PUT and DELETE /api/v1/tags/:string/response
+fn gen_api_tag_response(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let set = warp::path!("tags" / String / "response")
+        .and(warp::put())
+        .and(with_db(pool.clone()))
+        .and(with_json_body())
+        .and_then(|suffix, pool, spec| api::set_tag_response(pool, suffix, spec));
+    let clear = warp::path!("tags" / String / "response")
+        .and(warp::delete())
+        .and(with_db(pool))
+        .and_then(|suffix, pool| api::set_tag_response(pool, suffix, ResponseSpec::default()));
+    set.or(clear)
+}
+
+// POST /api/v1/tags/:string/activate and /api/v1/tags/:string/deactivate
+fn gen_api_toggle_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let activate = warp::path!("tags" / String / "activate").map(|suffix| (suffix, true));
+    let deactivate = warp::path!("tags" / String / "deactivate").map(|suffix| (suffix, false));
+    activate
+        .or(deactivate)
+        .unify()
+        .and(warp::post())
+        .and(with_db(pool))
+        .and_then(|(suffix, active), pool| api::set_tag_active(pool, suffix, active))
+}
+
+// GET /api/v1/tags/:string/webhooks
+fn gen_api_list_webhooks(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("tags" / String / "webhooks")
+        .and(warp::get())
+        .and(warp::query::<display::WebhookQuery>())
+        .and(with_db(pool))
+        .and_then(|suffix, query, pool| api::list_webhooks(pool, suffix, query))
+}
+
+// GET /api/v1/webhooks/:id
+fn gen_api_get_webhook(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("webhooks" / i32)
+        .and(warp::get())
+        .and(with_db(pool))
+        .and_then(|webhook_id, pool| api::get_webhook(pool, webhook_id))
+}
+
+// GET /api/v1/search
+fn gen_api_search(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("search")
+        .and(warp::get())
+        .and(warp::query::<search::SearchQuery>())
+        .and(with_db(pool))
+        .and_then(|query, pool| api::search_webhooks(pool, query))
+}
+
+// GET and POST /api/v1/tags/:string/har
+fn gen_api_har(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: &AppConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let export = warp::path!("tags" / String / "har")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(with_config(config.clone()))
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::query::<har::HarQuery>())
+        .and_then(|suffix, pool, config, host, query| {
+            api::export_har(pool, config, host, suffix, query)
+        });
+    let import = warp::path!("tags" / String / "har")
+        .and(warp::post())
+        .and(with_db(pool))
+        .and(warp::body::content_length_limit(har::MAX_HAR_SIZE))
+        .and(warp::body::json())
+        .and_then(|suffix, pool, body| api::import_har(pool, suffix, body));
+    export.or(import)
+}
+
+// GET /api/v1/webhooks/:id/deliveries
+fn gen_api_webhook_deliveries(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    warp::path!("webhooks" / i32 / "deliveries")
+        .and(warp::get())
+        .and(with_db(pool))
+        .and_then(|webhook_id, pool| api::list_webhook_deliveries(pool, webhook_id))
+}
+
+// POST /api/v1/webhooks/:id/replay and GET /api/v1/webhooks/:id/replays
+fn gen_api_replays(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    forwarder: Forwarder,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let send = warp::path!("webhooks" / i32 / "replay")
+        .and(warp::post())
+        .and(with_db(pool.clone()))
+        .and(with_forwarder(forwarder))
+        .and(with_json_body())
+        .and_then(|webhook_id, pool, forwarder, request| {
+            api::replay_webhook(pool, forwarder, webhook_id, request)
+        });
+    let list = warp::path!("webhooks" / i32 / "replays")
+        .and(warp::get())
+        .and(with_db(pool))
+        .and_then(|webhook_id, pool| api::list_replays(pool, webhook_id));
+    send.or(list)
+}
+
+// GET and POST /api/v1/tags/:string/destinations, PUT and DELETE /api/v1/tags/:string/destinations/:id
+fn gen_api_destinations(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let list = warp::path!("tags" / String / "destinations")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(|suffix, pool| api::list_destinations(pool, suffix));
+    let create = warp::path!("tags" / String / "destinations")
+        .and(warp::post())
+        .and(with_db(pool.clone()))
+        .and(with_json_body())
+        .and_then(|suffix, pool, spec| api::create_destination(pool, suffix, spec));
+    let replace = warp::path!("tags" / String / "destinations" / i32)
+        .and(warp::put())
+        .and(with_db(pool.clone()))
+        .and(with_json_body())
+        .and_then(|suffix, destination_id, pool, spec| {
+            api::replace_destination(pool, suffix, destination_id, spec)
+        });
+    let delete = warp::path!("tags" / String / "destinations" / i32)
+        .and(warp::delete())
+        .and(with_db(pool))
+        .and_then(|suffix, destination_id, pool| {
+            api::delete_destination(pool, suffix, destination_id)
+        });
+    list.or(create).or(replace).or(delete)
+}
+
+// GET /api/v1/deliveries, POST /api/v1/deliveries/:id/redrive and /api/v1/deliveries/redrive
+fn gen_api_deliveries(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    queue: DeliveryQueue,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let list = warp::path!("deliveries")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and(warp::query::<delivery::DeliveryQuery>())
+        .and_then(api::list_deliveries);
+    let redrive_one = warp::path!("deliveries" / i32 / "redrive")
+        .and(warp::post())
+        .and(with_db(pool.clone()))
+        .and(with_queue(queue.clone()))
+        .and_then(|delivery_id, pool, queue| api::redrive_delivery(pool, queue, delivery_id));
+    let redrive_all = warp::path!("deliveries" / "redrive")
+        .and(warp::post())
+        .and(with_db(pool))
+        .and(with_queue(queue))
+        .and_then(api::redrive_dead);
+    list.or(redrive_one).or(redrive_all)
+}
+
+fn with_json_body<T: serde::de::DeserializeOwned + Send>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 32).and(warp::body::json())
+}
+
 fn with_db(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
 ) -> impl Filter<
@@ -134,6 +843,71 @@ fn with_db(
     warp::any().map(move || pool.clone())
 }
 
+// warp::query::raw rejects requests without a query string, we want None instead
+fn with_query_string(
+) -> impl Filter<Extract = (Option<String>,), Error = std::convert::Infallible> + Clone + 'static {
+    warp::query::raw()
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify()
+}
+
+// The leftmost X-Forwarded-For entry is the original client, only believe it when configured to
+fn with_remote_addr(
+    trust_forwarded_for: bool,
+) -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone + 'static {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |peer: Option<SocketAddr>, forwarded_for: Option<String>| {
+                let forwarded = forwarded_for
+                    .filter(|_| trust_forwarded_for)
+                    .and_then(|val| forwarded_client(&val));
+                forwarded.or_else(|| peer.map(|addr| addr.to_string()))
+            },
+        )
+}
+
+// Anything that isn't an address, with or without a port, is ignored rather than stored
+fn forwarded_client(forwarded_for: &str) -> Option<String> {
+    let first = forwarded_for.split(',').next()?.trim();
+    first
+        .parse::<IpAddr>()
+        .map(|ip| ip.to_string())
+        .or_else(|_| first.parse::<SocketAddr>().map(|addr| addr.to_string()))
+        .ok()
+}
+
+fn with_config(
+    config: AppConfig,
+) -> impl Filter<Extract = (AppConfig,), Error = std::convert::Infallible> + Clone + 'static {
+    warp::any().map(move || config.clone())
+}
+
+fn with_feed(
+    feed: LiveFeed,
+) -> impl Filter<Extract = (LiveFeed,), Error = std::convert::Infallible> + Clone + 'static {
+    warp::any().map(move || feed.clone())
+}
+
+fn with_forwarder(
+    forwarder: Forwarder,
+) -> impl Filter<Extract = (Forwarder,), Error = std::convert::Infallible> + Clone + 'static {
+    warp::any().map(move || forwarder.clone())
+}
+
+fn with_queue(
+    queue: DeliveryQueue,
+) -> impl Filter<Extract = (DeliveryQueue,), Error = std::convert::Infallible> + Clone + 'static {
+    warp::any().map(move || queue.clone())
+}
+
+fn with_pruner(
+    pruner: Pruner,
+) -> impl Filter<Extract = (Pruner,), Error = std::convert::Infallible> + Clone + 'static {
+    warp::any().map(move || pruner.clone())
+}
+
 fn with_templater(
     templater: Templater,
 ) -> impl Filter<Extract = (Templater,), Error = std::convert::Infallible> + Clone + 'static {
@@ -148,3 +922,113 @@ fn with_templater(
 //     let filter = gen_display(pool, templater);
 //     let value = warp::test::request().path("/display").filter();
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::{forwarded_client, gen_api};
+    use crate::config::AppConfig;
+    use crate::delivery::DeliveryQueue;
+    use crate::forward::Forwarder;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_forwarded_client() {
+        assert_eq!(
+            forwarded_client("203.0.113.7, 10.0.0.1").as_deref(),
+            Some("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_client(" 2001:db8::1 ").as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(
+            forwarded_client("[2001:db8::1]:443").as_deref(),
+            Some("[2001:db8::1]:443")
+        );
+        assert_eq!(forwarded_client(""), None);
+        assert_eq!(forwarded_client(&"x".repeat(100)), None);
+    }
+
+    // Everything here is turned away before the handlers ask the pool for a connection
+    #[tokio::test]
+    async fn test_api_rejects_without_db() {
+        let config = AppConfig::new(
+            &mut vec![("DATABASE_URL".to_string(), "pgsql://test".to_string())].into_iter(),
+        );
+        let pool = Pool::builder().build_unchecked(ConnectionManager::new("pgsql://test"));
+        let api = gen_api(
+            pool,
+            &config,
+            Forwarder::new(&config),
+            DeliveryQueue::new(&config),
+        );
+        let send = |method: &str, path: &str, body: Value| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .json(&body)
+                .reply(&api)
+        };
+        let read = |response: warp::http::Response<bytes::Bytes>| {
+            let error: Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(error["status"], response.status().as_u16());
+            (
+                response.status().as_u16(),
+                error["message"].as_str().unwrap().to_string(),
+            )
+        };
+
+        let (status, message) = read(
+            send(
+                "POST",
+                "/api/v1/tags",
+                json!({"url_suffix": "a".repeat(33)}),
+            )
+            .await,
+        );
+        assert_eq!(status, 400);
+        assert_eq!(message, "A tag has to be between 1 and 32 characters");
+        let (status, _) = read(send("POST", "/api/v1/tags", json!({"url_suffix": "a/b"})).await);
+        assert_eq!(status, 400);
+        let (status, _) = read(
+            send(
+                "POST",
+                "/api/v1/tags",
+                json!({"url_suffix": "shop", "expires_in": "soon"}),
+            )
+            .await,
+        );
+        assert_eq!(status, 400);
+
+        let (status, message) = read(
+            send(
+                "PATCH",
+                "/api/v1/tags/shop",
+                json!({"url_suffix": "has space"}),
+            )
+            .await,
+        );
+        assert_eq!(status, 400);
+        assert_eq!(message, "A tag can only use letters, digits, '-' and '_'");
+        let (status, _) =
+            read(send("PATCH", "/api/v1/tags/shop", json!({"failure_rate": 1.5})).await);
+        assert_eq!(status, 400);
+
+        // Rejections come back in the same json shape as handler errors
+        let (status, _) = read(send("PATCH", "/api/v1/tags/shop", json!({"active": "yes"})).await);
+        assert_eq!(status, 400);
+        let (status, message) = read(send("GET", "/api/v1/nowhere", json!({})).await);
+        assert_eq!((status, message.as_str()), (404, "No such api endpoint"));
+        let (status, _) = read(send("DELETE", "/api/v1/search", json!({})).await);
+        assert_eq!(status, 405);
+        let (status, _) = read(
+            warp::test::request()
+                .method("GET")
+                .path("/api/v1/tags/shop/webhooks?limit=lots")
+                .reply(&api)
+                .await,
+        );
+        assert_eq!(status, 400);
+    }
+}
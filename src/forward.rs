@@ -0,0 +1,294 @@
+use super::config::AppConfig;
+use super::model::{HeaderList, HeaderPair};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_tls::HttpsConnector;
+use metrics::timing;
+use quanta::Clock;
+use std::time::Duration;
+
+// Upstream bodies past this are cut short, we're keeping a record not mirroring a download
+const MAX_UPSTREAM_BODY: usize = 1024 * 1024;
+
+// Headers about the connection we received on rather than the request, RFC 7230 section 6.1,
+// plus the ones hyper works out again for the new connection
+pub(crate) const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+// What came back from a forward target
+#[derive(Debug)]
+pub(crate) struct UpstreamResponse {
+    pub status: u16,
+    pub headers: HeaderList,
+    pub body: Vec<u8>,
+    pub latency: Duration,
+}
+
+#[derive(Clone)]
+pub struct Forwarder {
+    client: Client<HttpsConnector<HttpConnector>>,
+    timeout: Duration,
+}
+
+impl Forwarder {
+    pub fn new(config: &AppConfig) -> Forwarder {
+        Forwarder {
+            // Certificates are checked against the system's roots through openssl
+            client: Client::builder().build(HttpsConnector::new()),
+            timeout: config.forward_timeout,
+        }
+    }
+
+    // Sends one request, the timeout covers reading the body as well as the status line
+    pub(crate) async fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &HeaderList,
+        body: &[u8],
+    ) -> Result<UpstreamResponse, String> {
+        let clock = Clock::new();
+        let start = clock.start();
+        let request = build_request(method, url, headers, body)?;
+        let exchange = async {
+            let response = self
+                .client
+                .request(request)
+                .await
+                .map_err(|err| err.to_string())?;
+            let status = response.status().as_u16();
+            let headers = HeaderList::from(response.headers());
+            let mut upstream_body = response.into_body();
+            let mut collected = Vec::new();
+            while let Some(chunk) = upstream_body.data().await {
+                let chunk = chunk.map_err(|err| err.to_string())?;
+                collected.extend_from_slice(&chunk);
+                if collected.len() > MAX_UPSTREAM_BODY {
+                    collected.truncate(MAX_UPSTREAM_BODY);
+                    break;
+                }
+            }
+            Ok::<_, String>((status, headers, collected))
+        };
+        let (status, headers, collected) = tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| format!("No response within {}s", self.timeout.as_secs()))??;
+        let latency = clock.delta(start, clock.end());
+        timing!("forward.send.latency", latency);
+        Ok(UpstreamResponse {
+            status,
+            headers,
+            body: collected,
+            latency,
+        })
+    }
+}
+
+fn build_request(
+    method: &str,
+    url: &str,
+    headers: &HeaderList,
+    body: &[u8],
+) -> Result<Request<Body>, String> {
+    let method = Method::from_bytes(method.as_bytes()).map_err(|err| err.to_string())?;
+    let mut builder = Request::builder().method(method).uri(url);
+    for pair in headers.iter() {
+        builder = builder.header(pair.name.as_str(), pair.value.as_str());
+    }
+    builder
+        .body(Body::from(body.to_vec()))
+        .map_err(|err| err.to_string())
+}
+
+pub(crate) fn strip_hop_by_hop(headers: &HeaderList) -> HeaderList {
+    // Connection can name further headers that only applied to the hop we received on
+    let named: Vec<String> = headers
+        .iter()
+        .filter(|pair| pair.name.eq_ignore_ascii_case("connection"))
+        .flat_map(|pair| pair.value.split(','))
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    HeaderList(
+        headers
+            .iter()
+            .filter(|pair| {
+                let name = pair.name.to_lowercase();
+                !HOP_BY_HOP.contains(&name.as_str()) && !named.contains(&name)
+            })
+            .cloned()
+            .collect::<Vec<HeaderPair>>(),
+    )
+}
+
+// Anything after the tag in the recorded path, and the query, carry on to the target
+pub(crate) fn target_url(forward_url: &str, path_tail: &str, query_string: Option<&str>) -> String {
+    let mut target = forward_url.to_string();
+    if !path_tail.is_empty() {
+        if !target.ends_with('/') {
+            target.push('/');
+        }
+        target.push_str(path_tail.trim_start_matches('/'));
+    }
+    if let Some(query) = query_string.filter(|query| !query.is_empty()) {
+        target.push(if target.contains('?') { '&' } else { '?' });
+        target.push_str(query);
+    }
+    target
+}
+
+pub(crate) fn validate_forward_url(url: &str) -> Result<(), String> {
+    if url.is_empty() {
+        return Ok(());
+    }
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|_| format!("{} is not a valid URL", url))?;
+    match (uri.scheme_str(), uri.host()) {
+        (Some("http"), Some(_)) | (Some("https"), Some(_)) => Ok(()),
+        _ => Err(format!(
+            "{} needs to be an absolute http:// or https:// URL",
+            url
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_hop_by_hop, target_url, validate_forward_url, Forwarder};
+    use crate::config::AppConfig;
+    use crate::model::{HeaderList, HeaderPair};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    // What the stand-in upstream saw: method, path and query, headers and body
+    type Seen = Arc<Mutex<Option<(String, String, Vec<(String, String)>, Vec<u8>)>>>;
+
+    #[test]
+    fn test_target_url() {
+        assert_eq!(
+            target_url("http://upstream/hooks", "", None),
+            "http://upstream/hooks"
+        );
+        assert_eq!(
+            target_url("http://upstream/hooks", "github/push", Some("a=1")),
+            "http://upstream/hooks/github/push?a=1"
+        );
+        assert_eq!(
+            target_url("http://upstream/hooks/?key=k", "", Some("a=1")),
+            "http://upstream/hooks/?key=k&a=1"
+        );
+        assert!(validate_forward_url("").is_ok());
+        assert!(validate_forward_url("http://127.0.0.1:8080/in").is_ok());
+        assert!(validate_forward_url("https://example.com/in").is_ok());
+        assert!(validate_forward_url("ftp://example.com/in").is_err());
+        assert!(validate_forward_url("/relative").is_err());
+    }
+
+    #[test]
+    fn test_strip_hop_by_hop() {
+        let pair = |name: &str, value: &str| HeaderPair {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        let headers = HeaderList(vec![
+            pair("host", "recorder.example.com"),
+            pair("connection", "keep-alive, x-hop"),
+            pair("x-hop", "gone"),
+            pair("content-length", "2"),
+            pair("content-type", "application/json"),
+            pair("x-github-event", "push"),
+        ]);
+        let stripped = strip_hop_by_hop(&headers);
+        let kept: Vec<&str> = stripped.iter().map(|pair| pair.name.as_str()).collect();
+        assert_eq!(kept, vec!["content-type", "x-github-event"]);
+    }
+
+    #[tokio::test]
+    async fn test_send_to_local_upstream() {
+        let seen: Seen = Arc::new(Mutex::new(None));
+        let recorder = seen.clone();
+        let make_service = make_service_fn(move |_| {
+            let recorder = recorder.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let recorder = recorder.clone();
+                    async move {
+                        let method = request.method().to_string();
+                        let target = request.uri().to_string();
+                        let headers = request
+                            .headers()
+                            .iter()
+                            .map(|(name, value)| {
+                                (name.to_string(), value.to_str().unwrap().to_string())
+                            })
+                            .collect();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        *recorder.lock().unwrap() = Some((method, target, headers, body.to_vec()));
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(202)
+                                .header("x-upstream", "stand-in")
+                                .body(Body::from("queued"))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let upstream = format!("http://{}/hooks", server.local_addr());
+        tokio::spawn(server);
+
+        let forwarder = Forwarder::new(&AppConfig::new(
+            &mut vec![("DATABASE_URL".to_string(), "pgsql://test".to_string())].into_iter(),
+        ));
+        let pair = |name: &str, value: &str| HeaderPair {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        let recorded = HeaderList(vec![
+            pair("host", "recorder.example.com"),
+            pair("connection", "close"),
+            pair("content-type", "application/json"),
+            pair("x-github-event", "push"),
+        ]);
+        let url = target_url(&upstream, "github/push", Some("a=1&b=2"));
+        let response = forwarder
+            .send("PUT", &url, &strip_hop_by_hop(&recorded), br#"{"ok":true}"#)
+            .await
+            .expect("Stand-in upstream should answer");
+        assert_eq!(response.status, 202);
+        assert_eq!(response.headers.get("x-upstream"), Some("stand-in"));
+        assert_eq!(response.body, b"queued");
+
+        let (method, target, headers, body) = seen.lock().unwrap().take().unwrap();
+        assert_eq!(method, "PUT");
+        assert_eq!(target, "/hooks/github/push?a=1&b=2");
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(found, _)| found == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(header("content-type"), Some("application/json"));
+        assert_eq!(header("x-github-event"), Some("push"));
+        // hyper sets these for the new connection rather than passing ours on
+        assert_ne!(header("host"), Some("recorder.example.com"));
+        assert_ne!(header("connection"), Some("close"));
+        assert_eq!(body, br#"{"ok":true}"#);
+    }
+}
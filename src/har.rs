@@ -0,0 +1,724 @@
+use super::config::AppConfig;
+use super::db;
+use super::display::find_tag_id;
+use super::forward;
+use super::model::{BodyEncoding, HeaderList, HeaderPair, NewWebhook, RecordedResponse, Webhook};
+use super::schema::webhooks;
+use super::tagmgr;
+use bytes::Buf;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use futures::TryStreamExt;
+use metrics::counter;
+use openssl::base64;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::convert::TryInto;
+use warp::http::header::{HeaderName, HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE, LOCATION};
+use warp::http::{Method, Response, StatusCode, Uri};
+use warp::multipart::FormData;
+
+// HAR files are one big JSON document, this is as much as we'll take in or hand out
+pub const MAX_HAR_SIZE: u64 = 32 * 1024 * 1024;
+pub const HAR_MAX_ENTRIES: i64 = 5000;
+
+// Just the parts of HAR 1.2 we produce or read, anything else in an imported file is ignored
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HarLog {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub creator: HarCreator,
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    #[serde(default)]
+    pub started_date_time: String,
+    #[serde(default)]
+    pub time: f64,
+    pub request: HarRequest,
+    #[serde(default)]
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: serde_json::Map<String, Value>,
+    #[serde(default)]
+    pub timings: HarTimings,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Value>,
+    #[serde(default)]
+    pub headers: Vec<HeaderPair>,
+    #[serde(default)]
+    pub query_string: Vec<HeaderPair>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    #[serde(default)]
+    pub headers_size: i64,
+    #[serde(default)]
+    pub body_size: i64,
+}
+
+// HAR 1.2 has no way to mark a request body as base64, _encoding is the usual extension
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(
+        rename = "_encoding",
+        alias = "encoding",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    #[serde(default)]
+    pub status: i32,
+    #[serde(default)]
+    pub status_text: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Value>,
+    #[serde(default)]
+    pub headers: Vec<HeaderPair>,
+    #[serde(default)]
+    pub content: HarContent,
+    #[serde(rename = "redirectURL", default)]
+    pub redirect_url: String,
+    #[serde(default)]
+    pub headers_size: i64,
+    #[serde(default)]
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    #[serde(default)]
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HarTimings {
+    #[serde(default)]
+    pub send: f64,
+    #[serde(default)]
+    pub wait: f64,
+    #[serde(default)]
+    pub receive: f64,
+}
+
+// Text stays text, anything else goes out as base64
+fn encode_body(body: &[u8]) -> (String, Option<String>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (base64::encode_block(body), Some("base64".to_string())),
+    }
+}
+
+fn decode_body(text: &str, encoding: Option<&str>) -> Result<Vec<u8>, String> {
+    match encoding {
+        Some("base64") => {
+            base64::decode_block(text).map_err(|_| "Body isn't valid base64".to_string())
+        }
+        _ => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+fn har_time(time: NaiveDateTime) -> String {
+    DateTime::<Utc>::from_utc(time, Utc).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// record_url is where the webhook's tag records, the path tail and query go on the end
+pub(crate) fn webhook_entry(webhook: &Webhook, record_url: &str) -> HarEntry {
+    let query_string = webhook
+        .query_string
+        .as_deref()
+        .and_then(|query| serde_urlencoded::from_str::<Vec<(String, String)>>(query).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| HeaderPair { name, value })
+        .collect();
+    let post_data = if webhook.body.is_empty() {
+        None
+    } else {
+        let (text, encoding) = encode_body(&webhook.body);
+        Some(HarPostData {
+            mime_type: webhook.content_type().unwrap_or_default().to_string(),
+            text,
+            encoding,
+        })
+    };
+    // Webhooks from before responses were kept get status 0, which is what HAR uses for no response
+    let status = webhook.response_status.unwrap_or(0);
+    let response_headers = webhook.response_headers.clone().unwrap_or_default();
+    let response_body = webhook.response_body.as_deref().unwrap_or_default();
+    let (content_text, content_encoding) = encode_body(response_body);
+    let delay = f64::from(webhook.response_delay_ms);
+    HarEntry {
+        started_date_time: har_time(webhook.upload_time),
+        time: delay,
+        request: HarRequest {
+            method: webhook.method.clone(),
+            url: forward::target_url(
+                record_url,
+                &webhook.path_tail,
+                webhook.query_string.as_deref(),
+            ),
+            http_version: "HTTP/1.1".to_string(),
+            cookies: Vec::new(),
+            headers: webhook.headers.0.clone(),
+            query_string,
+            post_data,
+            headers_size: -1,
+            body_size: webhook.body.len() as i64,
+        },
+        response: HarResponse {
+            status,
+            status_text: StatusCode::from_u16(status.try_into().unwrap_or(0))
+                .ok()
+                .and_then(|code| code.canonical_reason())
+                .unwrap_or_default()
+                .to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            cookies: Vec::new(),
+            content: HarContent {
+                size: response_body.len() as i64,
+                mime_type: response_headers
+                    .get("content-type")
+                    .unwrap_or_default()
+                    .to_string(),
+                text: Some(content_text),
+                encoding: content_encoding,
+            },
+            headers: response_headers.0,
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: response_body.len() as i64,
+        },
+        cache: serde_json::Map::new(),
+        timings: HarTimings {
+            send: 0.0,
+            wait: delay,
+            receive: 0.0,
+        },
+    }
+}
+
+pub(crate) fn build_har(found: &[Webhook], record_url: &str) -> Har {
+    Har {
+        log: HarLog {
+            version: "1.2".to_string(),
+            creator: HarCreator {
+                name: "hook-recorder".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            entries: found
+                .iter()
+                .map(|webhook| webhook_entry(webhook, record_url))
+                .collect(),
+        },
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct HarQuery {
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+// datetime-local inputs leave the seconds off, and a blank one means no bound
+pub(crate) fn parse_bound(value: Option<&str>) -> Result<Option<NaiveDateTime>, String> {
+    match value.map(str::trim).filter(|value| !value.is_empty()) {
+        None => Ok(None),
+        Some(value) => NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+            .map(Some)
+            .map_err(|_| format!("{} is not a time like 2026-10-18T09:30:00", value)),
+    }
+}
+
+impl HarQuery {
+    pub(crate) fn bounds(&self) -> Result<(Option<NaiveDateTime>, Option<NaiveDateTime>), String> {
+        Ok((
+            parse_bound(self.since.as_deref())?,
+            parse_bound(self.until.as_deref())?,
+        ))
+    }
+}
+
+// Oldest first like a browser capture, keeping the newest HAR_MAX_ENTRIES when there are more
+pub(crate) fn load_entries(
+    conn: &PgConnection,
+    tag_id_val: i32,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+) -> Result<Vec<Webhook>, diesel::result::Error> {
+    let mut listing = webhooks::table
+        .filter(webhooks::tag_id.eq(tag_id_val))
+        .order_by(webhooks::id.desc())
+        .limit(HAR_MAX_ENTRIES)
+        .into_boxed();
+    if let Some(since) = since {
+        listing = listing.filter(webhooks::upload_time.ge(since));
+    }
+    if let Some(until) = until {
+        listing = listing.filter(webhooks::upload_time.lt(until));
+    }
+    let mut found = listing.load::<Webhook>(conn)?;
+    found.reverse();
+    Ok(found)
+}
+
+// An entry turned back into what record would have saved
+#[derive(Debug)]
+pub(crate) struct ImportedWebhook {
+    method: String,
+    headers: HeaderList,
+    body: Vec<u8>,
+    query_string: Option<String>,
+    path_tail: String,
+    upload_time: NaiveDateTime,
+    response: Option<ImportedResponse>,
+}
+
+#[derive(Debug)]
+struct ImportedResponse {
+    status: i32,
+    headers: HeaderList,
+    body: Vec<u8>,
+    delay_ms: i32,
+}
+
+fn import_headers(pairs: &[HeaderPair]) -> Result<HeaderList, String> {
+    // HTTP/2 captures list :authority and friends as headers
+    pairs
+        .iter()
+        .filter(|pair| !pair.name.starts_with(':'))
+        .map(|pair| {
+            // Only what record could have received, which also keeps NULs out of the jsonb column
+            HeaderName::from_bytes(pair.name.as_bytes())
+                .map_err(|_| format!("{} is not a valid header name", pair.name))?;
+            HeaderValue::from_bytes(pair.value.as_bytes())
+                .map_err(|_| format!("The {} header has an invalid value", pair.name))?;
+            Ok(HeaderPair {
+                name: pair.name.to_lowercase(),
+                value: pair.value.clone(),
+            })
+        })
+        .collect::<Result<_, String>>()
+        .map(HeaderList)
+}
+
+pub(crate) fn read_entry(entry: &HarEntry) -> Result<ImportedWebhook, String> {
+    let request = &entry.request;
+    // Any token goes, the method column is text so extension methods of any length fit
+    let method = request.method.to_uppercase();
+    Method::from_bytes(method.as_bytes())
+        .map_err(|_| format!("{} is not an HTTP method", request.method))?;
+    let uri = request
+        .url
+        .parse::<Uri>()
+        .map_err(|_| format!("{} is not a valid URL", request.url))?;
+    // Our own exports point at /record/:tag, only what comes after the tag is the tail
+    let path_tail = match uri.path().strip_prefix("/record/") {
+        Some(rest) => rest.split_once('/').map(|(_, tail)| tail).unwrap_or(""),
+        None => uri.path().trim_start_matches('/'),
+    };
+    let body = match &request.post_data {
+        Some(post_data) => decode_body(&post_data.text, post_data.encoding.as_deref())?,
+        None => Vec::new(),
+    };
+    let response = if entry.response.status > 0 {
+        let content = &entry.response.content;
+        entry
+            .response
+            .status
+            .try_into()
+            .ok()
+            .and_then(|status: u16| StatusCode::from_u16(status).ok())
+            .ok_or_else(|| format!("{} is not an HTTP status", entry.response.status))?;
+        Some(ImportedResponse {
+            status: entry.response.status,
+            headers: import_headers(&entry.response.headers)?,
+            body: decode_body(
+                content.text.as_deref().unwrap_or_default(),
+                content.encoding.as_deref(),
+            )?,
+            delay_ms: entry.timings.wait.max(0.0).min(f64::from(i32::MAX)) as i32,
+        })
+    } else {
+        None
+    };
+    Ok(ImportedWebhook {
+        method,
+        headers: import_headers(&request.headers)?,
+        body,
+        query_string: uri
+            .query()
+            .filter(|query| !query.is_empty())
+            .map(String::from),
+        path_tail: path_tail.to_string(),
+        upload_time: DateTime::parse_from_rfc3339(&entry.started_date_time)
+            .map(|time| time.naive_utc())
+            .unwrap_or_else(|_| Utc::now().naive_utc()),
+        response,
+    })
+}
+
+// Checks every entry before anything is written, so a bad file imports nothing
+pub(crate) fn read_entries(har: &Har) -> Result<Vec<ImportedWebhook>, String> {
+    har.log
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| read_entry(entry).map_err(|err| format!("Entry {}: {}", index, err)))
+        .collect()
+}
+
+// Imported webhooks keep their original time and aren't forwarded anywhere
+pub(crate) fn save_imported(
+    conn: &PgConnection,
+    tag_id_val: i32,
+    imported: &[ImportedWebhook],
+) -> Result<Vec<i32>, diesel::result::Error> {
+    conn.transaction(|| {
+        let mut ids = Vec::with_capacity(imported.len());
+        for webhook in imported {
+            let new_webhook = NewWebhook {
+                headers: &webhook.headers,
+                body: &webhook.body,
+                tag_id: tag_id_val,
+                method: &webhook.method,
+                query_string: webhook.query_string.as_deref(),
+                path_tail: &webhook.path_tail,
+                remote_addr: None,
+                body_encoding: BodyEncoding::detect(&webhook.body).as_str(),
+                signature_verdict: None,
+            };
+            let saved_id = diesel::insert_into(webhooks::table)
+                .values((webhooks::upload_time.eq(webhook.upload_time), &new_webhook))
+                .returning(webhooks::id)
+                .get_result::<i32>(conn)?;
+            if let Some(response) = &webhook.response {
+                diesel::update(webhooks::table.find(saved_id))
+                    .set(&RecordedResponse {
+                        response_status: response.status,
+                        response_headers: &response.headers,
+                        response_body: &response.body,
+                        response_delay_ms: response.delay_ms,
+                        response_fault: None,
+                    })
+                    .execute(conn)?;
+            }
+            ids.push(saved_id);
+        }
+        counter!("har.save_imported.webhooks", ids.len() as u64);
+        Ok(ids)
+    })
+}
+
+fn text_reply(status: StatusCode, message: String) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(message.into_bytes())
+        .unwrap()
+}
+
+// GET /display/:string/har, handed over as a file to open in browser tooling
+pub async fn download_har(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    config: AppConfig,
+    host: Option<String>,
+    display_url: String,
+    query: HarQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let (since, until) = match query.bounds() {
+        Ok(bounds) => bounds,
+        Err(message) => return Ok(text_reply(StatusCode::BAD_REQUEST, message)),
+    };
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(text_reply(
+                StatusCode::SERVICE_UNAVAILABLE,
+                db::UNAVAILABLE.to_string(),
+            ))
+        }
+    };
+    let found = find_tag_id(&conn, &display_url)
+        .and_then(|tag_id_val| load_entries(&conn, tag_id_val, since, until));
+    Ok(match found {
+        Ok(found) => {
+            counter!("har.download_har.entries", found.len() as u64);
+            let har = build_har(
+                &found,
+                &tagmgr::record_url(&config, host.as_deref(), &display_url),
+            );
+            Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .header(
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}.har\"", display_url),
+                )
+                .body(serde_json::to_vec(&har).unwrap_or_default())
+                .unwrap()
+        }
+        Err(diesel::result::Error::NotFound) => {
+            text_reply(StatusCode::NOT_FOUND, "No such tag".to_string())
+        }
+        Err(err) => text_reply(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    })
+}
+
+// The file input is the only part we care about
+async fn read_upload(form: FormData) -> Result<Vec<u8>, String> {
+    let parts: Vec<_> = form.try_collect().await.map_err(|err| err.to_string())?;
+    for mut part in parts {
+        if part.name() != "har" {
+            continue;
+        }
+        let mut contents = Vec::new();
+        while let Some(chunk) = part.data().await {
+            contents.extend_from_slice(chunk.map_err(|err| err.to_string())?.bytes());
+        }
+        return Ok(contents);
+    }
+    Err("Choose a HAR file to import".to_string())
+}
+
+// POST /display/:string/har, back to the history page to see what came in
+pub async fn import_har_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    display_url: String,
+    form: FormData,
+) -> Result<impl warp::Reply, Infallible> {
+    let imported = read_upload(form)
+        .await
+        .and_then(|contents| {
+            serde_json::from_slice::<Har>(&contents)
+                .map_err(|err| format!("Not a HAR file: {}", err))
+        })
+        .and_then(|har| read_entries(&har));
+    let imported = match imported {
+        Ok(imported) => imported,
+        Err(message) => return Ok(text_reply(StatusCode::BAD_REQUEST, message)),
+    };
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(text_reply(
+                StatusCode::SERVICE_UNAVAILABLE,
+                db::UNAVAILABLE.to_string(),
+            ))
+        }
+    };
+    let saved = find_tag_id(&conn, &display_url)
+        .and_then(|tag_id_val| save_imported(&conn, tag_id_val, &imported));
+    Ok(match saved {
+        Ok(_) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(LOCATION, format!("/display/{}/history", display_url))
+            .body(Vec::new())
+            .unwrap(),
+        Err(diesel::result::Error::NotFound) => {
+            text_reply(StatusCode::NOT_FOUND, "No such tag".to_string())
+        }
+        Err(err) => text_reply(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_har, parse_bound, read_entries, Har};
+    use crate::model::tests::webhook;
+    use crate::model::{HeaderList, HeaderPair, Webhook};
+
+    fn pair(name: &str, value: &str) -> HeaderPair {
+        HeaderPair {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_har_round_trip() {
+        let webhook = Webhook {
+            id: 3,
+            upload_time: chrono::NaiveDateTime::from_timestamp(1_600_000_000, 250_000_000),
+            query_string: Some("a=1&b=two%20words".to_string()),
+            path_tail: "github/push".to_string(),
+            response_status: Some(202),
+            response_headers: Some(HeaderList(vec![pair("content-type", "text/plain")])),
+            response_body: Some(b"ok".to_vec()),
+            response_delay_ms: 40,
+            ..webhook(
+                "PUT",
+                &[("content-type", "application/octet-stream")],
+                b"\x1f\x8b\xff",
+            )
+        };
+        let har = build_har(&[webhook], "http://hooks.example.com/record/t1");
+        let json = serde_json::to_value(&har).unwrap();
+        let entry = &json["log"]["entries"][0];
+        assert_eq!(json["log"]["version"], "1.2");
+        assert_eq!(entry["startedDateTime"], "2020-09-13T12:26:40.250Z");
+        assert_eq!(
+            entry["request"]["url"],
+            "http://hooks.example.com/record/t1/github/push?a=1&b=two%20words"
+        );
+        assert_eq!(entry["request"]["queryString"][1]["value"], "two words");
+        assert_eq!(entry["request"]["postData"]["_encoding"], "base64");
+        assert_eq!(entry["response"]["statusText"], "Accepted");
+        assert_eq!(entry["response"]["content"]["text"], "ok");
+        assert_eq!(entry["response"]["redirectURL"], "");
+
+        let parsed: Har = serde_json::from_value(json).unwrap();
+        let imported = read_entries(&parsed).unwrap();
+        let back = &imported[0];
+        assert_eq!(back.method, "PUT");
+        assert_eq!(back.path_tail, "github/push");
+        assert_eq!(back.query_string.as_deref(), Some("a=1&b=two%20words"));
+        assert_eq!(back.body, b"\x1f\x8b\xff");
+        assert_eq!(
+            back.upload_time,
+            chrono::NaiveDateTime::from_timestamp(1_600_000_000, 250_000_000)
+        );
+        let response = back.response.as_ref().unwrap();
+        assert_eq!(response.status, 202);
+        assert_eq!(response.body, b"ok");
+        assert_eq!(response.delay_ms, 40);
+    }
+
+    #[test]
+    fn test_read_foreign_entries() {
+        let har: Har = serde_json::from_str(
+            r#"{"log": {"entries": [{
+                "startedDateTime": "2026-10-18T09:30:00.000+02:00",
+                "request": {
+                    "method": "post",
+                    "url": "https://api.example.com/v1/events",
+                    "headers": [{"name": ":authority", "value": "api.example.com"},
+                                {"name": "Content-Type", "value": "application/json"}],
+                    "postData": {"mimeType": "application/json", "text": "{}"}
+                },
+                "response": {"status": 0}
+            }]}}"#,
+        )
+        .unwrap();
+        let imported = read_entries(&har).unwrap();
+        assert_eq!(imported[0].method, "POST");
+        assert_eq!(imported[0].path_tail, "v1/events");
+        assert_eq!(
+            imported[0].headers,
+            HeaderList(vec![pair("content-type", "application/json")])
+        );
+        assert_eq!(imported[0].body, b"{}");
+        assert_eq!(imported[0].upload_time.to_string(), "2026-10-18 07:30:00");
+        assert!(imported[0].response.is_none());
+
+        let bad: Har = serde_json::from_str(
+            r#"{"log": {"entries": [{"request": {"method": "GET", "url": "not a url"}}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_entries(&bad).unwrap_err(),
+            "Entry 0: not a url is not a valid URL"
+        );
+
+        let entries = |second: &str| -> Har {
+            serde_json::from_str(&format!(
+                r#"{{"log": {{"entries": [
+                    {{"request": {{"method": "VERSION-CONTROL-CHECKOUT", "url": "/"}}}},
+                    {}
+                ]}}}}"#,
+                second
+            ))
+            .unwrap()
+        };
+        let imported = read_entries(&entries(
+            r#"{"request": {"method": "GET", "url": "/"}, "response": {"status": 204}}"#,
+        ))
+        .unwrap();
+        assert_eq!(imported[0].method, "VERSION-CONTROL-CHECKOUT");
+        assert_eq!(
+            read_entries(&entries(
+                r#"{"request": {"method": "GET", "url": "/",
+                    "headers": [{"name": "x-bad", "value": "a\u0000b"}]}}"#
+            ))
+            .unwrap_err(),
+            "Entry 1: The x-bad header has an invalid value"
+        );
+        assert_eq!(
+            read_entries(&entries(
+                r#"{"request": {"method": "GET", "url": "/",
+                    "headers": [{"name": "bad header", "value": "x"}]}}"#
+            ))
+            .unwrap_err(),
+            "Entry 1: bad header is not a valid header name"
+        );
+        assert_eq!(
+            read_entries(&entries(
+                r#"{"request": {"method": "GET", "url": "/"}, "response": {"status": 42}}"#
+            ))
+            .unwrap_err(),
+            "Entry 1: 42 is not an HTTP status"
+        );
+    }
+
+    #[test]
+    fn test_parse_bound() {
+        assert_eq!(parse_bound(None), Ok(None));
+        assert_eq!(parse_bound(Some(" ")), Ok(None));
+        assert_eq!(
+            parse_bound(Some("2026-10-18T09:30"))
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "2026-10-18 09:30:00"
+        );
+        assert!(parse_bound(Some("2026-10-18T09:30:15.5"))
+            .unwrap()
+            .is_some());
+        assert!(parse_bound(Some("yesterday")).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 extern crate chrono;
 extern crate diesel;
 extern crate handlebars;
+use super::retention::{PruneStatus, Pruner};
 use super::templating::Templater;
 use diesel::pg::PgConnection;
 use diesel::r2d2::{self, ConnectionManager};
@@ -20,16 +21,28 @@ struct HealthcheckPayload {
     idle_timeout: Option<Duration>,
     max_lifetime: Option<Duration>,
     conn_timeout: Duration,
+    retention: RetentionPayload,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RetentionPayload {
+    max_age: String,
+    max_count: String,
+    expired_tags: String,
+    interval: Duration,
+    #[serde(skip_deserializing)]
+    status: PruneStatus,
 }
 
 pub async fn healthcheck(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
     templater: Templater,
+    pruner: Pruner,
 ) -> Result<impl warp::Reply, Infallible> {
     debug!("Healthcheck called");
     let clock = Clock::new();
     let check_start = clock.start();
-    let payload = _do_check_health(pool);
+    let payload = _do_check_health(pool, &pruner);
     let check_end = clock.end();
     timing!(
         "healthcheck.state_check.ms",
@@ -43,7 +56,10 @@ pub async fn healthcheck(
     ))
 }
 
-fn _do_check_health(pool: r2d2::Pool<ConnectionManager<PgConnection>>) -> HealthcheckPayload {
+fn _do_check_health(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    pruner: &Pruner,
+) -> HealthcheckPayload {
     let state = pool.state();
     let healthy = state.connections > 0 && state.idle_connections > 0;
     HealthcheckPayload {
@@ -54,5 +70,12 @@ fn _do_check_health(pool: r2d2::Pool<ConnectionManager<PgConnection>>) -> Health
         idle_timeout: pool.idle_timeout(),
         max_lifetime: pool.max_lifetime(),
         conn_timeout: pool.connection_timeout(),
+        retention: RetentionPayload {
+            max_age: pruner.policy.describe_age(),
+            max_count: pruner.policy.describe_count(),
+            expired_tags: pruner.describe_grace(),
+            interval: pruner.interval,
+            status: pruner.status(),
+        },
     }
 }
@@ -0,0 +1,64 @@
+use serde_json::Value;
+
+// Finds the value at a path into a json document, taking either a JSON pointer (/a/0/b)
+// or the dotted form people tend to type ($.a[0].b or a.0.b)
+pub(crate) fn lookup<'a>(doc: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.starts_with('/') || path.is_empty() {
+        return doc.pointer(path);
+    }
+    let trimmed = path.trim_start_matches('$').trim_start_matches('.');
+    let mut current = doc;
+    for segment in trimmed
+        .split(&['.', '[', ']'][..])
+        .filter(|segment| !segment.is_empty())
+    {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+// With nothing to compare against the path only has to exist, scalars also match their string form
+pub(crate) fn matches(doc: &Value, path: &str, expected: Option<&Value>) -> bool {
+    match (lookup(doc, path), expected) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(found), Some(Value::String(wanted))) if !found.is_string() => {
+            let rendered = found.to_string();
+            !found.is_object() && !found.is_array() && rendered == *wanted
+        }
+        (Some(found), Some(wanted)) => found == wanted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lookup, matches};
+    use serde_json::json;
+
+    #[test]
+    fn test_lookup_path_styles() {
+        let doc = json!({"data": {"items": [{"id": 7}, {"id": 8}]}, "type": "push"});
+        assert_eq!(lookup(&doc, "$.data.items[1].id"), Some(&json!(8)));
+        assert_eq!(lookup(&doc, "data.items.0.id"), Some(&json!(7)));
+        assert_eq!(lookup(&doc, "/data/items/0/id"), Some(&json!(7)));
+        assert_eq!(lookup(&doc, "type"), Some(&json!("push")));
+        assert_eq!(lookup(&doc, "$.data.missing"), None);
+        assert_eq!(lookup(&doc, "type.nested"), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let doc = json!({"action": "opened", "number": 42, "draft": false});
+        assert!(matches(&doc, "action", None));
+        assert!(matches(&doc, "action", Some(&json!("opened"))));
+        assert!(!matches(&doc, "action", Some(&json!("closed"))));
+        assert!(matches(&doc, "number", Some(&json!(42))));
+        assert!(matches(&doc, "number", Some(&json!("42"))));
+        assert!(matches(&doc, "draft", Some(&json!("false"))));
+        assert!(!matches(&doc, "missing", None));
+    }
+}
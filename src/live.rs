@@ -0,0 +1,274 @@
+use super::config::AppConfig;
+use super::db;
+use super::display::find_tag_id;
+use super::model::Webhook;
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::Text;
+use futures::{future, pin_mut, stream, StreamExt};
+use log::{debug, info, warn};
+use metrics::counter;
+use native_tls::TlsConnector;
+use postgres_native_tls::MakeTlsConnector;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::time::delay_for;
+use tokio_postgres::AsyncMessage;
+use warp::http::StatusCode;
+
+// Slow subscribers that fall further behind than this skip ahead rather than hold up recording
+const LIVE_CHANNEL_CAPACITY: usize = 256;
+const NOTIFY_CHANNEL: &str = "hook_recorder_webhooks";
+const LISTEN_RETRY_SECS: u64 = 5;
+
+// Just enough about a new webhook to draw a row for it, small enough to fit in a NOTIFY payload
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LiveHook {
+    pub id: i32,
+    pub tag_id: Option<i32>,
+    pub method: String,
+    pub upload_time: NaiveDateTime,
+    pub body_size: usize,
+    pub content_type: Option<String>,
+}
+
+impl From<&Webhook> for LiveHook {
+    fn from(webhook: &Webhook) -> Self {
+        LiveHook {
+            id: webhook.id,
+            tag_id: webhook.tag_id,
+            method: webhook.method.clone(),
+            upload_time: webhook.upload_time,
+            body_size: webhook.body.len(),
+            content_type: webhook.content_type().map(String::from),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LiveFeed {
+    tx: broadcast::Sender<LiveHook>,
+    // With NOTIFY on our own webhooks come back through the listener like everyone else's
+    use_notify: bool,
+}
+
+impl LiveFeed {
+    pub fn new(config: &AppConfig) -> LiveFeed {
+        let (tx, _) = broadcast::channel(LIVE_CHANNEL_CAPACITY);
+        LiveFeed {
+            tx,
+            use_notify: config.enable_pg_notify,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveHook> {
+        self.tx.subscribe()
+    }
+
+    // Called once a webhook is safely in the db, nobody watching isn't an error
+    pub fn publish(&self, pool: &r2d2::Pool<ConnectionManager<PgConnection>>, webhook: &Webhook) {
+        let hook = LiveHook::from(webhook);
+        if self.use_notify {
+            // Without a connection the webhook is still recorded, it just doesn't show up live
+            let notified = pool
+                .get()
+                .map_err(|err| err.to_string())
+                .and_then(|conn| notify(&conn, &hook).map_err(|err| err.to_string()));
+            if let Err(err) = notified {
+                counter!("live.publish.notify_errors", 1);
+                warn!("Unable to notify replicas of webhook {}: {}", hook.id, err);
+            }
+        } else if self.tx.send(hook).is_ok() {
+            counter!("live.publish.delivered", 1);
+        }
+    }
+}
+
+fn notify(conn: &PgConnection, hook: &LiveHook) -> Result<usize, diesel::result::Error> {
+    let payload = serde_json::to_string(hook).unwrap();
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(NOTIFY_CHANNEL)
+        .bind::<Text, _>(payload)
+        .execute(conn)
+}
+
+// LISTEN needs a connection diesel can't give us, so it gets a task and a client of its own
+pub fn spawn_listener(config: &AppConfig, feed: &LiveFeed) {
+    if !feed.use_notify {
+        debug!("Postgres notifications disabled, live feed is local only");
+        return;
+    }
+    // Same as diesel's connections, sslmode in the URL decides whether TLS is tried or required
+    let tls = match TlsConnector::new() {
+        Ok(connector) => MakeTlsConnector::new(connector),
+        Err(err) => {
+            counter!("live.listener.errors", 1);
+            warn!(
+                "Unable to set up TLS for the {} listener: {}",
+                NOTIFY_CHANNEL, err
+            );
+            return;
+        }
+    };
+    let db_url = config.db_url.clone();
+    let tx = feed.tx.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = listen(&db_url, tls.clone(), &tx).await {
+                counter!("live.listener.errors", 1);
+                warn!("Lost {} listener: {}", NOTIFY_CHANNEL, err);
+            }
+            delay_for(Duration::from_secs(LISTEN_RETRY_SECS)).await;
+        }
+    });
+}
+
+async fn listen(
+    db_url: &str,
+    tls: MakeTlsConnector,
+    tx: &broadcast::Sender<LiveHook>,
+) -> Result<(), tokio_postgres::Error> {
+    let (client, mut connection) = tokio_postgres::connect(db_url, tls).await?;
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+    // The connection only moves while its messages are read, so LISTEN goes out alongside them
+    let statement = format!("LISTEN {}", NOTIFY_CHANNEL);
+    let listening = client.batch_execute(&statement);
+    pin_mut!(listening);
+    let mut listened = false;
+    loop {
+        tokio::select! {
+            result = &mut listening, if !listened => {
+                result?;
+                listened = true;
+                info!("Listening for webhooks on {}", NOTIFY_CHANNEL);
+            }
+            message = messages.next() => match message {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    match serde_json::from_str::<LiveHook>(notification.payload()) {
+                        Ok(hook) => {
+                            counter!("live.listener.notifications", 1);
+                            let _ = tx.send(hook);
+                        }
+                        Err(err) => warn!("Ignoring unparseable notification: {}", err),
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+// GET /display/:string/stream
+pub async fn stream_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    feed: LiveFeed,
+    display_url: String,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => return Ok(Box::new(db::unavailable())),
+    };
+    let tag_id_val = match find_tag_id(&conn, &display_url) {
+        Ok(found_id) => found_id,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(Box::new(warp::reply::with_status(
+                "No such tag",
+                StatusCode::NOT_FOUND,
+            )))
+        }
+        Err(_) => return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR)),
+    };
+    counter!("live.stream_tag.subscribers", 1);
+    let events = feed.subscribe().filter_map(move |received| {
+        future::ready(match received {
+            Ok(hook) if hook.tag_id == Some(tag_id_val) => Some(Ok::<_, Infallible>((
+                warp::sse::id(hook.id),
+                warp::sse::event("webhook"),
+                warp::sse::json(hook),
+            ))),
+            Ok(_) => None,
+            Err(RecvError::Lagged(skipped)) => {
+                counter!("live.stream_tag.lagged", skipped);
+                None
+            }
+            Err(RecvError::Closed) => None,
+        })
+    });
+    Ok(Box::new(warp::sse::reply(
+        warp::sse::keep_alive().stream(events),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LiveFeed, LiveHook};
+    use crate::config::AppConfig;
+    use crate::model::tests::webhook;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use std::time::Duration;
+
+    #[test]
+    fn test_live_hook_from_webhook() {
+        let recorded = webhook(
+            "PUT",
+            &[("content-type", "application/json")],
+            br#"{"ok":true}"#,
+        );
+        let hook = LiveHook::from(&recorded);
+        assert_eq!(hook.id, 1);
+        assert_eq!(hook.tag_id, Some(1));
+        assert_eq!(hook.method, "PUT");
+        assert_eq!(hook.body_size, 11);
+        assert_eq!(hook.content_type.as_deref(), Some("application/json"));
+
+        // NOTIFY payloads have to come back out of the listener in one piece
+        let payload = serde_json::to_string(&hook).unwrap();
+        let back: LiveHook = serde_json::from_str(&payload).unwrap();
+        assert_eq!(back.id, hook.id);
+        assert_eq!(back.upload_time, hook.upload_time);
+    }
+
+    #[test]
+    fn test_publish_without_notify() {
+        let config = AppConfig::new(
+            &mut vec![("DATABASE_URL".to_string(), "pgsql://test".to_string())].into_iter(),
+        );
+        let feed = LiveFeed::new(&config);
+        // Never connects, publishing locally doesn't touch the db
+        let pool = Pool::builder().build_unchecked(ConnectionManager::new("pgsql://test"));
+        let recorded = webhook("POST", &[], b"");
+        feed.publish(&pool, &recorded);
+
+        let mut received = feed.subscribe();
+        feed.publish(&pool, &recorded);
+        let hook = received.try_recv().unwrap();
+        assert_eq!(hook.id, 1);
+        assert_eq!(hook.content_type, None);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_publish_without_db() {
+        let config = AppConfig::new(
+            &mut vec![
+                ("DATABASE_URL".to_string(), "pgsql://test".to_string()),
+                ("ENABLE_PG_NOTIFY".to_string(), "true".to_string()),
+            ]
+            .into_iter(),
+        );
+        let feed = LiveFeed::new(&config);
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::new("pgsql://test"));
+        // Losing the db only loses the notification, recording carries on
+        let mut received = feed.subscribe();
+        feed.publish(&pool, &webhook("POST", &[], b""));
+        assert!(received.try_recv().is_err());
+    }
+}
@@ -1,3 +1,5 @@
+// warp nests a type per filter, and the routing table is deep enough to need more room
+#![recursion_limit = "256"]
 extern crate openssl;
 #[macro_use]
 extern crate diesel;
@@ -11,20 +13,40 @@ extern crate r2d2;
 extern crate signal_hook;
 extern crate warp;
 
+pub mod api;
 pub mod config;
 pub mod db;
+pub mod delivery;
+pub mod destinations;
+pub mod diff;
 pub mod display;
+pub mod export;
+pub mod faults;
 pub mod filters;
+pub mod forward;
+pub mod har;
 pub mod healthcheck;
+pub mod jsonpath;
+pub mod live;
 pub mod model;
+pub mod pretty;
 pub mod record;
+pub mod replay;
+pub mod retention;
 pub mod schema;
+pub mod search;
 pub mod server;
+pub mod signatures;
 pub mod templating;
 pub mod tagmgr;
+pub mod ws;
 
 use config::AppConfig;
 use db::DbFacade;
+use delivery::DeliveryQueue;
+use forward::Forwarder;
+use live::LiveFeed;
+use retention::Pruner;
 use templating::Templater;
 
 use log::Level;
@@ -58,8 +80,29 @@ async fn main() -> Result<(), Error> {
     // Setup metrics facade and logexporter
     init_logging(&config);
 
+    // Webhooks get fanned out to live viewers, optionally via postgres for other replicas
+    let feed = LiveFeed::new(&config);
+    live::spawn_listener(&config, &feed);
+
+    // Shared http client for relaying webhooks to their tags' forward targets
+    let forwarder = Forwarder::new(&config);
+    // Forwards are queued in postgres and sent by a worker that retries them with backoff
+    let queue = DeliveryQueue::new(&config);
+    delivery::spawn_worker(db.get_pool(), forwarder.clone(), &queue);
+    // Old webhooks are pruned periodically according to the global and per-tag retention
+    let pruner = Pruner::new(&config);
+    retention::spawn_pruner(db.get_pool(), &pruner);
+
     // The return here is a transmit handle to signal shutdown of the warp server
-    let tx = server::spawn_server(db, config, Templater::new());
+    let tx = server::spawn_server(
+        db,
+        config,
+        Templater::new(),
+        feed,
+        forwarder,
+        queue,
+        pruner,
+    );
     timing!("init.time_to_serve", clock.delta(init_start, clock.end()));
     info!("Server task spawned, entering runloop waiting for shutdown signal");
     // Now that everything important is running asynchronously on a threadpool
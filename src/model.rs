@@ -1,22 +1,199 @@
 use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Jsonb;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
+use warp::http::HeaderMap;
 
 #[derive(Queryable, Deserialize, Serialize, Clone, Debug)]
 pub struct Webhook {
     pub id: i32,
-    pub headers: String,
-    pub body: String,
+    pub headers: HeaderList,
+    #[serde(with = "base64_body")]
+    pub body: Vec<u8>,
     pub upload_time: NaiveDateTime,
     pub tag_id: Option<i32>,
+    pub method: String,
+    pub query_string: Option<String>,
+    pub path_tail: String,
+    pub remote_addr: Option<String>,
+    pub body_encoding: String,
+    pub response_status: Option<i32>,
+    pub response_headers: Option<HeaderList>,
+    #[serde(with = "base64_opt_body", default)]
+    pub response_body: Option<Vec<u8>>,
+    pub response_delay_ms: i32,
+    pub response_fault: Option<String>,
+    pub signature_verdict: Option<String>,
+}
+
+impl Webhook {
+    // The body as text, when it was recorded as valid UTF-8
+    pub fn body_text(&self) -> Option<&str> {
+        if self.body_encoding == BodyEncoding::Utf8.as_str() {
+            std::str::from_utf8(&self.body).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("content-type")
+    }
+
+    // What we answered with, as text when it's printable
+    pub fn response_body_text(&self) -> Option<&str> {
+        self.response_body
+            .as_deref()
+            .and_then(|body| std::str::from_utf8(body).ok())
+    }
+}
+
+// Set once the response has been decided, after the webhook itself is saved
+#[derive(AsChangeset)]
+#[table_name = "webhooks"]
+pub struct RecordedResponse<'a> {
+    pub response_status: i32,
+    pub response_headers: &'a HeaderList,
+    pub response_body: &'a [u8],
+    pub response_delay_ms: i32,
+    pub response_fault: Option<&'a str>,
 }
 
 use super::schema::webhooks;
 #[derive(Insertable)]
 #[table_name = "webhooks"]
 pub struct NewWebhook<'a> {
-    pub headers: &'a str,
-    pub body: &'a str,
+    pub headers: &'a HeaderList,
+    pub body: &'a [u8],
     pub tag_id: i32,
+    pub method: &'a str,
+    pub query_string: Option<&'a str>,
+    pub path_tail: &'a str,
+    pub remote_addr: Option<&'a str>,
+    pub body_encoding: &'a str,
+    pub signature_verdict: Option<&'a str>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyEncoding {
+    Utf8,
+    Binary,
+}
+
+impl BodyEncoding {
+    pub fn detect(body: &[u8]) -> BodyEncoding {
+        match std::str::from_utf8(body) {
+            Ok(_) => BodyEncoding::Utf8,
+            Err(_) => BodyEncoding::Binary,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BodyEncoding::Utf8 => "utf-8",
+            BodyEncoding::Binary => "binary",
+        }
+    }
+}
+
+// Bodies can be arbitrary bytes, so they travel through serde as base64 strings
+mod base64_body {
+    use openssl::base64;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode_block(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode_block(&encoded).map_err(D::Error::custom)
+    }
+}
+
+// Same again for the response body, which is missing on webhooks from before responses were kept
+mod base64_opt_body {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        body: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match body {
+            Some(bytes) => super::base64_body::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super::base64_body")] Vec<u8>);
+        let wrapped = Option::<Wrapper>::deserialize(deserializer)?;
+        Ok(wrapped.map(|Wrapper(bytes)| bytes))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HeaderPair {
+    pub name: String,
+    pub value: String,
+}
+
+// Headers as received, with repeats, stored as a JSONB array of name/value objects
+#[derive(
+    AsExpression, FromSqlRow, Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq,
+)]
+#[sql_type = "Jsonb"]
+#[serde(transparent)]
+pub struct HeaderList(pub Vec<HeaderPair>);
+
+impl HeaderList {
+    // First value for a header, names are compared case-insensitively like HTTP does
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|pair| pair.name.eq_ignore_ascii_case(name))
+            .map(|pair| pair.value.as_str())
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, HeaderPair> {
+        self.0.iter()
+    }
+}
+
+// hyper has already grouped repeated names by the time we see them, so A, B, A comes out as
+// A, A, B, and values that aren't UTF-8 get replacement characters for the bad bytes
+impl From<&HeaderMap> for HeaderList {
+    fn from(header_map: &HeaderMap) -> HeaderList {
+        HeaderList(
+            header_map
+                .iter()
+                .map(|(name, value)| HeaderPair {
+                    name: name.as_str().to_string(),
+                    value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl FromSql<Jsonb, Pg> for HeaderList {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let value = <serde_json::Value as FromSql<Jsonb, Pg>>::from_sql(bytes)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for HeaderList {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, out)
+    }
 }
 
 #[derive(Queryable, Deserialize, Serialize, Clone, Debug)]
@@ -25,6 +202,35 @@ pub struct Tag {
     pub url_suffix: String,
     pub created_at: NaiveDateTime,
     pub active: bool,
+    pub response_status: Option<i32>,
+    #[serde(default)]
+    pub response_headers: HeaderList,
+    pub response_body: Option<String>,
+    pub delay_min_ms: i32,
+    pub delay_max_ms: i32,
+    pub failure_rate: f64,
+    pub failure_status: i32,
+    pub fail_next: i32,
+    pub signature_scheme: String,
+    // Write only, the api and pages only ever say whether one is set
+    #[serde(skip_serializing, default)]
+    pub signature_secret: String,
+    pub signature_header: String,
+    pub signature_tolerance_secs: i32,
+    pub reject_unsigned: bool,
+    pub retention_max_age_secs: Option<i32>,
+    pub retention_max_count: Option<i32>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl Tag {
+    pub fn response_spec(&self) -> ResponseSpec {
+        ResponseSpec {
+            response_status: self.response_status,
+            response_headers: self.response_headers.clone(),
+            response_body: self.response_body.clone(),
+        }
+    }
 }
 
 use super::schema::tags;
@@ -34,3 +240,282 @@ pub struct NewTag {
     pub url_suffix: String,
     pub active: bool,
 }
+
+// Partial update of a tag, fields left as None are not touched
+#[derive(AsChangeset, Deserialize, Debug, Default)]
+#[table_name = "tags"]
+pub struct TagChanges {
+    pub url_suffix: Option<String>,
+    pub active: Option<bool>,
+    pub delay_min_ms: Option<i32>,
+    pub delay_max_ms: Option<i32>,
+    pub failure_rate: Option<f64>,
+    pub failure_status: Option<i32>,
+    pub fail_next: Option<i32>,
+    pub signature_scheme: Option<String>,
+    pub signature_secret: Option<String>,
+    pub signature_header: Option<String>,
+    pub signature_tolerance_secs: Option<i32>,
+    pub reject_unsigned: Option<bool>,
+    // Some(None) puts the tag back on the global policy
+    #[serde(default, deserialize_with = "double_option")]
+    pub retention_max_age_secs: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub retention_max_count: Option<Option<i32>>,
+}
+
+// Tells a field that was left out (don't touch it) apart from an explicit null (clear it)
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// What record_webhook answers with for a tag, the body is a handlebars template
+#[derive(AsChangeset, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[table_name = "tags"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct ResponseSpec {
+    pub response_status: Option<i32>,
+    #[serde(default)]
+    pub response_headers: HeaderList,
+    pub response_body: Option<String>,
+}
+
+impl ResponseSpec {
+    pub fn is_empty(&self) -> bool {
+        self.response_status.is_none()
+            && self.response_headers.0.is_empty()
+            && self.response_body.is_none()
+    }
+}
+
+#[derive(Queryable, Deserialize, Serialize, Clone, Debug)]
+pub struct Replay {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub target_url: String,
+    pub strip_hop_by_hop: bool,
+    pub host_override: Option<String>,
+    pub sent_at: NaiveDateTime,
+    pub status: Option<i32>,
+    pub headers: Option<HeaderList>,
+    #[serde(with = "base64_opt_body", default)]
+    pub body: Option<Vec<u8>>,
+    pub latency_ms: Option<i32>,
+    pub error: Option<String>,
+}
+
+impl Replay {
+    pub fn body_text(&self) -> Option<&str> {
+        self.body
+            .as_deref()
+            .and_then(|body| std::str::from_utf8(body).ok())
+    }
+}
+
+use super::schema::replays;
+#[derive(Insertable)]
+#[table_name = "replays"]
+pub struct NewReplay<'a> {
+    pub webhook_id: i32,
+    pub target_url: &'a str,
+    pub strip_hop_by_hop: bool,
+    pub host_override: Option<&'a str>,
+    pub status: Option<i32>,
+    pub headers: Option<&'a HeaderList>,
+    pub body: Option<&'a [u8]>,
+    pub latency_ms: Option<i32>,
+    pub error: Option<&'a str>,
+}
+
+// A forward on its way to one of a tag's destinations, dead once it's run out of attempts
+#[derive(Queryable, QueryableByName, Deserialize, Serialize, Clone, Debug)]
+#[table_name = "deliveries"]
+pub struct Delivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub target_url: String,
+    pub state: String,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub destination_id: Option<i32>,
+    pub response_status: Option<i32>,
+    pub response_headers: Option<HeaderList>,
+    #[serde(with = "base64_opt_body", default)]
+    pub response_body: Option<Vec<u8>>,
+    pub latency_ms: Option<i32>,
+}
+
+impl Delivery {
+    pub fn response_body_text(&self) -> Option<&str> {
+        self.response_body
+            .as_deref()
+            .and_then(|body| std::str::from_utf8(body).ok())
+    }
+}
+
+use super::schema::deliveries;
+#[derive(Insertable)]
+#[table_name = "deliveries"]
+pub struct NewDelivery<'a> {
+    pub webhook_id: i32,
+    pub target_url: &'a str,
+    pub destination_id: Option<i32>,
+}
+
+// Whatever came of a delivery's latest attempt, an error leaves the rest NULL
+#[derive(AsChangeset, Default)]
+#[table_name = "deliveries"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct DeliveryOutcome<'a> {
+    pub response_status: Option<i32>,
+    pub response_headers: Option<&'a HeaderList>,
+    pub response_body: Option<&'a [u8]>,
+    pub latency_ms: Option<i32>,
+    pub last_error: Option<&'a str>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Clone, Debug)]
+pub struct Destination {
+    pub id: i32,
+    pub tag_id: i32,
+    pub name: String,
+    pub url: String,
+    pub enabled: bool,
+    pub match_header: Option<String>,
+    pub match_header_value: Option<String>,
+    pub match_json_path: Option<String>,
+    pub match_json_value: Option<serde_json::Value>,
+    pub created_at: NaiveDateTime,
+}
+
+// Everything about a destination that can be set, PUT replaces the lot so None clears a condition
+use super::schema::forward_destinations;
+#[derive(Insertable, AsChangeset, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[table_name = "forward_destinations"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct DestinationSpec {
+    pub name: String,
+    pub url: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub match_header: Option<String>,
+    pub match_header_value: Option<String>,
+    pub match_json_path: Option<String>,
+    pub match_json_value: Option<serde_json::Value>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl From<&Destination> for DestinationSpec {
+    fn from(destination: &Destination) -> Self {
+        DestinationSpec {
+            name: destination.name.clone(),
+            url: destination.url.clone(),
+            enabled: destination.enabled,
+            match_header: destination.match_header.clone(),
+            match_header_value: destination.match_header_value.clone(),
+            match_json_path: destination.match_json_path.clone(),
+            match_json_value: destination.match_json_value.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{BodyEncoding, HeaderList, HeaderPair, Webhook};
+    use warp::http::header::{HeaderMap, HeaderValue};
+
+    // A webhook as record would have saved it, tests override whatever else they care about
+    pub(crate) fn webhook(method: &str, headers: &[(&str, &str)], body: &[u8]) -> Webhook {
+        Webhook {
+            id: 1,
+            headers: HeaderList(
+                headers
+                    .iter()
+                    .map(|(name, value)| HeaderPair {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect(),
+            ),
+            body: body.to_vec(),
+            upload_time: chrono::NaiveDateTime::from_timestamp(0, 0),
+            tag_id: Some(1),
+            method: method.to_string(),
+            query_string: None,
+            path_tail: String::new(),
+            remote_addr: None,
+            body_encoding: BodyEncoding::detect(body).as_str().to_string(),
+            response_status: None,
+            response_headers: None,
+            response_body: None,
+            response_delay_ms: 0,
+            response_fault: None,
+            signature_verdict: None,
+        }
+    }
+
+    #[test]
+    fn test_header_list_keeps_repeats() {
+        let mut header_map = HeaderMap::new();
+        header_map.insert("content-type", HeaderValue::from_static("text/plain"));
+        header_map.append("x-seen", HeaderValue::from_static("first"));
+        header_map.append("x-seen", HeaderValue::from_static("second"));
+        let headers = HeaderList::from(&header_map);
+        let pairs: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|pair| (pair.name.as_str(), pair.value.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("content-type", "text/plain"),
+                ("x-seen", "first"),
+                ("x-seen", "second")
+            ]
+        );
+        assert_eq!(headers.get("Content-Type"), Some("text/plain"));
+        assert_eq!(headers.get("x-missing"), None);
+    }
+
+    #[test]
+    fn test_header_list_from_header_map_limits() {
+        let mut header_map = HeaderMap::new();
+        header_map.append("x-a", HeaderValue::from_static("1"));
+        header_map.append("x-b", HeaderValue::from_static("2"));
+        header_map.append("x-a", HeaderValue::from_static("3"));
+        header_map.append("x-latin1", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+        let headers = HeaderList::from(&header_map);
+        let pairs: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|pair| (pair.name.as_str(), pair.value.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("x-a", "1"),
+                ("x-a", "3"),
+                ("x-b", "2"),
+                ("x-latin1", "caf\u{fffd}")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_body_encoding_detect() {
+        assert_eq!(BodyEncoding::detect(b"{\"ok\":true}"), BodyEncoding::Utf8);
+        assert_eq!(
+            BodyEncoding::detect(&[0x1f, 0x8b, 0x08, 0xff]),
+            BodyEncoding::Binary
+        );
+    }
+}
@@ -0,0 +1,625 @@
+use handlebars::html_escape;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::fmt::Write;
+
+// Past this the raw body is quicker to read than a page of markup
+const MAX_PRETTY_BYTES: usize = 1 << 20;
+// JSON nodes deeper than this start out collapsed
+const OPEN_DEPTH: usize = 3;
+// Multipart bodies nested deeper than this are shown as plain text
+const MAX_PART_DEPTH: usize = 4;
+
+// The body as html for the display page: a structured view when the content type is one we
+// understand, the text as is otherwise, and nothing for binary the page hex dumps instead
+pub fn render(body: &[u8], content_type: Option<&str>) -> String {
+    let text = std::str::from_utf8(body).ok();
+    let view = if body.len() <= MAX_PRETTY_BYTES {
+        structured(body, content_type, 0)
+    } else {
+        None
+    };
+    match (view, text) {
+        (Some(view), Some(text)) => format!(
+            "{}<details class=\"pretty-raw\"><summary>Raw body</summary>\
+             <pre class=\"has-text-black-ter\">{}</pre></details>",
+            view,
+            html_escape(text)
+        ),
+        (Some(view), None) => view,
+        (None, Some(text)) => format!(
+            "<pre class=\"has-text-black-ter\">{}</pre>",
+            html_escape(text)
+        ),
+        (None, None) => String::new(),
+    }
+}
+
+fn structured(body: &[u8], content_type: Option<&str>, depth: usize) -> Option<String> {
+    let essence = content_type
+        .and_then(|value| value.split(';').next())
+        .map(|essence| essence.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if essence.starts_with("multipart/") {
+        if depth >= MAX_PART_DEPTH {
+            return None;
+        }
+        let boundary = header_param(content_type?, "boundary")?;
+        return multipart_html(body, &boundary, depth + 1);
+    }
+    let text = std::str::from_utf8(body).ok()?;
+    match essence.as_str() {
+        "application/json" | "text/json" => json_html(text),
+        "application/xml" | "text/xml" => xml_html(text),
+        "application/x-www-form-urlencoded" => form_html(text),
+        essence if essence.ends_with("+json") => json_html(text),
+        essence if essence.ends_with("+xml") => xml_html(text),
+        // Senders that leave the content type off still mostly send JSON
+        "" if text.trim_start().starts_with(&['{', '['][..]) => json_html(text),
+        "" if text.trim_start().starts_with("<?xml") => xml_html(text),
+        _ => None,
+    }
+}
+
+// A parameter from a header value like multipart/form-data; boundary="abc"
+fn header_param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, found) = param.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(found.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+// serde_json's own Value sorts object keys, this keeps them in the order they were sent
+enum Node {
+    Null,
+    Bool(bool),
+    Number(serde_json::Number),
+    Text(String),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Node, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Node, E> {
+        Ok(Node::Null)
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Node, E> {
+        Ok(Node::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Node, E> {
+        Ok(Node::Number(value.into()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Node, E> {
+        Ok(Node::Number(value.into()))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Node, E> {
+        Ok(serde_json::Number::from_f64(value).map_or(Node::Null, Node::Number))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Node, E> {
+        Ok(Node::Text(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Node, E> {
+        Ok(Node::Text(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Node::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut members = Vec::new();
+        while let Some(member) = map.next_entry()? {
+            members.push(member);
+        }
+        Ok(Node::Object(members))
+    }
+}
+
+fn json_html(text: &str) -> Option<String> {
+    let root: Node = serde_json::from_str(text).ok()?;
+    let mut out = String::from("<div class=\"pretty pretty-json\">");
+    json_node(&root, "", false, 0, &mut out);
+    out.push_str("</div>");
+    Some(out)
+}
+
+fn json_quoted(text: &str) -> String {
+    html_escape(&serde_json::to_string(text).unwrap_or_default())
+}
+
+// Containers become details elements, so collapsing a node needs no script
+fn json_node(node: &Node, label: &str, comma: bool, depth: usize, out: &mut String) {
+    let comma = if comma { "," } else { "" };
+    let (open, close, children) = match node {
+        Node::Array(items) if !items.is_empty() => (
+            "[",
+            "]",
+            items
+                .iter()
+                .map(|item| (String::new(), item))
+                .collect::<Vec<_>>(),
+        ),
+        Node::Object(members) if !members.is_empty() => (
+            "{",
+            "}",
+            members
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        format!("<span class=\"pretty-key\">{}</span>: ", json_quoted(key)),
+                        value,
+                    )
+                })
+                .collect(),
+        ),
+        scalar => {
+            let value = match scalar {
+                Node::Null => "<span class=\"pretty-literal\">null</span>".to_string(),
+                Node::Bool(value) => format!("<span class=\"pretty-literal\">{}</span>", value),
+                Node::Number(value) => format!("<span class=\"pretty-number\">{}</span>", value),
+                Node::Text(value) => {
+                    format!(
+                        "<span class=\"pretty-string\">{}</span>",
+                        json_quoted(value)
+                    )
+                }
+                Node::Array(_) => "[]".to_string(),
+                Node::Object(_) => "{}".to_string(),
+            };
+            let _ = write!(out, "<div>{}{}{}</div>", label, value, comma);
+            return;
+        }
+    };
+    let unit = match (node, children.len()) {
+        (Node::Object(_), 1) => "key",
+        (Node::Object(_), _) => "keys",
+        (_, 1) => "item",
+        _ => "items",
+    };
+    let _ = write!(
+        out,
+        "<details{}><summary>{}{}<span class=\"pretty-count\"> {} {} {}{}</span></summary>\
+         <div class=\"pretty-nested\">",
+        if depth < OPEN_DEPTH { " open" } else { "" },
+        label,
+        open,
+        children.len(),
+        unit,
+        close,
+        comma
+    );
+    let last = children.len() - 1;
+    for (index, (child_label, child)) in children.iter().enumerate() {
+        json_node(child, child_label, index < last, depth + 1, out);
+    }
+    let _ = write!(out, "</div><div>{}{}</div></details>", close, comma);
+}
+
+#[derive(Debug, PartialEq)]
+enum XmlToken<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    // Self closing elements, comments, declarations and CDATA all sit on a line of their own
+    Markup(&'a str),
+    Text(&'a str),
+}
+
+// Just enough of a tokenizer to indent by, anything unterminated means it isn't XML after all
+fn xml_tokens(text: &str) -> Option<Vec<XmlToken<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            tokens.push(XmlToken::Text(&rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+        let terminator = if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<![CDATA[") {
+            "]]>"
+        } else if rest.starts_with("<?") {
+            "?>"
+        } else {
+            ">"
+        };
+        let end = if terminator == ">" {
+            tag_end(rest)?
+        } else {
+            rest.find(terminator)? + terminator.len()
+        };
+        let tag = &rest[..end];
+        tokens.push(
+            if terminator != ">" || tag.starts_with("<!") || tag.ends_with("/>") {
+                XmlToken::Markup(tag)
+            } else if tag.starts_with("</") {
+                XmlToken::Close(tag)
+            } else {
+                XmlToken::Open(tag)
+            },
+        );
+        rest = &rest[end..];
+    }
+    Some(tokens)
+}
+
+// Where a tag ends, skipping any > inside quoted attribute values
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, found) in tag.char_indices() {
+        match (quote, found) {
+            (None, '"') | (None, '\'') => quote = Some(found),
+            (Some(open), found) if open == found => quote = None,
+            (None, '>') => return Some(index + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn xml_html(text: &str) -> Option<String> {
+    if !text.trim_start().starts_with('<') {
+        return None;
+    }
+    let tokens = xml_tokens(text)?;
+    let tag = |raw: &str| {
+        let class = if raw.starts_with("<!--") {
+            "pretty-comment"
+        } else {
+            "pretty-tag"
+        };
+        format!("<span class=\"{}\">{}</span>", class, html_escape(raw))
+    };
+    let mut lines = Vec::new();
+    let mut depth: usize = 0;
+    let mut index = 0;
+    while index < tokens.len() {
+        let indent = "  ".repeat(depth);
+        match (&tokens[index], tokens.get(index + 1), tokens.get(index + 2)) {
+            // Elements holding nothing but text stay on one line
+            (XmlToken::Open(open), Some(XmlToken::Text(inner)), Some(XmlToken::Close(close))) => {
+                lines.push(format!(
+                    "{}{}{}{}",
+                    indent,
+                    tag(open),
+                    html_escape(inner.trim()),
+                    tag(close)
+                ));
+                index += 3;
+                continue;
+            }
+            (XmlToken::Open(open), Some(XmlToken::Close(close)), _) => {
+                lines.push(format!("{}{}{}", indent, tag(open), tag(close)));
+                index += 2;
+                continue;
+            }
+            (XmlToken::Open(open), _, _) => {
+                lines.push(format!("{}{}", indent, tag(open)));
+                depth += 1;
+            }
+            (XmlToken::Close(close), _, _) => {
+                depth = depth.saturating_sub(1);
+                lines.push(format!("{}{}", "  ".repeat(depth), tag(close)));
+            }
+            (XmlToken::Markup(markup), _, _) => lines.push(format!("{}{}", indent, tag(markup))),
+            (XmlToken::Text(inner), _, _) => {
+                if !inner.trim().is_empty() {
+                    lines.push(format!("{}{}", indent, html_escape(inner.trim())));
+                }
+            }
+        }
+        index += 1;
+    }
+    Some(format!(
+        "<pre class=\"pretty pretty-xml has-text-black-ter\">{}</pre>",
+        lines.join("\n")
+    ))
+}
+
+fn form_html(text: &str) -> Option<String> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(text.trim()).ok()?;
+    if pairs.is_empty() {
+        return None;
+    }
+    let mut out = String::from(
+        "<table class=\"table is-narrow is-fullwidth pretty-form\">\
+         <thead><tr><th>Key</th><th>Value</th></tr></thead><tbody>",
+    );
+    for (key, value) in &pairs {
+        let _ = write!(
+            out,
+            "<tr><td class=\"pretty-key\">{}</td><td>{}</td></tr>",
+            html_escape(key),
+            html_escape(value)
+        );
+    }
+    out.push_str("</tbody></table>");
+    Some(out)
+}
+
+#[derive(Debug)]
+struct Part<'a> {
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(found, _)| found.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|found| found + from)
+}
+
+fn strip_newline(bytes: &[u8]) -> &[u8] {
+    bytes
+        .strip_suffix(b"\r\n")
+        .or_else(|| bytes.strip_suffix(b"\n"))
+        .unwrap_or(bytes)
+}
+
+// A body cut off before the closing delimiter still shows the parts it got to
+fn multipart_parts<'a>(body: &'a [u8], boundary: &str) -> Vec<Part<'a>> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut parts = Vec::new();
+    let mut pos = match find(body, delimiter, 0) {
+        Some(found) => found + delimiter.len(),
+        None => return parts,
+    };
+    while !body[pos..].starts_with(b"--") {
+        // Whatever's left of the delimiter line is padding
+        pos = match find(body, b"\n", pos) {
+            Some(found) => found + 1,
+            None => break,
+        };
+        let next = find(body, delimiter, pos);
+        let part = strip_newline(&body[pos..next.unwrap_or(body.len())]);
+        let (head, part_body) = if part.starts_with(b"\r\n") || part.starts_with(b"\n") {
+            (&part[..0], strip_newline_start(part))
+        } else if let Some(found) = find(part, b"\r\n\r\n", 0) {
+            (&part[..found], &part[found + 4..])
+        } else if let Some(found) = find(part, b"\n\n", 0) {
+            (&part[..found], &part[found + 2..])
+        } else {
+            (part, &part[part.len()..])
+        };
+        let headers = String::from_utf8_lossy(head)
+            .lines()
+            .filter_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect();
+        parts.push(Part {
+            headers,
+            body: part_body,
+        });
+        pos = match next {
+            Some(found) => found + delimiter.len(),
+            None => break,
+        };
+    }
+    parts
+}
+
+fn strip_newline_start(bytes: &[u8]) -> &[u8] {
+    bytes
+        .strip_prefix(b"\r\n")
+        .or_else(|| bytes.strip_prefix(b"\n"))
+        .unwrap_or(bytes)
+}
+
+fn multipart_html(body: &[u8], boundary: &str, depth: usize) -> Option<String> {
+    let parts = multipart_parts(body, boundary);
+    if parts.is_empty() {
+        return None;
+    }
+    let mut out = String::from("<div class=\"pretty pretty-multipart\">");
+    for (index, part) in parts.iter().enumerate() {
+        let disposition = part.header("content-disposition").unwrap_or_default();
+        let mut title = format!("Part {}", index + 1);
+        if let Some(name) = header_param(disposition, "name") {
+            let _ = write!(title, ": {}", name);
+        }
+        if let Some(filename) = header_param(disposition, "filename") {
+            let _ = write!(title, " ({})", filename);
+        }
+        let _ = write!(
+            out,
+            "<details open class=\"pretty-part\"><summary>{}</summary>",
+            html_escape(&title)
+        );
+        if !part.headers.is_empty() {
+            out.push_str("<table class=\"table is-narrow\"><tbody>");
+            for (name, value) in &part.headers {
+                let _ = write!(
+                    out,
+                    "<tr><td class=\"pretty-key\">{}</td><td>{}</td></tr>",
+                    html_escape(name),
+                    html_escape(value)
+                );
+            }
+            out.push_str("</tbody></table>");
+        }
+        match (
+            structured(part.body, part.header("content-type"), depth),
+            std::str::from_utf8(part.body),
+        ) {
+            (Some(view), _) => out.push_str(&view),
+            (None, Ok(text)) => {
+                let _ = write!(
+                    out,
+                    "<pre class=\"has-text-black-ter\">{}</pre>",
+                    html_escape(text)
+                );
+            }
+            (None, Err(_)) => {
+                let _ = write!(out, "<p>{} bytes of binary data</p>", part.body.len());
+            }
+        }
+        out.push_str("</details>");
+    }
+    out.push_str("</div>");
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{header_param, multipart_parts, render, xml_tokens, XmlToken, MAX_PART_DEPTH};
+
+    #[test]
+    fn test_render_json() {
+        let html = render(
+            br#"{"zebra": 1, "apple": {"tag": "<b>", "ok": true, "none": null}, "list": []}"#,
+            Some("application/json; charset=utf-8"),
+        );
+        // Keys keep the order they were sent in rather than serde_json's sorted one
+        let zebra = html.find("&quot;zebra&quot;").expect("zebra key");
+        let apple = html.find("&quot;apple&quot;").expect("apple key");
+        assert!(zebra < apple);
+        assert!(html.contains("<span class=\"pretty-number\">1</span>,"));
+        assert!(html.contains("<span class=\"pretty-string\">&quot;&lt;b&gt;&quot;</span>"));
+        assert!(html.contains("<span class=\"pretty-literal\">null</span>"));
+        assert!(html.contains("<span class=\"pretty-count\"> 3 keys },</span>"));
+        assert!(html.contains("[]</div>"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("Raw body"));
+
+        // No content type, but it looks like JSON
+        assert!(render(b"[1, 2]", None).contains("pretty-json"));
+        // Not JSON after all, so it's shown as sent
+        assert_eq!(
+            render(b"{nope", Some("application/json")),
+            "<pre class=\"has-text-black-ter\">{nope</pre>"
+        );
+    }
+
+    #[test]
+    fn test_render_xml() {
+        let html = render(
+            b"<?xml version=\"1.0\"?><order id=\"7\"><!-- note --><item>a &amp; b</item><empty/><gift></gift></order>",
+            Some("application/atom+xml"),
+        );
+        let expected = [
+            "<span class=\"pretty-tag\">&lt;?xml version=&quot;1.0&quot;?&gt;</span>",
+            "<span class=\"pretty-tag\">&lt;order id=&quot;7&quot;&gt;</span>",
+            "  <span class=\"pretty-comment\">&lt;!-- note --&gt;</span>",
+            "  <span class=\"pretty-tag\">&lt;item&gt;</span>a &amp;amp; b<span class=\"pretty-tag\">&lt;/item&gt;</span>",
+            "  <span class=\"pretty-tag\">&lt;empty/&gt;</span>",
+            "  <span class=\"pretty-tag\">&lt;gift&gt;</span><span class=\"pretty-tag\">&lt;/gift&gt;</span>",
+            "<span class=\"pretty-tag\">&lt;/order&gt;</span>",
+        ]
+        .join("\n");
+        assert!(html.contains(&expected), "{}", html);
+
+        assert_eq!(
+            xml_tokens("<a title=\"x>y\">"),
+            Some(vec![XmlToken::Open("<a title=\"x>y\">")])
+        );
+        assert_eq!(xml_tokens("<a><!-- unterminated"), None);
+    }
+
+    #[test]
+    fn test_render_form() {
+        let html = render(
+            b"name=Jane+Doe&note=%3Cscript%3E&empty=",
+            Some("application/x-www-form-urlencoded"),
+        );
+        assert!(html.contains("<tr><td class=\"pretty-key\">name</td><td>Jane Doe</td></tr>"));
+        assert!(html.contains("<td>&lt;script&gt;</td>"));
+        assert!(html.contains("<td class=\"pretty-key\">empty</td><td></td>"));
+    }
+
+    #[test]
+    fn test_render_multipart() {
+        let body = b"preamble\r\n--xyz\r\n\
+            Content-Disposition: form-data; name=\"meta\"\r\n\
+            Content-Type: application/json\r\n\r\n\
+            {\"a\": 1}\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n\
+            \xff\x00\xfe\r\n\
+            --xyz--\r\n";
+        let content_type = "multipart/form-data; boundary=\"xyz\"";
+        assert_eq!(
+            header_param(content_type, "boundary").as_deref(),
+            Some("xyz")
+        );
+
+        let parts = multipart_parts(body, "xyz");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].header("content-type"), Some("application/json"));
+        assert_eq!(parts[0].body, b"{\"a\": 1}");
+        assert_eq!(parts[1].body, b"\xff\x00\xfe");
+
+        // Binary parts don't stop the rest being shown, the page hex dumps the whole body too
+        let html = render(body, Some(content_type));
+        assert!(html.contains("<summary>Part 1: meta</summary>"));
+        assert!(html.contains("pretty-json"));
+        assert!(html.contains("<summary>Part 2: file (a.bin)</summary>"));
+        assert!(html.contains("<p>3 bytes of binary data</p>"));
+        assert!(!html.contains("Raw body"));
+        assert_eq!(render(b"\xff\x00", Some("image/png")), "");
+    }
+
+    #[test]
+    fn test_render_nested_multipart() {
+        // Each level wraps the one below it as its only part
+        let mut body = b"{\"deepest\": true}".to_vec();
+        let mut content_type = "application/json".to_string();
+        for level in 0..10 {
+            let boundary = format!("b{}", level);
+            let mut wrapped =
+                format!("--{}\r\nContent-Type: {}\r\n\r\n", boundary, content_type).into_bytes();
+            wrapped.extend_from_slice(&body);
+            wrapped.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+            body = wrapped;
+            content_type = format!("multipart/mixed; boundary={}", boundary);
+        }
+        let html = render(&body, Some(&content_type));
+        assert_eq!(html.matches("pretty-multipart").count(), MAX_PART_DEPTH);
+        assert!(!html.contains("pretty-json"));
+        // Past the limit the rest of the body is still there as escaped text
+        assert!(html.contains("--b5\r\nContent-Type: multipart/mixed; boundary=b4"));
+        assert!(html.contains("{&quot;deepest&quot;: true}"));
+    }
+}
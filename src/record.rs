@@ -1,46 +1,75 @@
+use super::db;
+use super::delivery::DeliveryQueue;
 use super::diesel::prelude::RunQueryDsl;
-use super::model::{NewWebhook, Tag, Webhook};
+use super::faults::{self, Fault, FaultPlan};
+use super::live::LiveFeed;
+use super::model::{BodyEncoding, HeaderList, NewWebhook, RecordedResponse, Tag, Webhook};
 use super::schema::tags::dsl::*;
+use super::signatures::{self, Verdict};
+use super::tagmgr;
+use super::templating::Templater;
 use crate::diesel::ExpressionMethods;
 use crate::diesel::QueryDsl;
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
-use log::debug;
+use log::{debug, warn};
 use metrics::{counter, timing};
 use quanta::Clock;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::convert::TryInto;
 
 use std::mem;
+use std::time::Duration;
+use warp::http::header::CONTENT_TYPE;
 use warp::http::HeaderMap;
-use warp::http::StatusCode;
+use warp::http::Method;
+use warp::http::{Response, StatusCode};
+
+// Everything the record filter extracts from a request, bundled so the handler signature stays sane
+#[derive(Debug)]
+pub struct IncomingHook {
+    pub url_suffix: String,
+    pub path_tail: String,
+    pub method: Method,
+    pub query_string: Option<String>,
+    pub remote_addr: Option<String>,
+    pub headers: HeaderMap,
+    pub body: bytes::Bytes,
+}
 
 // This wrapper handles type conversions from the aggregated buffer and HeaderMap the filters give us
 pub async fn record_webhook(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
-    body_bytes: bytes::Bytes,
-    header_map: HeaderMap,
-    url_seen: String,
+    feed: LiveFeed,
+    queue: DeliveryQueue,
+    templater: Templater,
+    hook: IncomingHook,
 ) -> Result<impl warp::Reply, Infallible> {
+    let header_map = &hook.headers;
+    let body_bytes = &hook.body;
+    let url_seen = hook.url_suffix.clone();
     let clock = Clock::new();
     let type_coercion_start = clock.start();
     counter!(
         "record.record_webhook.header_map.bytes",
-        mem::size_of_val(&header_map).try_into().unwrap()
+        mem::size_of_val(header_map).try_into().unwrap()
     );
     counter!(
         "record.record_webhook.body_bytes.bytes",
-        mem::size_of_val(&body_bytes).try_into().unwrap()
+        mem::size_of_val(body_bytes).try_into().unwrap()
     );
-    let headers = format!("{:?}", header_map); // TODO: Use serde_json to derive a string serializer
-    let body = String::from_utf8(body_bytes.to_vec()).unwrap();
+    let headers = HeaderList::from(header_map);
+    let body_encoding = BodyEncoding::detect(body_bytes);
     counter!(
-        "record.record_webhook.body_string.bytes",
-        mem::size_of_val(&body).try_into().unwrap()
+        "record.record_webhook.body.len",
+        body_bytes.len().try_into().unwrap()
     );
     counter!(
-        "record.record_webhook.header_string.bytes",
-        mem::size_of_val(&headers).try_into().unwrap()
+        "record.record_webhook.header_list.count",
+        headers.0.len().try_into().unwrap()
     );
     timing!(
         "record.record_webhook.type_coercion",
@@ -48,58 +77,254 @@ pub async fn record_webhook(
     );
     let tag_match_start = clock.start();
     debug!("Finding tag id for url_suffix: {}", url_seen);
-    let found = find_tag_id(&pool, url_seen).await;
-    let found_tag_id = match found {
-        Ok(found_id) => found_id,
-        Err(diesel::result::Error::NotFound) => return Ok(StatusCode::from_u16(404).unwrap()),
-        Err(_) => return Ok(StatusCode::from_u16(500).unwrap()),
+    let found = find_tag(&pool, url_seen).await;
+    let tag = match found {
+        Ok(tag) => tag,
+        Err(diesel::result::Error::NotFound) => return Ok(empty_response(StatusCode::NOT_FOUND)),
+        Err(_) => return Ok(empty_response(StatusCode::INTERNAL_SERVER_ERROR)),
     };
     timing!(
         "record.record_webhook.find_tag_id",
         clock.delta(tag_match_start, clock.end())
     );
+    let now = chrono::Utc::now().timestamp();
+    let verdict = signatures::verify(&tag, &headers, body_bytes, now);
+    if let Some(verdict) = verdict {
+        counter!("record.record_webhook.signature", 1, "verdict" => verdict.as_str());
+    }
     let db_write_start = clock.start();
-    let result = _do_record_webhook(&pool, &headers, &body, found_tag_id).await;
+    let result =
+        _do_record_webhook(&pool, &hook, &headers, body_encoding, tag.tag_id, verdict).await;
     timing!(
         "record.record_webhook.db_write",
         clock.delta(db_write_start, clock.end())
     );
     // Quick'n'dirty proxy that the row got inserted successfully
     if result.upload_time.timestamp() > 0 {
-        Ok(StatusCode::OK)
+        feed.publish(&pool, &result);
+        // Rejected requests are still recorded, but don't use up any of the tag's faults
+        let rejected = tag.reject_unsigned && verdict.map_or(false, |val| val != Verdict::Valid);
+        // Whoever we're tapping for can do without requests we've already turned away
+        if !rejected {
+            queue.enqueue(&pool, tag.tag_id, &result);
+        }
+        let plan = if rejected {
+            FaultPlan::default()
+        } else {
+            match db::connect(&pool) {
+                Ok(conn) => faults::plan_faults(&conn, &tag),
+                // Answering normally beats failing the request over a fault we meant to inject
+                Err(_) => FaultPlan::default(),
+            }
+        };
+        let response = match (verdict, plan.failure) {
+            (Some(verdict), _) if rejected => unauthorized_response(verdict),
+            (_, Some(fault)) => failure_response(&tag, fault),
+            (_, None) => canned_response(&templater, &tag, &hook, &result),
+        };
+        _do_record_response(&pool, result.id, &response, &plan);
+        if plan.delay > Duration::from_millis(0) {
+            counter!(
+                "record.record_webhook.injected_delay_ms",
+                plan.delay.as_millis().try_into().unwrap()
+            );
+            tokio::time::delay_for(plan.delay).await;
+        }
+        Ok(response)
     } else {
-        Ok(StatusCode::from_u16(500).unwrap())
+        Ok(empty_response(StatusCode::INTERNAL_SERVER_ERROR))
     }
 }
 
-// The private function where we shove strings into text fields into pgsql
+fn failure_response(tag: &Tag, fault: Fault) -> Response<Vec<u8>> {
+    let status =
+        StatusCode::from_u16(tag.failure_status as u16).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+    let mut response =
+        Response::new(format!("Simulated failure ({})", fault.as_str()).into_bytes());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/plain; charset=utf-8".parse().unwrap());
+    response
+}
+
+fn unauthorized_response(verdict: Verdict) -> Response<Vec<u8>> {
+    let mut response =
+        Response::new(format!("Signature check failed ({})", verdict.as_str()).into_bytes());
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/plain; charset=utf-8".parse().unwrap());
+    response
+}
+
+fn empty_response(status: StatusCode) -> Response<Vec<u8>> {
+    let mut response = Response::new(Vec::new());
+    *response.status_mut() = status;
+    response
+}
+
+// What a response template can see of the request it's answering
+#[derive(Serialize)]
+struct ResponseContext<'a> {
+    webhook_id: i32,
+    tag: &'a str,
+    method: &'a str,
+    path_tail: &'a str,
+    query_string: Option<&'a str>,
+    query: HashMap<String, String>,
+    remote_addr: Option<&'a str>,
+    // Lowercased names, first value wins when a header repeats
+    headers: HashMap<String, &'a str>,
+    body: Option<&'a str>,
+    json: Option<Value>,
+    form: Option<HashMap<String, String>>,
+}
+
+impl<'a> ResponseContext<'a> {
+    fn new(tag: &'a Tag, hook: &'a IncomingHook, webhook: &'a Webhook) -> ResponseContext<'a> {
+        let mut header_values = HashMap::new();
+        for pair in webhook.headers.iter() {
+            header_values
+                .entry(pair.name.to_lowercase())
+                .or_insert_with(|| pair.value.as_str());
+        }
+        let body_text = webhook.body_text();
+        let is_form = webhook.content_type().map_or(false, |val| {
+            val.starts_with("application/x-www-form-urlencoded")
+        });
+        ResponseContext {
+            webhook_id: webhook.id,
+            tag: &tag.url_suffix,
+            method: hook.method.as_str(),
+            path_tail: &hook.path_tail,
+            query_string: hook.query_string.as_deref(),
+            query: hook
+                .query_string
+                .as_deref()
+                .and_then(|qs| serde_urlencoded::from_str(qs).ok())
+                .unwrap_or_default(),
+            remote_addr: hook.remote_addr.as_deref(),
+            headers: header_values,
+            body: body_text,
+            json: body_text.and_then(|text| serde_json::from_str(text).ok()),
+            form: body_text
+                .filter(|_| is_form)
+                .and_then(|text| serde_urlencoded::from_str(text).ok()),
+        }
+    }
+}
+
+// A plain 200 unless the tag has a response spec, the webhook is already saved either way
+fn canned_response(
+    templater: &Templater,
+    tag: &Tag,
+    hook: &IncomingHook,
+    webhook: &Webhook,
+) -> Response<Vec<u8>> {
+    let spec = tag.response_spec();
+    if spec.is_empty() {
+        return empty_response(StatusCode::OK);
+    }
+    let body = match &spec.response_body {
+        Some(template) => {
+            let context = ResponseContext::new(tag, hook, webhook);
+            match templater.render_response(template, &context) {
+                Ok(rendered) => rendered.into_bytes(),
+                Err(err) => {
+                    counter!("record.canned_response.render_errors", 1);
+                    warn!("Response template for {} failed: {}", tag.url_suffix, err);
+                    let mut response = Response::new(err.into_bytes());
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    return response;
+                }
+            }
+        }
+        None => Vec::new(),
+    };
+    let mut builder = Response::builder().status(spec.response_status.unwrap_or(200) as u16);
+    for pair in spec.response_headers.iter() {
+        builder = builder.header(pair.name.as_str(), pair.value.as_str());
+    }
+    if spec.response_body.is_some() && spec.response_headers.get(CONTENT_TYPE.as_str()).is_none() {
+        builder = builder.header(CONTENT_TYPE, "text/plain; charset=utf-8");
+    }
+    builder.body(body).unwrap_or_else(|err| {
+        warn!("Bad response spec for {}: {}", tag.url_suffix, err);
+        empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+    })
+}
+
+// The private function where we shove the request into its row in pgsql
 async fn _do_record_webhook(
     pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
-    headers: &str,
-    body: &str,
+    hook: &IncomingHook,
+    headers: &HeaderList,
+    body_encoding: BodyEncoding,
     found_tag_id: i32,
+    verdict: Option<Verdict>,
 ) -> Webhook {
     use super::schema::webhooks;
     let newdoc = NewWebhook {
         headers,
-        body,
+        body: &hook.body,
         tag_id: found_tag_id,
+        method: hook.method.as_str(),
+        query_string: hook.query_string.as_deref(),
+        path_tail: &hook.path_tail,
+        remote_addr: hook.remote_addr.as_deref(),
+        body_encoding: body_encoding.as_str(),
+        signature_verdict: verdict.map(Verdict::as_str),
     };
     diesel::insert_into(webhooks::table)
         .values(&newdoc)
         .get_result::<Webhook>(&pool.get().unwrap())
-        .expect("Error saving new webhook POST")
+        .expect("Error saving new webhook")
+}
+
+// Every attempt keeps what the sender got back, failures and delays included
+fn _do_record_response(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    webhook_id: i32,
+    response: &Response<Vec<u8>>,
+    plan: &FaultPlan,
+) {
+    use super::schema::webhooks;
+    let sent_headers = HeaderList::from(response.headers());
+    let changes = RecordedResponse {
+        response_status: response.status().as_u16().into(),
+        response_headers: &sent_headers,
+        response_body: response.body(),
+        response_delay_ms: plan.delay.as_millis().try_into().unwrap(),
+        response_fault: plan.failure.map(Fault::as_str),
+    };
+    let saved = db::connect(pool)
+        .map_err(|err| err.to_string())
+        .and_then(|conn| {
+            diesel::update(webhooks::table.find(webhook_id))
+                .set(&changes)
+                .execute(&conn)
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = saved {
+        warn!(
+            "Unable to save response for webhook {}: {}",
+            webhook_id, err
+        );
+    }
 }
 
-async fn find_tag_id(
+// Expired tags 404 the same as deactivated ones
+async fn find_tag(
     pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
     url_seen: String,
-) -> Result<i32, diesel::result::Error> {
+) -> Result<Tag, diesel::result::Error> {
     let tag = tags
         .filter(url_suffix.eq(url_seen))
+        .filter(tagmgr::unexpired())
         .first::<Tag>(&pool.get().unwrap())?;
     if tag.active {
-        Ok(tag.tag_id)
+        Ok(tag)
     } else {
         Err(diesel::result::Error::NotFound)
     }
@@ -0,0 +1,300 @@
+use super::db;
+use super::display::{self, find_tag_id};
+use super::forward::{self, Forwarder};
+use super::model::{HeaderList, HeaderPair, NewReplay, Replay, Webhook};
+use super::schema::{replays, webhooks};
+use super::templating::Templater;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use log::warn;
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::convert::TryInto;
+use warp::http::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use warp::http::{Response, StatusCode};
+
+fn default_strip() -> bool {
+    true
+}
+
+// Where to send a webhook again, and how much of the original request to keep
+#[derive(Deserialize, Debug)]
+pub struct ReplayRequest {
+    pub url: String,
+    #[serde(default = "default_strip")]
+    pub strip_hop_by_hop: bool,
+    pub host: Option<String>,
+}
+
+impl ReplayRequest {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.url.is_empty() {
+            return Err("A URL to replay to is required".to_string());
+        }
+        forward::validate_forward_url(&self.url)?;
+        if let Some(host) = &self.host {
+            HeaderValue::from_str(host).map_err(|_| format!("{} is not a valid Host", host))?;
+        }
+        Ok(())
+    }
+}
+
+// The stored headers as they'll go out, Host is replaced rather than added to when overridden
+pub(crate) fn replay_headers(webhook: &Webhook, request: &ReplayRequest) -> HeaderList {
+    let mut headers = if request.strip_hop_by_hop {
+        forward::strip_hop_by_hop(&webhook.headers)
+    } else {
+        webhook.headers.clone()
+    };
+    if let Some(host) = &request.host {
+        headers
+            .0
+            .retain(|pair| !pair.name.eq_ignore_ascii_case("host"));
+        headers.0.push(HeaderPair {
+            name: "host".to_string(),
+            value: host.clone(),
+        });
+    }
+    headers
+}
+
+// The request has already gone out by the time the attempt is saved, so say so rather than
+// invite a retry that sends it twice
+pub(crate) const UNRECORDED: &str =
+    "The webhook was replayed but the database is unavailable to record the attempt";
+
+#[derive(Debug)]
+pub(crate) enum ReplayError {
+    Unrecorded,
+    Database(diesel::result::Error),
+}
+
+// Sends the webhook and records the attempt whether or not anything answered
+pub(crate) async fn replay_webhook(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    forwarder: &Forwarder,
+    webhook: &Webhook,
+    request: &ReplayRequest,
+) -> Result<Replay, ReplayError> {
+    let headers = replay_headers(webhook, request);
+    let result = forwarder
+        .send(&webhook.method, &request.url, &headers, &webhook.body)
+        .await;
+    let mut attempt = NewReplay {
+        webhook_id: webhook.id,
+        target_url: &request.url,
+        strip_hop_by_hop: request.strip_hop_by_hop,
+        host_override: request.host.as_deref(),
+        status: None,
+        headers: None,
+        body: None,
+        latency_ms: None,
+        error: None,
+    };
+    match &result {
+        Ok(upstream) => {
+            counter!("replay.replay_webhook.sent", 1);
+            attempt.status = Some(upstream.status.into());
+            attempt.headers = Some(&upstream.headers);
+            attempt.body = Some(&upstream.body);
+            attempt.latency_ms = Some(upstream.latency.as_millis().try_into().unwrap_or(i32::MAX));
+        }
+        Err(err) => {
+            counter!("replay.replay_webhook.errors", 1);
+            warn!(
+                "Replay of webhook {} to {} failed: {}",
+                webhook.id, request.url, err
+            );
+            attempt.error = Some(err);
+        }
+    }
+    let conn = db::connect(pool).map_err(|_| ReplayError::Unrecorded)?;
+    diesel::insert_into(replays::table)
+        .values(&attempt)
+        .get_result::<Replay>(&conn)
+        .map_err(ReplayError::Database)
+}
+
+// Newest first
+pub(crate) fn load_replays(
+    conn: &PgConnection,
+    webhook_id: i32,
+) -> Result<Vec<Replay>, diesel::result::Error> {
+    replays::table
+        .filter(replays::webhook_id.eq(webhook_id))
+        .order_by(replays::id.desc())
+        .load::<Replay>(conn)
+}
+
+// What the replay form on the display page is filled in with
+#[derive(Serialize, Debug)]
+pub(crate) struct ReplayFormView {
+    pub url: String,
+    pub host: String,
+    pub strip_hop_by_hop: bool,
+    pub error: Option<String>,
+}
+
+impl Default for ReplayFormView {
+    fn default() -> Self {
+        ReplayFormView {
+            url: String::new(),
+            host: String::new(),
+            strip_hop_by_hop: true,
+            error: None,
+        }
+    }
+}
+
+// Checkboxes are only sent when ticked, a blank Host means leave it alone
+fn parse_replay_form(body: &HashMap<String, String>) -> ReplayRequest {
+    ReplayRequest {
+        url: body
+            .get("url")
+            .map(|val| val.trim().to_string())
+            .unwrap_or_default(),
+        strip_hop_by_hop: body.contains_key("strip_hop_by_hop"),
+        host: body
+            .get("host")
+            .map(|val| val.trim().to_string())
+            .filter(|val| !val.is_empty()),
+    }
+}
+
+// POST /display/:string/:id/replay, back to the webhook to see how it went
+pub async fn replay_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    forwarder: Forwarder,
+    display_url: String,
+    webhook_id: i32,
+    body: HashMap<String, String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let request = parse_replay_form(&body);
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(db::UNAVAILABLE.to_string()))
+        }
+    };
+    if let Err(message) = request.validate() {
+        // Back to the webhook with what was typed, so it can be fixed rather than retyped
+        let form = ReplayFormView {
+            url: request.url,
+            host: request.host.unwrap_or_default(),
+            strip_hop_by_hop: request.strip_hop_by_hop,
+            error: Some(message),
+        };
+        let (html, status) =
+            display::render_webhook_page(&conn, &templater, &display_url, webhook_id, form);
+        let status = match status {
+            StatusCode::OK => StatusCode::BAD_REQUEST,
+            status => status,
+        };
+        return Ok(Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(html));
+    }
+    let found = find_tag_id(&conn, &display_url).and_then(|tag_id_val| {
+        webhooks::table
+            .filter(webhooks::tag_id.eq(tag_id_val))
+            .filter(webhooks::id.eq(webhook_id))
+            .first::<Webhook>(&conn)
+    });
+    // Let go of the connection before sending, the attempt is saved with another one
+    drop(conn);
+    let replayed = match found {
+        Ok(webhook) => replay_webhook(&pool, &forwarder, &webhook, &request).await,
+        Err(err) => Err(ReplayError::Database(err)),
+    };
+    Ok(match replayed {
+        Ok(_) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(
+                LOCATION,
+                format!("/display/{}/{}#replays", display_url, webhook_id),
+            )
+            .body(String::new()),
+        Err(ReplayError::Database(diesel::result::Error::NotFound)) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such webhook".to_string()),
+        Err(ReplayError::Unrecorded) => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(UNRECORDED.to_string()),
+        Err(ReplayError::Database(err)) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(err.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_replay_form, replay_headers, ReplayRequest};
+    use crate::model::tests::webhook;
+    use crate::model::HeaderList;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_replay_headers() {
+        let original = webhook(
+            "POST",
+            &[
+                ("host", "recorder.example.com"),
+                ("connection", "close"),
+                ("content-type", "application/json"),
+            ],
+            b"{}",
+        );
+        let names = |headers: HeaderList| -> Vec<String> {
+            headers
+                .iter()
+                .map(|pair| format!("{}={}", pair.name, pair.value))
+                .collect()
+        };
+        let stripped = ReplayRequest {
+            url: "http://localhost/".to_string(),
+            strip_hop_by_hop: true,
+            host: None,
+        };
+        assert_eq!(
+            names(replay_headers(&original, &stripped)),
+            vec!["content-type=application/json"]
+        );
+        let kept = ReplayRequest {
+            strip_hop_by_hop: false,
+            host: Some("api.internal".to_string()),
+            ..stripped
+        };
+        assert_eq!(
+            names(replay_headers(&original, &kept)),
+            vec![
+                "connection=close",
+                "content-type=application/json",
+                "host=api.internal"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_replay_form() {
+        let mut form = HashMap::new();
+        form.insert("url".to_string(), " http://127.0.0.1:8080/in ".to_string());
+        form.insert("host".to_string(), String::new());
+        let request = parse_replay_form(&form);
+        assert_eq!(request.url, "http://127.0.0.1:8080/in");
+        assert!(!request.strip_hop_by_hop);
+        assert_eq!(request.host, None);
+        assert!(request.validate().is_ok());
+        form.insert("strip_hop_by_hop".to_string(), "on".to_string());
+        form.insert("host".to_string(), "bad\nhost".to_string());
+        let request = parse_replay_form(&form);
+        assert!(request.strip_hop_by_hop);
+        assert!(request.validate().is_err());
+    }
+}
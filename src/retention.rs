@@ -0,0 +1,332 @@
+use super::config::{self, AppConfig};
+use super::model::TagChanges;
+use super::schema::tags;
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::now;
+use diesel::pg::data_types::PgInterval;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::{BigInt, Integer};
+use log::{debug, info, warn};
+use metrics::counter;
+use serde::Serialize;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// What a tag keeps, None is no limit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Policy {
+    pub max_age: Option<Duration>,
+    pub max_count: Option<i64>,
+}
+
+impl Policy {
+    pub fn global(config: &AppConfig) -> Policy {
+        Policy {
+            max_age: config.retention_max_age,
+            max_count: config.retention_max_count,
+        }
+    }
+
+    // A tag's own setting wins over the global one, NULL follows it and 0 turns the limit off
+    pub fn for_tag(&self, max_age_secs: Option<i32>, max_count: Option<i32>) -> Policy {
+        Policy {
+            max_age: match max_age_secs {
+                Some(secs) if secs > 0 => Some(Duration::from_secs(secs as u64)),
+                Some(_) => None,
+                None => self.max_age,
+            },
+            max_count: match max_count {
+                Some(count) if count > 0 => Some(count.into()),
+                Some(_) => None,
+                None => self.max_count,
+            },
+        }
+    }
+
+    pub fn describe_age(&self) -> String {
+        self.max_age
+            .map(config::format_duration)
+            .unwrap_or_else(|| "forever".to_string())
+    }
+
+    pub fn describe_count(&self) -> String {
+        self.max_count
+            .map(|count| count.to_string())
+            .unwrap_or_else(|| "all".to_string())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PruneStatus {
+    pub next_run: Option<NaiveDateTime>,
+    pub last_run: Option<NaiveDateTime>,
+    pub last_pruned: i64,
+    pub last_tags_deleted: i64,
+    pub last_error: Option<String>,
+}
+
+// Handed to the healthcheck and tag pages so they can show what the pruning task is up to
+#[derive(Clone)]
+pub struct Pruner {
+    pub policy: Policy,
+    pub interval: Duration,
+    // How long expired tags hang around before they're deleted, None keeps them
+    pub expired_grace: Option<Duration>,
+    batch_size: i64,
+    status: Arc<Mutex<PruneStatus>>,
+}
+
+impl Pruner {
+    pub fn new(config: &AppConfig) -> Pruner {
+        Pruner {
+            policy: Policy::global(config),
+            interval: config.retention_interval,
+            expired_grace: Some(config.expired_tag_grace).filter(|_| config.delete_expired_tags),
+            batch_size: config.retention_batch_size,
+            status: Arc::new(Mutex::new(PruneStatus::default())),
+        }
+    }
+
+    pub fn status(&self) -> PruneStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn describe_grace(&self) -> String {
+        self.expired_grace
+            .map(|grace| format!("after {}", config::format_duration(grace)))
+            .unwrap_or_else(|| "never".to_string())
+    }
+}
+
+// Prunes and sweeps once at startup and then every interval
+pub fn spawn_pruner(pool: r2d2::Pool<ConnectionManager<PgConnection>>, pruner: &Pruner) {
+    let pruner = pruner.clone();
+    tokio::spawn(async move {
+        info!(
+            "Retention task started, keeping {} and {} webhooks per tag by default",
+            pruner.policy.describe_age(),
+            pruner.policy.describe_count()
+        );
+        let mut ticks = tokio::time::interval(pruner.interval);
+        loop {
+            ticks.tick().await;
+            let started = Utc::now().naive_utc();
+            let pruned = pool
+                .get()
+                .map_err(|err| err.to_string())
+                .and_then(|conn| run_once(&conn, &pruner).map_err(|err| err.to_string()));
+            let mut status = pruner.status.lock().unwrap();
+            status.last_run = Some(started);
+            status.next_run = chrono::Duration::from_std(pruner.interval)
+                .ok()
+                .map(|interval| started + interval);
+            match pruned {
+                Ok((count, tags_deleted)) => {
+                    debug!(
+                        "Retention pruned {} webhooks and deleted {} expired tags",
+                        count, tags_deleted
+                    );
+                    status.last_pruned = count;
+                    status.last_tags_deleted = tags_deleted;
+                    status.last_error = None;
+                }
+                Err(err) => {
+                    counter!("retention.errors", 1);
+                    warn!("Unable to prune webhooks: {}", err);
+                    status.last_error = Some(err);
+                }
+            }
+        }
+    });
+}
+
+fn run_once(conn: &PgConnection, pruner: &Pruner) -> Result<(i64, i64), diesel::result::Error> {
+    let pruned = prune_all(conn, pruner)?;
+    let tags_deleted = sweep_expired(conn, pruner)?;
+    Ok((pruned, tags_deleted))
+}
+
+// Blocking, but each statement only touches a batch, so it never hogs the table
+fn prune_all(conn: &PgConnection, pruner: &Pruner) -> Result<i64, diesel::result::Error> {
+    let overrides = tags::table
+        .select((
+            tags::tag_id,
+            tags::retention_max_age_secs,
+            tags::retention_max_count,
+        ))
+        .load::<(i32, Option<i32>, Option<i32>)>(conn)?;
+    let mut pruned = 0;
+    for (found_tag_id, max_age_secs, max_count) in overrides {
+        let policy = pruner.policy.for_tag(max_age_secs, max_count);
+        if let Some(max_age) = policy.max_age {
+            let count = prune_batches(
+                || prune_older(conn, found_tag_id, max_age, pruner.batch_size),
+                pruner.batch_size,
+            )?;
+            counter!("retention.pruned", count.try_into().unwrap(), "reason" => "age");
+            pruned += count;
+        }
+        if let Some(max_count) = policy.max_count {
+            let count = prune_batches(
+                || prune_beyond(conn, found_tag_id, max_count, pruner.batch_size),
+                pruner.batch_size,
+            )?;
+            counter!("retention.pruned", count.try_into().unwrap(), "reason" => "count");
+            pruned += count;
+        }
+    }
+    Ok(pruned as i64)
+}
+
+// Webhooks go first in batches, so the tag's cascade has nothing left to do
+fn sweep_expired(conn: &PgConnection, pruner: &Pruner) -> Result<i64, diesel::result::Error> {
+    let grace = match pruner.expired_grace {
+        Some(grace) => grace,
+        None => return Ok(0),
+    };
+    let grace_micros = grace.as_micros().try_into().unwrap_or(i64::MAX);
+    let expired = tags::table
+        .select(tags::tag_id)
+        .filter(tags::expires_at.lt((now - PgInterval::from_microseconds(grace_micros)).nullable()))
+        .load::<i32>(conn)?;
+    for found_tag_id in &expired {
+        let count = prune_batches(
+            || prune_beyond(conn, *found_tag_id, 0, pruner.batch_size),
+            pruner.batch_size,
+        )?;
+        counter!("retention.pruned", count.try_into().unwrap(), "reason" => "expired");
+        diesel::delete(tags::table.find(found_tag_id)).execute(conn)?;
+    }
+    counter!(
+        "retention.expired_tags_deleted",
+        expired.len().try_into().unwrap()
+    );
+    Ok(expired.len() as i64)
+}
+
+// Runs delete until one comes back short of a full batch
+fn prune_batches<F>(mut delete: F, batch_size: i64) -> Result<usize, diesel::result::Error>
+where
+    F: FnMut() -> Result<usize, diesel::result::Error>,
+{
+    let mut total = 0;
+    loop {
+        let deleted = delete()?;
+        total += deleted;
+        if (deleted as i64) < batch_size {
+            return Ok(total);
+        }
+    }
+}
+
+// Oldest first, walking the by_tag index from the low end
+fn prune_older(
+    conn: &PgConnection,
+    found_tag_id: i32,
+    max_age: Duration,
+    batch_size: i64,
+) -> Result<usize, diesel::result::Error> {
+    diesel::sql_query(
+        "DELETE FROM webhooks WHERE id IN (SELECT id FROM webhooks \
+         WHERE tag_id = $1 AND upload_time < now() - $2 * interval '1 second' \
+         ORDER BY id LIMIT $3)",
+    )
+    .bind::<Integer, _>(found_tag_id)
+    .bind::<BigInt, _>(max_age.as_secs().try_into().unwrap_or(i64::MAX))
+    .bind::<BigInt, _>(batch_size)
+    .execute(conn)
+}
+
+// Everything after the newest max_count, the offset stays put as the tail shrinks
+fn prune_beyond(
+    conn: &PgConnection,
+    found_tag_id: i32,
+    max_count: i64,
+    batch_size: i64,
+) -> Result<usize, diesel::result::Error> {
+    diesel::sql_query(
+        "DELETE FROM webhooks WHERE id IN (SELECT id FROM webhooks \
+         WHERE tag_id = $1 ORDER BY id DESC OFFSET $2 LIMIT $3)",
+    )
+    .bind::<Integer, _>(found_tag_id)
+    .bind::<BigInt, _>(max_count)
+    .bind::<BigInt, _>(batch_size)
+    .execute(conn)
+}
+
+pub(crate) fn validate_retention_changes(changes: &TagChanges) -> Result<(), String> {
+    if let Some(Some(secs)) = changes.retention_max_age_secs {
+        if secs < 0 {
+            return Err("retention_max_age_secs can't be negative".to_string());
+        }
+    }
+    if let Some(Some(count)) = changes.retention_max_count {
+        if count < 0 {
+            return Err("retention_max_count can't be negative".to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune_batches, validate_retention_changes, Policy};
+    use crate::model::TagChanges;
+    use std::time::Duration;
+
+    #[test]
+    fn test_policy_for_tag() {
+        let global = Policy {
+            max_age: Some(Duration::from_secs(86400)),
+            max_count: Some(100),
+        };
+        assert_eq!(global.for_tag(None, None), global);
+        assert_eq!(
+            global.for_tag(Some(60), Some(5)),
+            Policy {
+                max_age: Some(Duration::from_secs(60)),
+                max_count: Some(5),
+            }
+        );
+        assert_eq!(global.for_tag(Some(0), Some(0)), Policy::default());
+        assert_eq!(
+            Policy::default().for_tag(None, Some(10)),
+            Policy {
+                max_age: None,
+                max_count: Some(10),
+            }
+        );
+        assert_eq!(global.describe_age(), "1d");
+        assert_eq!(Policy::default().describe_count(), "all");
+    }
+
+    #[test]
+    fn test_prune_batches() {
+        let mut left = 25;
+        let pruned = prune_batches(
+            || {
+                let deleted = left.min(10);
+                left -= deleted;
+                Ok(deleted)
+            },
+            10,
+        );
+        assert_eq!(pruned.unwrap(), 25);
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn test_validate_retention_changes() {
+        let mut changes = TagChanges {
+            retention_max_age_secs: Some(Some(3600)),
+            retention_max_count: Some(None),
+            ..Default::default()
+        };
+        assert!(validate_retention_changes(&changes).is_ok());
+        changes.retention_max_count = Some(Some(-1));
+        assert!(validate_retention_changes(&changes).is_err());
+    }
+}
@@ -1,25 +1,109 @@
+table! {
+    deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        target_url -> Text,
+        state -> Varchar,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        destination_id -> Nullable<Int4>,
+        response_status -> Nullable<Int4>,
+        response_headers -> Nullable<Jsonb>,
+        response_body -> Nullable<Bytea>,
+        latency_ms -> Nullable<Int4>,
+    }
+}
+
+table! {
+    forward_destinations (id) {
+        id -> Int4,
+        tag_id -> Int4,
+        name -> Varchar,
+        url -> Text,
+        enabled -> Bool,
+        match_header -> Nullable<Varchar>,
+        match_header_value -> Nullable<Text>,
+        match_json_path -> Nullable<Text>,
+        match_json_value -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    replays (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        target_url -> Text,
+        strip_hop_by_hop -> Bool,
+        host_override -> Nullable<Text>,
+        sent_at -> Timestamp,
+        status -> Nullable<Int4>,
+        headers -> Nullable<Jsonb>,
+        body -> Nullable<Bytea>,
+        latency_ms -> Nullable<Int4>,
+        error -> Nullable<Text>,
+    }
+}
+
 table! {
     tags (tag_id) {
         tag_id -> Int4,
         url_suffix -> Varchar,
         created_at -> Timestamp,
         active -> Bool,
+        response_status -> Nullable<Int4>,
+        response_headers -> Jsonb,
+        response_body -> Nullable<Text>,
+        delay_min_ms -> Int4,
+        delay_max_ms -> Int4,
+        failure_rate -> Float8,
+        failure_status -> Int4,
+        fail_next -> Int4,
+        signature_scheme -> Varchar,
+        signature_secret -> Text,
+        signature_header -> Varchar,
+        signature_tolerance_secs -> Int4,
+        reject_unsigned -> Bool,
+        retention_max_age_secs -> Nullable<Int4>,
+        retention_max_count -> Nullable<Int4>,
+        expires_at -> Nullable<Timestamp>,
     }
 }
 
 table! {
     webhooks (id) {
         id -> Int4,
-        headers -> Text,
-        body -> Text,
+        headers -> Jsonb,
+        body -> Bytea,
         upload_time -> Timestamp,
         tag_id -> Nullable<Int4>,
+        method -> Text,
+        query_string -> Nullable<Text>,
+        path_tail -> Text,
+        remote_addr -> Nullable<Varchar>,
+        body_encoding -> Varchar,
+        response_status -> Nullable<Int4>,
+        response_headers -> Nullable<Jsonb>,
+        response_body -> Nullable<Bytea>,
+        response_delay_ms -> Int4,
+        response_fault -> Nullable<Varchar>,
+        signature_verdict -> Nullable<Varchar>,
     }
 }
 
+joinable!(deliveries -> forward_destinations (destination_id));
+joinable!(deliveries -> webhooks (webhook_id));
+joinable!(forward_destinations -> tags (tag_id));
+joinable!(replays -> webhooks (webhook_id));
 joinable!(webhooks -> tags (tag_id));
 
 allow_tables_to_appear_in_same_query!(
+    deliveries,
+    forward_destinations,
+    replays,
     tags,
     webhooks,
 );
@@ -0,0 +1,347 @@
+use super::db;
+use super::display::WebhookSummary;
+use super::har::parse_bound;
+use super::model::HeaderList;
+use super::schema::{tags, webhooks};
+use super::templating::Templater;
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::{Bool, Integer, Jsonb, Text};
+use log::debug;
+use metrics::{counter, timing};
+use quanta::Clock;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::convert::Infallible;
+use std::convert::TryInto;
+use warp::http::StatusCode;
+
+const SEARCH_PAGE_SIZE: i64 = 50;
+const SEARCH_MAX_PAGE_SIZE: i64 = 500;
+
+// Every field is optional and they all have to match, blank ones are ignored
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SearchQuery {
+    // Whole words in the body, in websearch syntax: "a phrase", this or that, -not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_value: Option<String>,
+    // A SQL/JSON path predicate for JSON bodies, like $.order.id == 12345
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Criteria {
+    text: Option<String>,
+    header: Option<String>,
+    header_value: Option<String>,
+    json: Option<String>,
+    tag: Option<String>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+}
+
+impl SearchQuery {
+    pub(crate) fn criteria(&self) -> Result<Criteria, String> {
+        fn given(value: &Option<String>) -> Option<String> {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        }
+        // Header names are stored lowercased
+        let header = given(&self.header).map(|name| name.to_lowercase());
+        let header_value = self.header_value.clone().filter(|value| !value.is_empty());
+        if header.is_none() && header_value.is_some() {
+            return Err("header_value needs a header name to go with it".to_string());
+        }
+        Ok(Criteria {
+            text: given(&self.q),
+            header,
+            header_value,
+            json: given(&self.json),
+            tag: given(&self.tag),
+            since: parse_bound(self.since.as_deref())?,
+            until: parse_bound(self.until.as_deref())?,
+        })
+    }
+
+    // The same search again as a query string, starting after before when there is one
+    fn page(&self, before: Option<i32>) -> String {
+        let page = SearchQuery {
+            before,
+            ..self.clone()
+        };
+        serde_urlencoded::to_string(&page).unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct SearchHit {
+    pub tag: String,
+    #[serde(flatten)]
+    pub webhook: WebhookSummary,
+}
+
+// A bad path would otherwise only come back as a database error from the search itself
+fn check_json_predicate(conn: &PgConnection, predicate: &str) -> Result<(), String> {
+    diesel::select(
+        sql::<Bool>("CAST(")
+            .bind::<Text, _>(predicate)
+            .sql(" AS jsonpath) IS NOT NULL"),
+    )
+    .get_result::<bool>(conn)
+    .map(|_| ())
+    .map_err(|err| match err {
+        diesel::result::Error::DatabaseError(_, info) => {
+            format!("json isn't a valid path predicate: {}", info.message())
+        }
+        err => err.to_string(),
+    })
+}
+
+pub(crate) fn prepare(conn: &PgConnection, query: &SearchQuery) -> Result<Criteria, String> {
+    let criteria = query.criteria()?;
+    if let Some(predicate) = &criteria.json {
+        check_json_predicate(conn, predicate)?;
+    }
+    Ok(criteria)
+}
+
+// Newest first across every tag, each condition rides one of the indexes from the search migration
+pub(crate) fn search(
+    conn: &PgConnection,
+    criteria: &Criteria,
+    before: Option<i32>,
+    limit: Option<i64>,
+) -> Result<(Vec<SearchHit>, Option<i32>), diesel::result::Error> {
+    let page_size = limit
+        .unwrap_or(SEARCH_PAGE_SIZE)
+        .clamp(1, SEARCH_MAX_PAGE_SIZE);
+    let mut page = webhooks::table
+        .inner_join(tags::table)
+        .select((
+            webhooks::id,
+            tags::url_suffix,
+            webhooks::method,
+            webhooks::upload_time,
+            webhooks::headers,
+            sql::<Integer>("octet_length(webhooks.body)"),
+            webhooks::response_status,
+            webhooks::signature_verdict,
+        ))
+        .order_by(webhooks::id.desc())
+        .limit(page_size + 1)
+        .into_boxed();
+    if let Some(text) = &criteria.text {
+        page = page.filter(
+            sql::<Bool>(
+                "webhook_body_tsvector(webhooks.body, webhooks.body_encoding) \
+                 @@ websearch_to_tsquery('simple', ",
+            )
+            .bind::<Text, _>(text.clone())
+            .sql(")"),
+        );
+    }
+    if let Some(name) = &criteria.header {
+        let wanted = match &criteria.header_value {
+            Some(value) => json!([{ "name": name, "value": value }]),
+            None => json!([{ "name": name }]),
+        };
+        page = page.filter(sql::<Bool>("webhooks.headers @> ").bind::<Jsonb, _>(wanted));
+    }
+    if let Some(predicate) = &criteria.json {
+        page = page.filter(
+            sql::<Bool>("webhook_body_json(webhooks.body, webhooks.body_encoding) @@ CAST(")
+                .bind::<Text, _>(predicate.clone())
+                .sql(" AS jsonpath)"),
+        );
+    }
+    if let Some(tag) = &criteria.tag {
+        page = page.filter(tags::url_suffix.eq(tag.clone()));
+    }
+    if let Some(since) = criteria.since {
+        page = page.filter(webhooks::upload_time.ge(since));
+    }
+    if let Some(until) = criteria.until {
+        page = page.filter(webhooks::upload_time.lt(until));
+    }
+    if let Some(before) = before {
+        page = page.filter(webhooks::id.lt(before));
+    }
+    let mut rows = page.load::<(
+        i32,
+        String,
+        String,
+        NaiveDateTime,
+        HeaderList,
+        i32,
+        Option<i32>,
+        Option<String>,
+    )>(conn)?;
+    let has_more = rows.len() as i64 > page_size;
+    rows.truncate(page_size as usize);
+    let hits: Vec<SearchHit> = rows
+        .into_iter()
+        .map(
+            |(
+                row_id,
+                row_tag,
+                row_method,
+                row_time,
+                row_headers,
+                row_size,
+                row_status,
+                row_verdict,
+            )| {
+                SearchHit {
+                    tag: row_tag,
+                    webhook: WebhookSummary {
+                        id: row_id,
+                        method: row_method,
+                        upload_time: row_time,
+                        body_size: row_size,
+                        content_type: row_headers.get("content-type").map(String::from),
+                        response_status: row_status,
+                        signature_verdict: row_verdict,
+                    },
+                }
+            },
+        )
+        .collect();
+    let next_before = if has_more {
+        hits.last().map(|hit| hit.webhook.id)
+    } else {
+        None
+    };
+    Ok((hits, next_before))
+}
+
+#[derive(Serialize, Default)]
+struct SearchPayload {
+    query: SearchQuery,
+    hits: Vec<SearchHit>,
+    first_page: String,
+    next_page: Option<String>,
+    is_first_page: bool,
+    error: Option<String>,
+}
+
+// GET /search
+pub async fn search_page(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    query: SearchQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    debug!("Search requested: {:?}", query);
+    let clock = Clock::new();
+    let search_start = clock.start();
+    let mut payload = SearchPayload {
+        is_first_page: query.before.is_none(),
+        ..Default::default()
+    };
+    let status = match db::connect(&pool) {
+        Ok(conn) => match prepare(&conn, &query) {
+            Ok(criteria) => match search(&conn, &criteria, query.before, query.limit) {
+                Ok((hits, next_before)) => {
+                    counter!("search.search_page.hits", hits.len().try_into().unwrap());
+                    payload.hits = hits;
+                    payload.next_page = next_before.map(|before| query.page(Some(before)));
+                    StatusCode::OK
+                }
+                Err(err) => {
+                    payload.error = Some(err.to_string());
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            },
+            Err(message) => {
+                payload.error = Some(message);
+                StatusCode::BAD_REQUEST
+            }
+        },
+        Err(_) => {
+            payload.error = Some(db::UNAVAILABLE.to_string());
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+    timing!(
+        "search.search_page.time",
+        clock.delta(search_start, clock.end())
+    );
+    payload.first_page = query.page(None);
+    payload.query = query;
+    let html = templater.hb.render("search", &payload);
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchQuery;
+
+    #[test]
+    fn test_criteria() {
+        let query = SearchQuery {
+            q: Some("  12345 ".to_string()),
+            header: Some("X-GitHub-Event".to_string()),
+            header_value: Some("push".to_string()),
+            json: Some(String::new()),
+            since: Some("2026-10-13T00:00".to_string()),
+            until: Some(String::new()),
+            ..Default::default()
+        };
+        let criteria = query.criteria().expect("Query should be valid");
+        assert_eq!(criteria.text.as_deref(), Some("12345"));
+        assert_eq!(criteria.header.as_deref(), Some("x-github-event"));
+        assert_eq!(criteria.header_value.as_deref(), Some("push"));
+        assert_eq!(criteria.json, None);
+        assert_eq!(criteria.since.unwrap().to_string(), "2026-10-13 00:00:00");
+        assert_eq!(criteria.until, None);
+
+        let orphan_value = SearchQuery {
+            header_value: Some("push".to_string()),
+            ..Default::default()
+        };
+        assert!(orphan_value.criteria().is_err());
+        let bad_time = SearchQuery {
+            since: Some("last tuesday".to_string()),
+            ..Default::default()
+        };
+        assert!(bad_time.criteria().is_err());
+    }
+
+    #[test]
+    fn test_page() {
+        let query = SearchQuery {
+            q: Some("order 12345".to_string()),
+            json: Some("$.total > 10".to_string()),
+            before: Some(99),
+            ..Default::default()
+        };
+        assert_eq!(
+            query.page(Some(42)),
+            "q=order+12345&json=%24.total+%3E+10&before=42"
+        );
+        assert_eq!(query.page(None), "q=order+12345&json=%24.total+%3E+10");
+    }
+}
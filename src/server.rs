@@ -1,6 +1,10 @@
 use super::config::AppConfig;
 use super::db::DbFacade;
+use super::delivery::DeliveryQueue;
 use super::filters;
+use super::forward::Forwarder;
+use super::live::LiveFeed;
+use super::retention::Pruner;
 use super::templating::Templater;
 use futures::channel::oneshot;
 use log::{debug, info};
@@ -10,14 +14,26 @@ pub fn spawn_server(
     db: DbFacade,
     config: AppConfig,
     templater: Templater,
+    feed: LiveFeed,
+    forwarder: Forwarder,
+    queue: DeliveryQueue,
+    pruner: Pruner,
 ) -> futures::channel::oneshot::Sender<()> {
     debug!("Going to spawn server");
     let (tx, rx) = oneshot::channel();
     let listen_addr = SocketAddr::new(config.listen_addr, config.listen_port);
-    let (addr, server) = warp::serve(filters::gen_filters(db.get_pool(), templater))
-        .bind_with_graceful_shutdown(listen_addr, async {
-            rx.await.ok();
-        });
+    let (addr, server) = warp::serve(filters::gen_filters(
+        db.get_pool(),
+        templater,
+        config,
+        feed,
+        forwarder,
+        queue,
+        pruner,
+    ))
+    .bind_with_graceful_shutdown(listen_addr, async {
+        rx.await.ok();
+    });
     info!(
         "Created server on {}, preparing to spawn onto background thread",
         addr
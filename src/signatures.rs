@@ -0,0 +1,414 @@
+use super::model::{HeaderList, Tag, TagChanges};
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use warp::http::header::HeaderName;
+
+// Senders retry for a while, but a day old timestamp is a replay whatever the tag says
+pub(crate) const MAX_TOLERANCE_SECS: i32 = 86_400;
+
+// How a tag's senders sign their requests, stored as signature_scheme
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Scheme {
+    None,
+    Github,
+    Stripe,
+    Slack,
+    HmacSha256,
+}
+
+impl Scheme {
+    pub(crate) fn parse(name: &str) -> Option<Scheme> {
+        match name {
+            "none" => Some(Scheme::None),
+            "github" => Some(Scheme::Github),
+            "stripe" => Some(Scheme::Stripe),
+            "slack" => Some(Scheme::Slack),
+            "hmac_sha256" => Some(Scheme::HmacSha256),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Valid,
+    Invalid,
+    Missing,
+    // Correctly signed but the timestamp is outside the tag's tolerance, likely a replay
+    Expired,
+}
+
+impl Verdict {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Valid => "valid",
+            Verdict::Invalid => "invalid",
+            Verdict::Missing => "missing",
+            Verdict::Expired => "expired",
+        }
+    }
+}
+
+// Only checks the signature settings actually being changed
+pub(crate) fn validate_signature_changes(changes: &TagChanges) -> Result<(), String> {
+    if let Some(scheme) = &changes.signature_scheme {
+        match Scheme::parse(scheme) {
+            None => {
+                return Err(format!(
+                    "{} is not a signature scheme, use none, github, stripe, slack or hmac_sha256",
+                    scheme
+                ))
+            }
+            Some(Scheme::None) => {}
+            Some(_) if changes.signature_secret.as_deref() == Some("") => {
+                return Err("Checking signatures needs a secret".to_string())
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(header) = &changes.signature_header {
+        HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| format!("{} is not a valid header name", header))?;
+    }
+    if let Some(tolerance) = changes.signature_tolerance_secs {
+        if !(0..=MAX_TOLERANCE_SECS).contains(&tolerance) {
+            return Err(format!(
+                "Timestamp tolerance must be between 0 and {} seconds",
+                MAX_TOLERANCE_SECS
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Changes alone can't tell whether the tag already has a secret, an empty one would make
+// every signature forgeable
+pub(crate) fn validate_secret_for(tag: &Tag, changes: &TagChanges) -> Result<(), String> {
+    let scheme = changes
+        .signature_scheme
+        .as_deref()
+        .unwrap_or(&tag.signature_scheme);
+    let secret = changes
+        .signature_secret
+        .as_deref()
+        .unwrap_or(&tag.signature_secret);
+    if Scheme::parse(scheme) != Some(Scheme::None) && secret.is_empty() {
+        return Err("Checking signatures needs a secret".to_string());
+    }
+    Ok(())
+}
+
+// None when the tag doesn't check signatures at all
+pub(crate) fn verify(tag: &Tag, headers: &HeaderList, body: &[u8], now: i64) -> Option<Verdict> {
+    let secret = tag.signature_secret.as_bytes();
+    let tolerance = i64::from(tag.signature_tolerance_secs);
+    let verdict = match Scheme::parse(&tag.signature_scheme)? {
+        Scheme::None => return None,
+        Scheme::Github => match headers.get("x-hub-signature-256") {
+            Some(header) => match header.strip_prefix("sha256=") {
+                Some(signature) => check_hex(secret, body, signature),
+                None => Verdict::Invalid,
+            },
+            None => Verdict::Missing,
+        },
+        Scheme::Stripe => match headers.get("stripe-signature") {
+            Some(header) => verify_stripe(secret, header, body, now, tolerance),
+            None => Verdict::Missing,
+        },
+        Scheme::Slack => match (
+            headers.get("x-slack-signature"),
+            headers.get("x-slack-request-timestamp"),
+        ) {
+            (Some(header), Some(timestamp)) => match header.strip_prefix("v0=") {
+                Some(signature) => {
+                    let signed = [b"v0:", timestamp.as_bytes(), b":", body].concat();
+                    within_tolerance(
+                        check_hex(secret, &signed, signature),
+                        timestamp,
+                        now,
+                        tolerance,
+                    )
+                }
+                None => Verdict::Invalid,
+            },
+            _ => Verdict::Missing,
+        },
+        Scheme::HmacSha256 => match headers.get(&tag.signature_header) {
+            Some(header) => verify_generic(secret, header, body),
+            None => Verdict::Missing,
+        },
+    };
+    Some(verdict)
+}
+
+// Stripe-Signature is t=<unix time>,v1=<hex>[,v1=<hex>...], more than one v1 while secrets are rolled
+fn verify_stripe(secret: &[u8], header: &str, body: &[u8], now: i64, tolerance: i64) -> Verdict {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", val)) => timestamp = Some(val),
+            Some(("v1", val)) => signatures.push(val),
+            _ => {}
+        }
+    }
+    let timestamp = match timestamp {
+        Some(val) if !signatures.is_empty() => val,
+        _ => return Verdict::Invalid,
+    };
+    let signed = [timestamp.as_bytes(), b".", body].concat();
+    let verdict = if signatures
+        .iter()
+        .any(|signature| check_hex(secret, &signed, signature) == Verdict::Valid)
+    {
+        Verdict::Valid
+    } else {
+        Verdict::Invalid
+    };
+    within_tolerance(verdict, timestamp, now, tolerance)
+}
+
+// Generic senders disagree on encoding, so take hex with or without sha256= or else base64
+fn verify_generic(secret: &[u8], header: &str, body: &[u8]) -> Verdict {
+    let signature = header.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    if signature.len() == 64 && signature.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return check_hex(secret, body, signature);
+    }
+    match base64::decode_block(signature) {
+        Ok(decoded) => check_bytes(secret, body, &decoded),
+        Err(_) => Verdict::Invalid,
+    }
+}
+
+fn within_tolerance(verdict: Verdict, timestamp: &str, now: i64, tolerance: i64) -> Verdict {
+    if verdict != Verdict::Valid {
+        return verdict;
+    }
+    match timestamp.trim().parse::<i64>() {
+        Ok(sent) if (now - sent).abs() <= tolerance => Verdict::Valid,
+        Ok(_) => Verdict::Expired,
+        Err(_) => Verdict::Invalid,
+    }
+}
+
+fn check_hex(secret: &[u8], message: &[u8], signature: &str) -> Verdict {
+    match decode_hex(signature) {
+        Some(expected) => check_bytes(secret, message, &expected),
+        None => Verdict::Invalid,
+    }
+}
+
+fn check_bytes(secret: &[u8], message: &[u8], expected: &[u8]) -> Verdict {
+    let computed = hmac_sha256(secret, message);
+    // memcmp::eq panics on differing lengths and is constant time otherwise
+    if computed.len() == expected.len() && memcmp::eq(&computed, expected) {
+        Verdict::Valid
+    } else {
+        Verdict::Invalid
+    }
+}
+
+pub(crate) fn hmac_sha256(secret: &[u8], message: &[u8]) -> Vec<u8> {
+    let key = PKey::hmac(secret).expect("Must be able to build an hmac key");
+    let mut signer =
+        Signer::new(MessageDigest::sha256(), &key).expect("Must be able to build an hmac signer");
+    signer
+        .update(message)
+        .expect("Must be able to feed the hmac signer");
+    signer
+        .sign_to_vec()
+        .expect("Must be able to finish the hmac")
+}
+
+// from_str_radix would take a leading +, so digits are checked up front and an odd one out fails the get
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hmac_sha256, validate_secret_for, validate_signature_changes, verify, Verdict};
+    use crate::model::{HeaderList, HeaderPair, Tag, TagChanges};
+    use openssl::base64;
+    use std::fmt::Write;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn tag(scheme: &str, secret: &str) -> Tag {
+        Tag {
+            tag_id: 1,
+            url_suffix: "signed".to_string(),
+            created_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+            active: true,
+            response_status: None,
+            response_headers: HeaderList::default(),
+            response_body: None,
+            delay_min_ms: 0,
+            delay_max_ms: 0,
+            failure_rate: 0.0,
+            failure_status: 503,
+            fail_next: 0,
+            signature_scheme: scheme.to_string(),
+            signature_secret: secret.to_string(),
+            signature_header: "X-Webhook-Signature".to_string(),
+            signature_tolerance_secs: 300,
+            reject_unsigned: false,
+            retention_max_age_secs: None,
+            retention_max_count: None,
+            expires_at: None,
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderList {
+        HeaderList(
+            pairs
+                .iter()
+                .map(|(name, value)| HeaderPair {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut out, byte| {
+            let _ = write!(out, "{:02x}", byte);
+            out
+        })
+    }
+
+    #[test]
+    fn test_github() {
+        // Example from GitHub's webhook validation docs
+        let github = tag("github", "It's a Secret to Everybody");
+        let signed = headers(&[(
+            "x-hub-signature-256",
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+        )]);
+        assert_eq!(
+            verify(&github, &signed, b"Hello, World!", NOW),
+            Some(Verdict::Valid)
+        );
+        assert_eq!(
+            verify(&github, &signed, b"Hello, World?", NOW),
+            Some(Verdict::Invalid)
+        );
+        assert_eq!(
+            verify(&github, &headers(&[]), b"Hello, World!", NOW),
+            Some(Verdict::Missing)
+        );
+        assert_eq!(
+            verify(&tag("none", ""), &signed, b"Hello, World!", NOW),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stripe() {
+        let stripe = tag("stripe", "whsec_test");
+        let body = br#"{"id": "evt_1"}"#;
+        let signed_at = NOW - 10;
+        let signature = hex(&hmac_sha256(
+            b"whsec_test",
+            &[signed_at.to_string().as_bytes(), b".", body].concat(),
+        ));
+        let header = format!("t={},v1={},v1=deadbeef,v0=ignored", signed_at, signature);
+        let signed = headers(&[("Stripe-Signature", &header)]);
+        assert_eq!(verify(&stripe, &signed, body, NOW), Some(Verdict::Valid));
+        assert_eq!(
+            verify(&stripe, &signed, body, NOW + 600),
+            Some(Verdict::Expired)
+        );
+        let forged = headers(&[("Stripe-Signature", "t=1699999990,v1=deadbeef")]);
+        assert_eq!(verify(&stripe, &forged, body, NOW), Some(Verdict::Invalid));
+    }
+
+    #[test]
+    fn test_slack_and_generic() {
+        let slack = tag("slack", "8f742231b10e8888abcd99yyyzzz85a5");
+        let body = b"token=xyz&team_id=T1";
+        let timestamp = NOW.to_string();
+        let signature = hex(&hmac_sha256(
+            b"8f742231b10e8888abcd99yyyzzz85a5",
+            &[b"v0:", timestamp.as_bytes(), b":", body].concat(),
+        ));
+        let signed = headers(&[
+            ("X-Slack-Signature", &format!("v0={}", signature)),
+            ("X-Slack-Request-Timestamp", &timestamp),
+        ]);
+        assert_eq!(verify(&slack, &signed, body, NOW), Some(Verdict::Valid));
+        assert_eq!(
+            verify(&slack, &signed, body, NOW - 301),
+            Some(Verdict::Expired)
+        );
+
+        let generic = tag("hmac_sha256", "shh");
+        let digest = hmac_sha256(b"shh", body);
+        for value in [
+            hex(&digest),
+            format!("sha256={}", hex(&digest)),
+            base64::encode_block(&digest),
+        ] {
+            let signed = headers(&[("x-webhook-signature", &value)]);
+            assert_eq!(verify(&generic, &signed, body, NOW), Some(Verdict::Valid));
+        }
+        let forged = headers(&[("x-webhook-signature", "c2hvcnQ=")]);
+        assert_eq!(verify(&generic, &forged, body, NOW), Some(Verdict::Invalid));
+    }
+
+    #[test]
+    fn test_validate_signature_changes() {
+        let changes = |scheme: &str, secret: Option<&str>| TagChanges {
+            signature_scheme: Some(scheme.to_string()),
+            signature_secret: secret.map(String::from),
+            ..Default::default()
+        };
+        assert!(validate_signature_changes(&changes("github", Some("shh"))).is_ok());
+        assert!(validate_signature_changes(&changes("none", Some(""))).is_ok());
+        assert!(validate_signature_changes(&changes("github", Some(""))).is_err());
+        assert!(validate_signature_changes(&changes("md5", None)).is_err());
+        let bad_header = TagChanges {
+            signature_header: Some("X Signature".to_string()),
+            ..Default::default()
+        };
+        assert!(validate_signature_changes(&bad_header).is_err());
+        let bad_tolerance = TagChanges {
+            signature_tolerance_secs: Some(-1),
+            ..Default::default()
+        };
+        assert!(validate_signature_changes(&bad_tolerance).is_err());
+    }
+
+    #[test]
+    fn test_validate_secret_for() {
+        let scheme_only = TagChanges {
+            signature_scheme: Some("github".to_string()),
+            ..Default::default()
+        };
+        // The column defaults to an empty secret, which anyone could sign with
+        assert!(validate_secret_for(&tag("none", ""), &scheme_only).is_err());
+        assert!(validate_secret_for(&tag("none", "shh"), &scheme_only).is_ok());
+        let with_secret = TagChanges {
+            signature_secret: Some("shh".to_string()),
+            ..scheme_only
+        };
+        assert!(validate_secret_for(&tag("none", ""), &with_secret).is_ok());
+        let clear_secret = TagChanges {
+            signature_secret: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(validate_secret_for(&tag("stripe", "shh"), &clear_secret).is_err());
+        assert!(validate_secret_for(&tag("none", ""), &TagChanges::default()).is_ok());
+    }
+}
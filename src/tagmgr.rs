@@ -1,39 +1,79 @@
 extern crate chrono;
 extern crate diesel;
-use super::model::{NewTag, Tag};
+use super::config::{self, AppConfig};
+use super::db;
+use super::faults;
+use super::model::{HeaderList, HeaderPair, NewTag, ResponseSpec, Tag, TagChanges};
+use super::retention::{self, Pruner};
 use super::schema::tags::dsl::*;
+use super::signatures::{self, Scheme};
 use super::templating::Templater;
+use diesel::dsl::{now, Gt, IsNull, Or};
+use diesel::pg::data_types::PgInterval;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
-use log::debug;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use log::{debug, warn};
 use metrics::{counter, timing};
 use quanta::Clock;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::convert::TryInto;
-use warp::http::StatusCode;
+use std::time::Duration;
+use warp::http::header::{HeaderName, HeaderValue, LOCATION};
+use warp::http::{Response, StatusCode};
 
 #[derive(Serialize, Deserialize)]
 struct TagsPayload {
     tag_count: u32,
     tags: Vec<Tag>,
+    include_inactive: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct TagListQuery {
+    pub include_inactive: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct DeletedTag {
+    pub tag: Tag,
+    pub webhooks_deleted: i64,
+}
+
+#[derive(Serialize)]
+struct DeleteTagPayload {
+    url_suffix: String,
+    webhook_count: i64,
+    deleted: bool,
 }
 
 pub async fn display_tagmgr(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
     templater: Templater,
+    query: TagListQuery,
 ) -> Result<impl warp::Reply, Infallible> {
     let clock = Clock::new();
     debug!("Beginning display tags request");
+    let include_inactive = query.include_inactive.unwrap_or(false);
     let tags_get_start = clock.start();
-    let live_tags = tags
-        .filter(active.eq(true))
-        .order_by(created_at)
-        .limit(50)
-        .load::<Tag>(&pool.get().unwrap())
-        .unwrap();
+    let mut listing = tags.order_by(created_at).limit(50).into_boxed();
+    if !include_inactive {
+        listing = listing.filter(active.eq(true)).filter(unexpired());
+    }
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html(db::UNAVAILABLE.to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    };
+    let live_tags = listing.load::<Tag>(&conn).unwrap();
     timing!(
         "tagmgr.display_tagmgr.live_tags_query",
         clock.delta(tags_get_start, clock.end())
@@ -47,48 +87,1098 @@ pub async fn display_tagmgr(
     let payload = TagsPayload {
         tag_count: count,
         tags: live_tags,
+        include_inactive,
     };
     let html = templater.hb.render("tags", &payload);
-    Ok(warp::reply::html(
-        html.unwrap_or_else(|err| err.to_string()),
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        StatusCode::OK,
     ))
 }
 
+// 64 characters so each random byte maps onto it evenly with a mask
+const SUFFIX_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const SUFFIX_ATTEMPTS: usize = 5;
+// The url_suffix column is a VARCHAR(32)
+const MAX_SUFFIX_LENGTH: usize = 32;
+pub(crate) const SUFFIX_CONSTRAINT: &str = "unique_url_suffix";
+// Expiry is for throwaway tags, anything longer lived can just be deleted by hand
+const MAX_EXPIRY_SECS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Serialize, Default)]
+struct NewTagPayload {
+    tag: Option<String>,
+    expires_in: Option<String>,
+    error: Option<String>,
+    created: Option<Tag>,
+    record_url: Option<String>,
+}
+
+fn render_new_tag(
+    templater: &Templater,
+    payload: &NewTagPayload,
+    status: StatusCode,
+) -> impl warp::Reply {
+    warp::reply::with_status(
+        warp::reply::html(
+            templater
+                .hb
+                .render("new_tag", payload)
+                .unwrap_or_else(|err| err.to_string()),
+        ),
+        status,
+    )
+}
+
 pub async fn show_new_tag(templater: Templater) -> Result<impl warp::Reply, Infallible> {
-    Ok(warp::reply::html(
-        templater
-            .hb
-            .render("new_tag", &())
-            .unwrap_or_else(|err| err.to_string()),
+    Ok(render_new_tag(
+        &templater,
+        &NewTagPayload::default(),
+        StatusCode::OK,
     ))
 }
 
 pub async fn new_tag(
     pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    config: AppConfig,
+    host: Option<String>,
     body: HashMap<String, String>,
 ) -> Result<impl warp::Reply, Infallible> {
-    let tag_val = match body.get("tag") {
-        Some(val) => val,
-        None => return Ok(StatusCode::from_u16(500).unwrap()),
+    // Leaving the tag blank asks for a generated one
+    let requested = body
+        .get("tag")
+        .map(|val| val.trim().to_string())
+        .filter(|val| !val.is_empty());
+    let expires_in = body.get("expires_in").cloned();
+    let expiry = match requested
+        .as_deref()
+        .map_or(Ok(()), validate_suffix)
+        .and_then(|_| parse_expiry(expires_in.as_deref()))
+    {
+        Ok(expiry) => expiry,
+        Err(message) => {
+            let payload = NewTagPayload {
+                tag: requested,
+                expires_in,
+                error: Some(message),
+                ..Default::default()
+            };
+            return Ok(render_new_tag(
+                &templater,
+                &payload,
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+    let ret = match db::connect(&pool) {
+        Ok(conn) => create_tag(&conn, requested.clone(), config.tag_suffix_length, expiry),
+        Err(_) => {
+            let payload = NewTagPayload {
+                tag: requested,
+                expires_in,
+                error: Some(db::UNAVAILABLE.to_string()),
+                ..Default::default()
+            };
+            return Ok(render_new_tag(
+                &templater,
+                &payload,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ));
+        }
+    };
+    let (payload, status) = match ret {
+        Ok(tag) => (
+            NewTagPayload {
+                record_url: Some(record_url(&config, host.as_deref(), &tag.url_suffix)),
+                created: Some(tag),
+                ..Default::default()
+            },
+            StatusCode::OK,
+        ),
+        Err(err) => new_tag_failed(requested, expires_in, err),
+    };
+    Ok(render_new_tag(&templater, &payload, status))
+}
+
+// A taken suffix is only the user's to fix when they picked it, running out of generated ones isn't
+fn new_tag_failed(
+    requested: Option<String>,
+    expires_in: Option<String>,
+    err: diesel::result::Error,
+) -> (NewTagPayload, StatusCode) {
+    let (message, status) = match (&requested, err) {
+        (Some(suffix), DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            counter!("tagmgr.new_tag.duplicate", 1);
+            (
+                format!("The tag {} is already taken, choose another", suffix),
+                StatusCode::CONFLICT,
+            )
+        }
+        (None, DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            counter!("tagmgr.new_tag.suffix_exhausted", 1);
+            warn!("Ran out of attempts generating a free tag suffix");
+            (
+                "Couldn't generate a free tag, try again".to_string(),
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+        }
+        (_, err) => (err.to_string(), StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let payload = NewTagPayload {
+        error: Some(message),
+        tag: requested,
+        expires_in,
+        ..Default::default()
     };
-    let ret = write_new_tag(pool.clone(), tag_val.to_string()).await;
-    match ret {
-        Ok(_) => Ok(StatusCode::from_u16(200).unwrap()),
-        Err(_) => Ok(StatusCode::from_u16(500).unwrap()),
+    (payload, status)
+}
+
+// Uses the requested suffix when there is one, otherwise generates suffixes until one is free
+pub(crate) fn create_tag(
+    conn: &PgConnection,
+    requested: Option<String>,
+    suffix_length: usize,
+    expiry: Option<Duration>,
+) -> Result<Tag, diesel::result::Error> {
+    if let Some(tag) = requested {
+        return write_new_tag(conn, tag, expiry);
+    }
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match write_new_tag(conn, generate_suffix(suffix_length), expiry) {
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _))
+                if attempts < SUFFIX_ATTEMPTS =>
+            {
+                counter!("tagmgr.create_tag.suffix_collision", 1);
+                debug!("Generated suffix collided, retrying");
+            }
+            result => return result,
+        }
     }
 }
 
-async fn write_new_tag(
-    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+pub(crate) fn generate_suffix(length: usize) -> String {
+    let mut buf = vec![0u8; length];
+    openssl::rand::rand_bytes(&mut buf).expect("Must be able to get random bytes");
+    buf.iter()
+        .map(|byte| SUFFIX_ALPHABET[(byte & 0x3f) as usize] as char)
+        .collect()
+}
+
+// Suffixes end up as a path segment in /record/:tag, so they stick to the generated alphabet
+pub(crate) fn validate_suffix(suffix: &str) -> Result<(), String> {
+    if suffix.is_empty() || suffix.len() > MAX_SUFFIX_LENGTH {
+        return Err(format!(
+            "A tag has to be between 1 and {} characters",
+            MAX_SUFFIX_LENGTH
+        ));
+    }
+    if !suffix.bytes().all(|byte| SUFFIX_ALPHABET.contains(&byte)) {
+        return Err("A tag can only use letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+// Prefers the configured PUBLIC_URL, falls back to the Host the request came in on
+pub(crate) fn public_base(config: &AppConfig, host: Option<&str>) -> String {
+    match (&config.public_url, host) {
+        (Some(public_url), _) => public_url.clone(),
+        (None, Some(host)) => format!("http://{}", host),
+        (None, None) => String::new(),
+    }
+}
+
+pub(crate) fn record_url(config: &AppConfig, host: Option<&str>, suffix: &str) -> String {
+    format!("{}/record/{}", public_base(config, host), suffix)
+}
+
+// The expiry is counted from the database's clock, the same one created_at comes from
+pub(crate) fn write_new_tag(
+    conn: &PgConnection,
     tag: String,
+    expiry: Option<Duration>,
 ) -> Result<Tag, diesel::result::Error> {
     use super::schema::tags;
     let newtag = NewTag {
         url_suffix: tag,
         active: true,
     };
+    let expiry = expiry.map(|ttl| {
+        let ttl_micros = ttl.as_micros().try_into().unwrap_or(i64::MAX);
+        tags::expires_at.eq((now + PgInterval::from_microseconds(ttl_micros)).nullable())
+    });
     let tag = diesel::insert_into(tags::table)
-        .values(&newtag)
-        .get_result::<Tag>(&pool.get().unwrap())?;
+        .values((&newtag, expiry))
+        .get_result::<Tag>(conn)?;
     Ok(tag)
 }
+
+// Blank means the tag never expires
+pub(crate) fn parse_expiry(val: Option<&str>) -> Result<Option<Duration>, String> {
+    let val = match val.map(|val| val.trim()).filter(|val| !val.is_empty()) {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+    let expiry = config::parse_duration(val)?;
+    if expiry.as_secs() == 0 {
+        return Err("A tag has to live for at least a second".to_string());
+    }
+    if expiry.as_secs() > MAX_EXPIRY_SECS {
+        return Err(format!(
+            "Tags can expire after at most {}",
+            config::format_duration(Duration::from_secs(MAX_EXPIRY_SECS))
+        ));
+    }
+    Ok(Some(expiry))
+}
+
+// Tags that never expire or haven't yet, expired ones are treated as inactive
+pub(crate) fn unexpired() -> Or<IsNull<expires_at>, Gt<expires_at, now>> {
+    expires_at.is_null().or(expires_at.gt(now))
+}
+
+pub(crate) fn set_tag_active(
+    conn: &PgConnection,
+    suffix: &str,
+    active_val: bool,
+) -> Result<Tag, diesel::result::Error> {
+    diesel::update(tags.filter(url_suffix.eq(suffix)))
+        .set(active.eq(active_val))
+        .get_result::<Tag>(conn)
+}
+
+fn count_tag_webhooks(conn: &PgConnection, suffix: &str) -> Result<i64, diesel::result::Error> {
+    use super::schema::{tags as tags_table, webhooks};
+    webhooks::table
+        .inner_join(tags_table::table)
+        .filter(url_suffix.eq(suffix))
+        .count()
+        .get_result(conn)
+}
+
+// Webhooks go with the tag thanks to ON DELETE CASCADE, count them first so we can say how many
+pub(crate) fn delete_tag(
+    conn: &PgConnection,
+    suffix: &str,
+) -> Result<DeletedTag, diesel::result::Error> {
+    conn.transaction(|| {
+        let webhooks_deleted = count_tag_webhooks(conn, suffix)?;
+        let tag = diesel::delete(tags.filter(url_suffix.eq(suffix))).get_result::<Tag>(conn)?;
+        counter!(
+            "tagmgr.delete_tag.webhooks_deleted",
+            webhooks_deleted as u64
+        );
+        Ok(DeletedTag {
+            tag,
+            webhooks_deleted,
+        })
+    })
+}
+
+// POST /tags/:tag/activate and /tags/:tag/deactivate, back to the tag list afterwards
+pub async fn toggle_tag(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    suffix: String,
+    active_val: bool,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(db::UNAVAILABLE.to_string()))
+        }
+    };
+    let response = match set_tag_active(&conn, &suffix, active_val) {
+        Ok(_) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(LOCATION, "/tags?include_inactive=true")
+            .body(String::new()),
+        Err(diesel::result::Error::NotFound) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such tag".to_string()),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(err.to_string()),
+    };
+    Ok(response)
+}
+
+// POST /tags/:tag/delete, asks for confirmation with the webhook count unless confirm=yes was sent
+pub async fn delete_tag_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+    body: HashMap<String, String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::html(db::UNAVAILABLE.to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    };
+    let confirmed = body.get("confirm").map(|val| val == "yes").unwrap_or(false);
+    let result = if confirmed {
+        delete_tag(&conn, &suffix).map(|deleted| deleted.webhooks_deleted)
+    } else {
+        tags.filter(url_suffix.eq(&suffix))
+            .select(tag_id)
+            .first::<i32>(&conn)
+            .and_then(|_| count_tag_webhooks(&conn, &suffix))
+    };
+    let (html, status) = match result {
+        Ok(webhook_count) => {
+            let payload = DeleteTagPayload {
+                url_suffix: suffix,
+                webhook_count,
+                deleted: confirmed,
+            };
+            (templater.hb.render("delete_tag", &payload), StatusCode::OK)
+        }
+        Err(diesel::result::Error::NotFound) => {
+            (Ok("No such tag".to_string()), StatusCode::NOT_FOUND)
+        }
+        Err(err) => (Ok(err.to_string()), StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    ))
+}
+
+// Whether a settings form was just saved, or why it couldn't be shown or saved
+#[derive(Serialize, Default)]
+struct FormOutcome {
+    error: Option<String>,
+    saved: bool,
+}
+
+impl FormOutcome {
+    fn failed(message: String) -> FormOutcome {
+        FormOutcome {
+            error: Some(message),
+            saved: false,
+        }
+    }
+}
+
+// Each tag settings page brings its own payload and template, loading and saving are shared
+trait TagFormPayload: Serialize {
+    const TEMPLATE: &'static str;
+
+    fn outcome(&mut self) -> &mut FormOutcome;
+}
+
+fn render_tag_form<P: TagFormPayload>(
+    templater: &Templater,
+    payload: &P,
+    status: StatusCode,
+) -> impl warp::Reply {
+    let html = templater.hb.render(P::TEMPLATE, payload);
+    warp::reply::with_status(
+        warp::reply::html(html.unwrap_or_else(|err| err.to_string())),
+        status,
+    )
+}
+
+fn load_tag_form(conn: &PgConnection, suffix: &str) -> Result<Tag, diesel::result::Error> {
+    tags.filter(url_suffix.eq(suffix)).first::<Tag>(conn)
+}
+
+fn update_tag_form(
+    conn: &PgConnection,
+    suffix: &str,
+    changes: &TagChanges,
+) -> Result<Tag, diesel::result::Error> {
+    diesel::update(tags.filter(url_suffix.eq(suffix)))
+        .set(changes)
+        .get_result::<Tag>(conn)
+}
+
+// The form for a tag that was just loaded or saved, or what went wrong finding it
+fn tag_form_outcome<P: TagFormPayload>(
+    found: Result<Tag, diesel::result::Error>,
+    saved: bool,
+    suffix: String,
+    build: impl FnOnce(String, Option<Tag>) -> P,
+) -> (P, StatusCode) {
+    let (mut payload, status, outcome) = match found {
+        Ok(tag) => (
+            build(suffix, Some(tag)),
+            StatusCode::OK,
+            FormOutcome { error: None, saved },
+        ),
+        Err(diesel::result::Error::NotFound) => (
+            build(suffix, None),
+            StatusCode::NOT_FOUND,
+            FormOutcome::failed("No such tag".to_string()),
+        ),
+        Err(err) => (
+            build(suffix, None),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            FormOutcome::failed(err.to_string()),
+        ),
+    };
+    *payload.outcome() = outcome;
+    (payload, status)
+}
+
+// Without a connection there's no tag to fill the page in with, just the reason
+fn unavailable_tag_form<P: TagFormPayload>(
+    suffix: String,
+    build: impl FnOnce(String, Option<Tag>) -> P,
+) -> (P, StatusCode) {
+    let mut payload = build(suffix, None);
+    *payload.outcome() = FormOutcome::failed(db::UNAVAILABLE.to_string());
+    (payload, StatusCode::SERVICE_UNAVAILABLE)
+}
+
+// A bad form comes back with why, the tag is looked up again just to fill in the rest of the page
+fn rejected_tag_form<P: TagFormPayload>(
+    conn: &PgConnection,
+    suffix: String,
+    message: String,
+    build: impl FnOnce(String, Option<Tag>) -> P,
+) -> (P, StatusCode) {
+    let found = load_tag_form(conn, &suffix).ok();
+    let mut payload = build(suffix, found);
+    *payload.outcome() = FormOutcome::failed(message);
+    (payload, StatusCode::BAD_REQUEST)
+}
+
+#[derive(Serialize, Default)]
+struct ResponseFormPayload {
+    url_suffix: String,
+    status: Option<String>,
+    headers: String,
+    body: Option<String>,
+    #[serde(flatten)]
+    outcome: FormOutcome,
+}
+
+impl ResponseFormPayload {
+    fn new(suffix: String, found: Option<Tag>) -> ResponseFormPayload {
+        let spec = found.map(|tag| tag.response_spec()).unwrap_or_default();
+        ResponseFormPayload {
+            url_suffix: suffix,
+            status: spec.response_status.map(|status| status.to_string()),
+            headers: spec
+                .response_headers
+                .iter()
+                .map(|pair| format!("{}: {}\n", pair.name, pair.value))
+                .collect(),
+            body: spec.response_body,
+            ..Default::default()
+        }
+    }
+}
+
+impl TagFormPayload for ResponseFormPayload {
+    const TEMPLATE: &'static str = "tag_response";
+
+    fn outcome(&mut self) -> &mut FormOutcome {
+        &mut self.outcome
+    }
+}
+
+// Checked before saving so a typo shows up here rather than as a 500 to whoever sends the webhook
+pub(crate) fn validate_response_spec(spec: &ResponseSpec) -> Result<(), String> {
+    if let Some(status) = spec.response_status {
+        if !(100..=599).contains(&status) {
+            return Err(format!("{} is not an HTTP status", status));
+        }
+    }
+    for pair in spec.response_headers.iter() {
+        HeaderName::from_bytes(pair.name.as_bytes())
+            .map_err(|_| format!("{} is not a valid header name", pair.name))?;
+        HeaderValue::from_str(&pair.value)
+            .map_err(|_| format!("The value for {} is not a valid header value", pair.name))?;
+    }
+    if let Some(body) = &spec.response_body {
+        handlebars::Template::compile(body).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn set_response_spec(
+    conn: &PgConnection,
+    suffix: &str,
+    spec: &ResponseSpec,
+) -> Result<Tag, diesel::result::Error> {
+    diesel::update(tags.filter(url_suffix.eq(suffix)))
+        .set(spec)
+        .get_result::<Tag>(conn)
+}
+
+// Blank fields clear that part of the spec, headers are one "Name: value" per line
+fn parse_response_form(body: &HashMap<String, String>) -> Result<ResponseSpec, String> {
+    let field = |name: &str| {
+        body.get(name)
+            .map(|val| val.replace("\r\n", "\n"))
+            .filter(|val| !val.trim().is_empty())
+    };
+    let status_val = match field("status") {
+        Some(val) => Some(
+            val.trim()
+                .parse::<i32>()
+                .map_err(|_| "Status must be a number".to_string())?,
+        ),
+        None => None,
+    };
+    let mut pairs = Vec::new();
+    for line in field("headers").unwrap_or_default().lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Header lines look like Name: value, got {}", line))?;
+        pairs.push(HeaderPair {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+        });
+    }
+    Ok(ResponseSpec {
+        response_status: status_val,
+        response_headers: HeaderList(pairs),
+        response_body: field("body"),
+    })
+}
+
+// GET /tags/:tag/response
+pub async fn show_response_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let (payload, status) = match db::connect(&pool) {
+        Ok(conn) => {
+            let found = load_tag_form(&conn, &suffix);
+            tag_form_outcome(found, false, suffix, ResponseFormPayload::new)
+        }
+        Err(_) => unavailable_tag_form(suffix, ResponseFormPayload::new),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+// POST /tags/:tag/response, on a bad spec the form comes back with what was typed and why
+pub async fn save_response_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+    body: HashMap<String, String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let parsed = parse_response_form(&body).and_then(|spec| {
+        validate_response_spec(&spec)?;
+        Ok(spec)
+    });
+    let (payload, status) = match parsed {
+        Ok(spec) => match db::connect(&pool) {
+            Ok(conn) => {
+                let updated = set_response_spec(&conn, &suffix, &spec);
+                if updated.is_ok() {
+                    counter!("tagmgr.save_response_form.saved", 1);
+                }
+                tag_form_outcome(updated, true, suffix, ResponseFormPayload::new)
+            }
+            Err(_) => unavailable_tag_form(suffix, ResponseFormPayload::new),
+        },
+        Err(message) => (
+            ResponseFormPayload {
+                url_suffix: suffix,
+                status: body.get("status").cloned(),
+                headers: body.get("headers").cloned().unwrap_or_default(),
+                body: body.get("body").cloned(),
+                outcome: FormOutcome::failed(message),
+            },
+            StatusCode::BAD_REQUEST,
+        ),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+#[derive(Serialize, Default)]
+struct FaultsFormPayload {
+    url_suffix: String,
+    tag: Option<Tag>,
+    #[serde(flatten)]
+    outcome: FormOutcome,
+}
+
+impl FaultsFormPayload {
+    fn new(suffix: String, found: Option<Tag>) -> FaultsFormPayload {
+        FaultsFormPayload {
+            url_suffix: suffix,
+            tag: found,
+            ..Default::default()
+        }
+    }
+}
+
+impl TagFormPayload for FaultsFormPayload {
+    const TEMPLATE: &'static str = "tag_faults";
+
+    fn outcome(&mut self) -> &mut FormOutcome {
+        &mut self.outcome
+    }
+}
+
+// Blank fields go back to their defaults, which is no misbehaviour at all
+fn parse_faults_form(body: &HashMap<String, String>) -> Result<TagChanges, String> {
+    fn field<T: std::str::FromStr>(
+        body: &HashMap<String, String>,
+        name: &str,
+        default: T,
+    ) -> Result<T, String> {
+        match body
+            .get(name)
+            .map(|val| val.trim())
+            .filter(|val| !val.is_empty())
+        {
+            Some(val) => val
+                .parse::<T>()
+                .map_err(|_| format!("{} must be a number", name)),
+            None => Ok(default),
+        }
+    }
+    Ok(TagChanges {
+        delay_min_ms: Some(field(body, "delay_min_ms", 0)?),
+        delay_max_ms: Some(field(body, "delay_max_ms", 0)?),
+        failure_rate: Some(field(body, "failure_rate", 0.0)?),
+        failure_status: Some(field(body, "failure_status", 503)?),
+        fail_next: Some(field(body, "fail_next", 0)?),
+        ..Default::default()
+    })
+}
+
+// GET /tags/:tag/faults
+pub async fn show_faults_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let (payload, status) = match db::connect(&pool) {
+        Ok(conn) => {
+            let found = load_tag_form(&conn, &suffix);
+            tag_form_outcome(found, false, suffix, FaultsFormPayload::new)
+        }
+        Err(_) => unavailable_tag_form(suffix, FaultsFormPayload::new),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+// POST /tags/:tag/faults
+pub async fn save_faults_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+    body: HashMap<String, String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            let (payload, status) = unavailable_tag_form(suffix, FaultsFormPayload::new);
+            return Ok(render_tag_form(&templater, &payload, status));
+        }
+    };
+    let parsed = parse_faults_form(&body).and_then(|changes| {
+        faults::validate_fault_changes(&changes)?;
+        Ok(changes)
+    });
+    let (payload, status) = match parsed {
+        Ok(changes) => {
+            let updated = update_tag_form(&conn, &suffix, &changes);
+            if updated.is_ok() {
+                counter!("tagmgr.save_faults_form.saved", 1);
+            }
+            tag_form_outcome(updated, true, suffix, FaultsFormPayload::new)
+        }
+        Err(message) => rejected_tag_form(&conn, suffix, message, FaultsFormPayload::new),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+#[derive(Serialize, Default)]
+struct RetentionFormPayload {
+    url_suffix: String,
+    tag: Option<Tag>,
+    max_age: String,
+    max_count: String,
+    default_age: String,
+    default_count: String,
+    keeps_age: String,
+    keeps_count: String,
+    #[serde(flatten)]
+    outcome: FormOutcome,
+}
+
+impl RetentionFormPayload {
+    fn new(suffix: String, pruner: &Pruner, found: Option<Tag>) -> RetentionFormPayload {
+        let mut payload = RetentionFormPayload {
+            url_suffix: suffix,
+            default_age: pruner.policy.describe_age(),
+            default_count: pruner.policy.describe_count(),
+            ..Default::default()
+        };
+        if let Some(tag) = found {
+            let keeps = pruner
+                .policy
+                .for_tag(tag.retention_max_age_secs, tag.retention_max_count);
+            payload.max_age = tag
+                .retention_max_age_secs
+                .map(|secs| config::format_duration(Duration::from_secs(secs as u64)))
+                .unwrap_or_default();
+            payload.max_count = tag
+                .retention_max_count
+                .map(|count| count.to_string())
+                .unwrap_or_default();
+            payload.keeps_age = keeps.describe_age();
+            payload.keeps_count = keeps.describe_count();
+            payload.tag = Some(tag);
+        }
+        payload
+    }
+}
+
+impl TagFormPayload for RetentionFormPayload {
+    const TEMPLATE: &'static str = "tag_retention";
+
+    fn outcome(&mut self) -> &mut FormOutcome {
+        &mut self.outcome
+    }
+}
+
+// Blank fields follow the global policy, 0 keeps everything
+fn parse_retention_form(body: &HashMap<String, String>) -> Result<TagChanges, String> {
+    let field = |name: &str| {
+        body.get(name)
+            .map(|val| val.trim())
+            .filter(|val| !val.is_empty())
+    };
+    let max_age = match field("max_age") {
+        Some(val) => Some(
+            config::parse_duration(val)?
+                .as_secs()
+                .try_into()
+                .map_err(|_| "Max age is too long".to_string())?,
+        ),
+        None => None,
+    };
+    let max_count = match field("max_count") {
+        Some(val) => Some(
+            val.parse::<i32>()
+                .map_err(|_| "Max count must be a whole number".to_string())?,
+        ),
+        None => None,
+    };
+    Ok(TagChanges {
+        retention_max_age_secs: Some(max_age),
+        retention_max_count: Some(max_count),
+        ..Default::default()
+    })
+}
+
+// GET /tags/:tag/retention
+pub async fn show_retention_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    pruner: Pruner,
+    suffix: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let build = |suffix, found| RetentionFormPayload::new(suffix, &pruner, found);
+    let (payload, status) = match db::connect(&pool) {
+        Ok(conn) => tag_form_outcome(load_tag_form(&conn, &suffix), false, suffix, build),
+        Err(_) => unavailable_tag_form(suffix, build),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+// POST /tags/:tag/retention
+pub async fn save_retention_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    pruner: Pruner,
+    suffix: String,
+    body: HashMap<String, String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let build = |suffix, found| RetentionFormPayload::new(suffix, &pruner, found);
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            let (payload, status) = unavailable_tag_form(suffix, build);
+            return Ok(render_tag_form(&templater, &payload, status));
+        }
+    };
+    let parsed = parse_retention_form(&body).and_then(|changes| {
+        retention::validate_retention_changes(&changes)?;
+        Ok(changes)
+    });
+    let (payload, status) = match parsed {
+        Ok(changes) => {
+            let updated = update_tag_form(&conn, &suffix, &changes);
+            if updated.is_ok() {
+                counter!("tagmgr.save_retention_form.saved", 1);
+            }
+            tag_form_outcome(updated, true, suffix, build)
+        }
+        Err(message) => rejected_tag_form(&conn, suffix, message, build),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+#[derive(Serialize, Default)]
+struct SignatureFormPayload {
+    url_suffix: String,
+    tag: Option<Tag>,
+    secret_set: bool,
+    #[serde(flatten)]
+    outcome: FormOutcome,
+}
+
+impl SignatureFormPayload {
+    fn new(suffix: String, found: Option<Tag>) -> SignatureFormPayload {
+        SignatureFormPayload {
+            url_suffix: suffix,
+            secret_set: found
+                .as_ref()
+                .map_or(false, |tag| !tag.signature_secret.is_empty()),
+            tag: found,
+            ..Default::default()
+        }
+    }
+}
+
+impl TagFormPayload for SignatureFormPayload {
+    const TEMPLATE: &'static str = "tag_signature";
+
+    fn outcome(&mut self) -> &mut FormOutcome {
+        &mut self.outcome
+    }
+}
+
+// The secret is never shown back, so leaving it blank keeps the one already saved
+fn parse_signature_form(body: &HashMap<String, String>) -> Result<TagChanges, String> {
+    let field = |name: &str| {
+        body.get(name)
+            .map(|val| val.trim().to_string())
+            .filter(|val| !val.is_empty())
+    };
+    let tolerance = match field("signature_tolerance_secs") {
+        Some(val) => val
+            .parse::<i32>()
+            .map_err(|_| "Timestamp tolerance must be a number".to_string())?,
+        None => 300,
+    };
+    Ok(TagChanges {
+        signature_scheme: Some(field("signature_scheme").unwrap_or_else(|| "none".to_string())),
+        signature_secret: field("signature_secret"),
+        signature_header: Some(
+            field("signature_header").unwrap_or_else(|| "X-Signature".to_string()),
+        ),
+        signature_tolerance_secs: Some(tolerance),
+        reject_unsigned: Some(body.contains_key("reject_unsigned")),
+        ..Default::default()
+    })
+}
+
+// GET /tags/:tag/signature
+pub async fn show_signature_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let (payload, status) = match db::connect(&pool) {
+        Ok(conn) => {
+            let found = load_tag_form(&conn, &suffix);
+            tag_form_outcome(found, false, suffix, SignatureFormPayload::new)
+        }
+        Err(_) => unavailable_tag_form(suffix, SignatureFormPayload::new),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+// POST /tags/:tag/signature
+pub async fn save_signature_form(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    templater: Templater,
+    suffix: String,
+    body: HashMap<String, String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let conn = match db::connect(&pool) {
+        Ok(conn) => conn,
+        Err(_) => {
+            let (payload, status) = unavailable_tag_form(suffix, SignatureFormPayload::new);
+            return Ok(render_tag_form(&templater, &payload, status));
+        }
+    };
+    let current = load_tag_form(&conn, &suffix).ok();
+    let parsed = parse_signature_form(&body).and_then(|changes| {
+        signatures::validate_signature_changes(&changes)?;
+        let checking =
+            changes.signature_scheme.as_deref().and_then(Scheme::parse) != Some(Scheme::None);
+        let has_secret = changes.signature_secret.is_some()
+            || current
+                .as_ref()
+                .map_or(false, |tag| !tag.signature_secret.is_empty());
+        if checking && !has_secret {
+            return Err("Checking signatures needs a secret".to_string());
+        }
+        Ok(changes)
+    });
+    let (payload, status) = match parsed {
+        Ok(changes) => {
+            let updated = update_tag_form(&conn, &suffix, &changes);
+            if updated.is_ok() {
+                counter!("tagmgr.save_signature_form.saved", 1);
+            }
+            tag_form_outcome(updated, true, suffix, SignatureFormPayload::new)
+        }
+        // The tag was already looked up to check for a secret
+        Err(message) => (
+            SignatureFormPayload {
+                outcome: FormOutcome::failed(message),
+                ..SignatureFormPayload::new(suffix, current)
+            },
+            StatusCode::BAD_REQUEST,
+        ),
+    };
+    Ok(render_tag_form(&templater, &payload, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        generate_suffix, new_tag_failed, parse_expiry, parse_response_form, parse_retention_form,
+        show_response_form, validate_response_spec, validate_suffix, SUFFIX_ALPHABET,
+    };
+    use crate::db;
+    use crate::templating::Templater;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::result::{DatabaseErrorKind, Error};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use warp::Reply;
+
+    #[test]
+    fn test_generate_suffix() {
+        let suffix = generate_suffix(32);
+        assert_eq!(suffix.len(), 32);
+        assert!(suffix.bytes().all(|byte| SUFFIX_ALPHABET.contains(&byte)));
+        assert_ne!(suffix, generate_suffix(32));
+        assert_eq!(validate_suffix(&suffix), Ok(()));
+        assert_eq!(validate_suffix("shop-1_a"), Ok(()));
+        assert!(validate_suffix("").is_err());
+        assert!(validate_suffix(&"a".repeat(33)).is_err());
+        assert!(validate_suffix("a/b").is_err());
+        assert!(validate_suffix("has space").is_err());
+        assert!(validate_suffix("..").is_err());
+    }
+
+    #[test]
+    fn test_parse_response_form() {
+        let mut form = HashMap::new();
+        form.insert("status".to_string(), " 202 ".to_string());
+        form.insert(
+            "headers".to_string(),
+            "Content-Type: application/json\r\n\r\nX-Echo: {{id}}\r\n".to_string(),
+        );
+        form.insert("body".to_string(), "{\"ok\":true}\r\n".to_string());
+        let spec = parse_response_form(&form).expect("Form should parse");
+        assert_eq!(spec.response_status, Some(202));
+        assert_eq!(spec.response_headers.get("x-echo"), Some("{{id}}"));
+        assert_eq!(spec.response_headers.0.len(), 2);
+        assert_eq!(spec.response_body.as_deref(), Some("{\"ok\":true}\n"));
+        assert!(validate_response_spec(&spec).is_ok());
+
+        form.insert("headers".to_string(), "no colon here".to_string());
+        assert!(parse_response_form(&form).is_err());
+        form.remove("headers");
+        form.insert("status".to_string(), "700".to_string());
+        assert!(validate_response_spec(&parse_response_form(&form).unwrap()).is_err());
+        form.insert("status".to_string(), String::new());
+        form.insert("body".to_string(), "{{#if}".to_string());
+        assert!(validate_response_spec(&parse_response_form(&form).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_parse_retention_form() {
+        let mut form = HashMap::new();
+        form.insert("max_age".to_string(), " 7d ".to_string());
+        form.insert("max_count".to_string(), String::new());
+        let changes = parse_retention_form(&form).expect("Form should parse");
+        assert_eq!(changes.retention_max_age_secs, Some(Some(7 * 24 * 60 * 60)));
+        assert_eq!(changes.retention_max_count, Some(None));
+
+        form.insert("max_age".to_string(), "0".to_string());
+        form.insert("max_count".to_string(), "25".to_string());
+        let changes = parse_retention_form(&form).expect("Form should parse");
+        assert_eq!(changes.retention_max_age_secs, Some(Some(0)));
+        assert_eq!(changes.retention_max_count, Some(Some(25)));
+
+        form.insert("max_age".to_string(), "a while".to_string());
+        assert!(parse_retention_form(&form).is_err());
+        form.insert("max_age".to_string(), "100000w".to_string());
+        assert!(parse_retention_form(&form).is_err());
+    }
+
+    #[test]
+    fn test_parse_expiry() {
+        assert_eq!(parse_expiry(None), Ok(None));
+        assert_eq!(parse_expiry(Some("  ")), Ok(None));
+        assert_eq!(
+            parse_expiry(Some("24h")),
+            Ok(Some(Duration::from_secs(24 * 60 * 60)))
+        );
+        assert_eq!(
+            parse_expiry(Some("7d")),
+            Ok(Some(Duration::from_secs(7 * 24 * 60 * 60)))
+        );
+        assert!(parse_expiry(Some("0")).is_err());
+        assert!(parse_expiry(Some("53w")).is_err());
+        assert!(parse_expiry(Some("tomorrow")).is_err());
+    }
+
+    #[test]
+    fn test_new_tag_failed() {
+        let taken = || {
+            Error::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new("duplicate key".to_string()),
+            )
+        };
+        let (payload, status) = new_tag_failed(Some("shop".to_string()), None, taken());
+        assert_eq!(status.as_u16(), 409);
+        assert_eq!(
+            payload.error.as_deref(),
+            Some("The tag shop is already taken, choose another")
+        );
+        let (payload, status) = new_tag_failed(None, Some("1d".to_string()), taken());
+        assert_eq!(status.as_u16(), 503);
+        assert_eq!(
+            payload.error.as_deref(),
+            Some("Couldn't generate a free tag, try again")
+        );
+        assert_eq!(payload.expires_in.as_deref(), Some("1d"));
+        let (_, status) = new_tag_failed(None, None, Error::NotFound);
+        assert_eq!(status.as_u16(), 500);
+    }
+
+    #[tokio::test]
+    async fn test_show_form_without_db() {
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::new("pgsql://test"));
+        let response = show_response_form(pool, Templater::new(), "shop".to_string())
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status().as_u16(), 503);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(db::UNAVAILABLE));
+    }
+}
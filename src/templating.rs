@@ -1,9 +1,12 @@
 extern crate handlebars;
 use super::model::Tag;
+use super::pretty;
 use chrono::NaiveDateTime;
 use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError};
 use log::debug;
+use openssl::base64;
 use rust_embed::RustEmbed;
+use serde::Serialize;
 use serde_json::Value;
 use std::sync::Arc;
 use std::time::Duration;
@@ -15,6 +18,8 @@ struct Templates;
 #[derive(Debug, Clone, Default)]
 pub struct Templater {
     pub hb: Arc<Handlebars<'static>>,
+    // Canned responses aren't html so they get a registry of their own that doesn't escape
+    pub responses: Arc<Handlebars<'static>>,
 }
 // TODO: implement a render method on this struct to hide the details of the boxed ref
 
@@ -54,11 +59,111 @@ impl Templater {
                 .expect("Failed to load new_tag.hbs"),
         )
         .expect("Failed to register new tag template");
+        reg.register_template_string(
+            "history",
+            std::str::from_utf8(Templates::get("history.hbs").unwrap().as_ref())
+                .expect("Failed to load history.hbs"),
+        )
+        .expect("Failed to register history template");
+        reg.register_template_string(
+            "live",
+            std::str::from_utf8(Templates::get("live.hbs").unwrap().as_ref())
+                .expect("Failed to load live.hbs"),
+        )
+        .expect("Failed to register live template");
+        reg.register_template_string(
+            "delete_tag",
+            std::str::from_utf8(Templates::get("delete_tag.hbs").unwrap().as_ref())
+                .expect("Failed to load delete_tag.hbs"),
+        )
+        .expect("Failed to register delete tag template");
+        reg.register_template_string(
+            "tag_response",
+            std::str::from_utf8(Templates::get("tag_response.hbs").unwrap().as_ref())
+                .expect("Failed to load tag_response.hbs"),
+        )
+        .expect("Failed to register tag response template");
+        reg.register_template_string(
+            "tag_faults",
+            std::str::from_utf8(Templates::get("tag_faults.hbs").unwrap().as_ref())
+                .expect("Failed to load tag_faults.hbs"),
+        )
+        .expect("Failed to register tag faults template");
+        reg.register_template_string(
+            "tag_signature",
+            std::str::from_utf8(Templates::get("tag_signature.hbs").unwrap().as_ref())
+                .expect("Failed to load tag_signature.hbs"),
+        )
+        .expect("Failed to register tag signature template");
+        reg.register_template_string(
+            "tag_retention",
+            std::str::from_utf8(Templates::get("tag_retention.hbs").unwrap().as_ref())
+                .expect("Failed to load tag_retention.hbs"),
+        )
+        .expect("Failed to register tag retention template");
+        reg.register_template_string(
+            "diff",
+            std::str::from_utf8(Templates::get("diff.hbs").unwrap().as_ref())
+                .expect("Failed to load diff.hbs"),
+        )
+        .expect("Failed to register diff template");
+        reg.register_template_string(
+            "search",
+            std::str::from_utf8(Templates::get("search.hbs").unwrap().as_ref())
+                .expect("Failed to load search.hbs"),
+        )
+        .expect("Failed to register search template");
+        reg.register_template_string(
+            "tag_forward",
+            std::str::from_utf8(Templates::get("tag_forward.hbs").unwrap().as_ref())
+                .expect("Failed to load tag_forward.hbs"),
+        )
+        .expect("Failed to register tag forward template");
+        reg.register_template_string(
+            "deliveries",
+            std::str::from_utf8(Templates::get("deliveries.hbs").unwrap().as_ref())
+                .expect("Failed to load deliveries.hbs"),
+        )
+        .expect("Failed to register deliveries template");
         debug!("Registering template helpers");
         reg.register_helper("duration", Box::new(Templater::duration_helper));
         reg.register_helper("systime", Box::new(Templater::systime_helper));
         reg.register_helper("tag", Box::new(Templater::tag_helper));
-        Templater { hb: Arc::new(reg) }
+        reg.register_helper("body_view", Box::new(Templater::body_view_helper));
+        let mut responses = Handlebars::new();
+        responses.register_escape_fn(handlebars::no_escape);
+        responses.register_helper("json", Box::new(Templater::json_helper));
+        Templater {
+            hb: Arc::new(reg),
+            responses: Arc::new(responses),
+        }
+    }
+
+    // Renders a tag's response body template, these come from users so aren't registered up front
+    pub fn render_response<T: Serialize>(
+        &self,
+        template: &str,
+        data: &T,
+    ) -> Result<String, String> {
+        self.responses
+            .render_template(template, data)
+            .map_err(|err| err.to_string())
+    }
+
+    // Writes a value back out as json, for echoing pieces of a json body into a json response
+    fn json_helper(
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _rc: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let param = h
+            .param(0)
+            .map(|v| v.value())
+            .ok_or_else(|| RenderError::new("param not found"))?;
+        out.write(&serde_json::to_string(param)?)?;
+        Ok(())
     }
 
     fn systime_helper(
@@ -125,6 +230,36 @@ impl Templater {
         let out_str = serde_json::to_string_pretty::<Tag>(&t)?;
         Ok(out_str)
     }
+
+    // Takes the base64 body and the content type, writes html so it has to escape for itself
+    fn body_view_helper(
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _rc: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let body = h
+            .param(0)
+            .map(|v| v.value())
+            .ok_or_else(|| RenderError::new("param not found"))?;
+        let content_type = h.param(1).map(|v| v.value()).unwrap_or(&Value::Null);
+        let out_str = Templater::body_view_inner(body, content_type)?;
+        out.write(&out_str)?;
+        Ok(())
+    }
+
+    pub(crate) fn body_view_inner(
+        body: &Value,
+        content_type: &Value,
+    ) -> Result<String, RenderError> {
+        let encoded = body
+            .as_str()
+            .ok_or_else(|| RenderError::new("body should be a base64 string"))?;
+        let decoded = base64::decode_block(encoded)
+            .map_err(|_| RenderError::new("body isn't valid base64"))?;
+        Ok(pretty::render(&decoded, content_type.as_str()))
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -143,6 +278,31 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_body_view_inner() {
+        let result =
+            Templater::body_view_inner(&json!("YT0x"), &json!("application/x-www-form-urlencoded"))
+                .expect("Base64 body should render");
+        assert!(result.contains("<td class=\"pretty-key\">a</td><td>1</td>"));
+        let result = Templater::body_view_inner(&json!("PGI+"), &json!(null))
+            .expect("Base64 body should render");
+        assert_eq!(result, "<pre class=\"has-text-black-ter\">&lt;b&gt;</pre>");
+        assert!(Templater::body_view_inner(&json!(null), &json!(null)).is_err());
+    }
+
+    #[test]
+    fn test_render_response() {
+        let templater = Templater::new();
+        let data = json!({"json": {"challenge": "a\"b"}, "headers": {"x-id": "<7>"}});
+        let result = templater
+            .render_response(
+                r#"{"challenge": {{json json.challenge}}, "id": "{{headers.x-id}}"}"#,
+                &data,
+            )
+            .expect("Response template should render");
+        assert_eq!(result, r#"{"challenge": "a\"b", "id": "<7>"}"#);
+    }
+
     #[test]
     fn test_systime_inner() {
         let value: NaiveDateTime = NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11);
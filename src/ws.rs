@@ -0,0 +1,271 @@
+use super::api::ApiWebhook;
+use super::db;
+use super::display::find_tag_id;
+use super::jsonpath;
+use super::live::{LiveFeed, LiveHook};
+use super::model::Webhook;
+use super::schema::webhooks;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use futures::{SinkExt, StreamExt};
+use log::{debug, warn};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::sync::broadcast::RecvError;
+use warp::ws::{Message, WebSocket};
+
+// What a client can send over the socket, subscribing again to a tag replaces its filters
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Command {
+    Subscribe {
+        tag: String,
+        #[serde(default)]
+        filters: SubscriptionFilters,
+    },
+    Unsubscribe {
+        tag: String,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event<'a> {
+    Subscribed {
+        tag: &'a str,
+    },
+    Unsubscribed {
+        tag: &'a str,
+    },
+    Webhook {
+        tag: &'a str,
+        webhook: ApiWebhook<'a>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct HeaderFilter {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct JsonFilter {
+    pub path: String,
+    // Leave out to only require that the path exists
+    pub equals: Option<Value>,
+}
+
+// Every filter given has to match for a webhook to be pushed
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct SubscriptionFilters {
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: Vec<HeaderFilter>,
+    #[serde(default)]
+    pub json: Vec<JsonFilter>,
+}
+
+impl SubscriptionFilters {
+    fn matches(&self, webhook: &Webhook) -> bool {
+        if let Some(wanted) = &self.method {
+            if !webhook.method.eq_ignore_ascii_case(wanted) {
+                return false;
+            }
+        }
+        let headers_match = self.headers.iter().all(|filter| {
+            webhook.headers.iter().any(|pair| {
+                pair.name.eq_ignore_ascii_case(&filter.name) && pair.value == filter.value
+            })
+        });
+        if !headers_match {
+            return false;
+        }
+        if self.json.is_empty() {
+            return true;
+        }
+        match webhook.body_text().map(serde_json::from_str::<Value>) {
+            Some(Ok(doc)) => self
+                .json
+                .iter()
+                .all(|filter| jsonpath::matches(&doc, &filter.path, filter.equals.as_ref())),
+            _ => false,
+        }
+    }
+}
+
+struct Subscription {
+    url_suffix: String,
+    filters: SubscriptionFilters,
+}
+
+// GET /ws, one socket per dashboard holding any number of tag subscriptions
+pub async fn client_connected(
+    socket: WebSocket,
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    feed: LiveFeed,
+) {
+    counter!("ws.client_connected", 1);
+    let (mut tx, mut rx) = socket.split();
+    let mut hooks = feed.subscribe();
+    // Keyed by tag_id since that's what comes down the feed
+    let mut subscriptions: HashMap<i32, Subscription> = HashMap::new();
+    loop {
+        let outgoing = tokio::select! {
+            incoming = rx.next() => match incoming {
+                Some(Ok(msg)) if msg.is_close() => break,
+                Some(Ok(msg)) => match msg.to_str() {
+                    Ok(text) => handle_command(&pool, &mut subscriptions, text),
+                    // Pings and binary frames don't mean anything to us
+                    Err(_) => continue,
+                },
+                Some(Err(err)) => {
+                    debug!("Websocket error: {}", err);
+                    break;
+                }
+                None => break,
+            },
+            received = hooks.recv() => match received {
+                Ok(hook) => match matching_webhook(&pool, &subscriptions, &hook) {
+                    Some(text) => text,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    counter!("ws.client_connected.lagged", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if tx.send(Message::text(outgoing)).await.is_err() {
+            break;
+        }
+    }
+    debug!("Websocket client went away");
+}
+
+fn handle_command(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    subscriptions: &mut HashMap<i32, Subscription>,
+    text: &str,
+) -> String {
+    let event = match serde_json::from_str::<Command>(text) {
+        Ok(Command::Subscribe { tag, filters }) => match db::connect(pool) {
+            Ok(conn) => match find_tag_id(&conn, &tag) {
+                Ok(found_id) => {
+                    counter!("ws.subscribe", 1);
+                    let payload = to_text(&Event::Subscribed { tag: &tag });
+                    subscriptions.insert(
+                        found_id,
+                        Subscription {
+                            url_suffix: tag,
+                            filters,
+                        },
+                    );
+                    return payload;
+                }
+                Err(diesel::result::Error::NotFound) => Event::Error {
+                    message: format!("No such tag {}", tag),
+                },
+                Err(err) => Event::Error {
+                    message: err.to_string(),
+                },
+            },
+            Err(_) => Event::Error {
+                message: db::UNAVAILABLE.to_string(),
+            },
+        },
+        Ok(Command::Unsubscribe { tag }) => {
+            subscriptions.retain(|_, sub| sub.url_suffix != tag);
+            return to_text(&Event::Unsubscribed { tag: &tag });
+        }
+        Err(err) => Event::Error {
+            message: err.to_string(),
+        },
+    };
+    to_text(&event)
+}
+
+// The feed only carries a summary, so the full webhook is fetched once a subscribed tag turns up
+fn matching_webhook(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    subscriptions: &HashMap<i32, Subscription>,
+    hook: &LiveHook,
+) -> Option<String> {
+    let sub = subscriptions.get(&hook.tag_id?)?;
+    let found = db::connect(pool)
+        .map_err(|err| err.to_string())
+        .and_then(|conn| {
+            webhooks::table
+                .find(hook.id)
+                .first::<Webhook>(&conn)
+                .map_err(|err| err.to_string())
+        });
+    let webhook = match found {
+        Ok(webhook) => webhook,
+        Err(err) => {
+            warn!("Unable to load webhook {} for websocket: {}", hook.id, err);
+            return None;
+        }
+    };
+    if !sub.filters.matches(&webhook) {
+        return None;
+    }
+    counter!("ws.webhooks_pushed", 1);
+    Some(to_text(&Event::Webhook {
+        tag: &sub.url_suffix,
+        webhook: ApiWebhook::new(&webhook),
+    }))
+}
+
+fn to_text(event: &Event) -> String {
+    serde_json::to_string(event).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionFilters;
+    use crate::model::tests::webhook;
+    use crate::model::Webhook;
+
+    fn github_push() -> Webhook {
+        Webhook {
+            response_status: Some(200),
+            ..webhook(
+                "POST",
+                &[("x-github-event", "push")],
+                br#"{"ref": "refs/heads/main", "commits": [{"id": "abc"}]}"#,
+            )
+        }
+    }
+
+    #[test]
+    fn test_subscription_filters() {
+        let webhook = github_push();
+        let parse = |filters: &str| serde_json::from_str::<SubscriptionFilters>(filters).unwrap();
+        assert!(parse("{}").matches(&webhook));
+        assert!(parse(r#"{"method": "post"}"#).matches(&webhook));
+        assert!(!parse(r#"{"method": "PUT"}"#).matches(&webhook));
+        assert!(
+            parse(r#"{"headers": [{"name": "X-GitHub-Event", "value": "push"}]}"#)
+                .matches(&webhook)
+        );
+        assert!(
+            !parse(r#"{"headers": [{"name": "X-GitHub-Event", "value": "ping"}]}"#)
+                .matches(&webhook)
+        );
+        assert!(
+            parse(r#"{"json": [{"path": "$.commits[0].id", "equals": "abc"}]}"#).matches(&webhook)
+        );
+        assert!(
+            !parse(r#"{"json": [{"path": "$.ref", "equals": "refs/heads/dev"}]}"#)
+                .matches(&webhook)
+        );
+    }
+}